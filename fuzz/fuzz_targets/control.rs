@@ -0,0 +1,134 @@
+//! Arbitrary sequences of API calls and well-formed segments applied to a
+//! `KcpControl`, checking that no combination of `len`/`frg`/window values
+//! panics and that `peek_size` agrees with `receive`.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use ultra_kcp_core::constants::KcpError;
+use ultra_kcp_core::kcp::{KcpCallBack, KcpControl, Segment};
+
+const CONV: u32 = 0x5eed;
+
+struct Discard;
+
+impl KcpCallBack for Discard {}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Send(Vec<u8>),
+    Receive {
+        capacity: u16,
+        peek: bool,
+    },
+    PeekSize,
+    /// Raw bytes, conversation id included
+    Input(Vec<u8>),
+    /// One segment with the right conversation id and a consistent length
+    Segment {
+        cmd: u8,
+        frg: u8,
+        wnd: u16,
+        ts: u32,
+        sn: u16,
+        una: u16,
+        payload: Vec<u8>,
+    },
+    Update(u16),
+    Flush,
+    SetMtu(u16),
+    SetWndsize(u8, u8),
+    SetNodelay {
+        nodelay: u8,
+        interval: u16,
+        resend: i8,
+        nocwnd: bool,
+    },
+    ToggleStreaming,
+    ToggleSack,
+    ToggleExtendedFrg,
+    ToggleChecksum,
+    SetMaxMessage(u16),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Scenario {
+    start: u32,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|scenario: Scenario| {
+    let mut kcp = KcpControl::new_on_stack(CONV, None);
+    kcp.set_callback(Box::new(Discard));
+    let mut current = scenario.start;
+
+    for op in scenario.ops {
+        match op {
+            Op::Send(data) => {
+                let _ = kcp.send(&data);
+            }
+            Op::Receive { capacity, peek } => {
+                let peeked = kcp.peek_size();
+                let mut buf = vec![0; capacity as usize];
+                match kcp.receive(Some(&mut buf), peek) {
+                    Ok(n) => assert_eq!(peeked, Ok(n)),
+                    Err(KcpError::BufferTooSmall) => assert!(peeked.unwrap() > buf.len()),
+                    Err(err) => assert_eq!(peeked, Err(err)),
+                }
+            }
+            Op::PeekSize => {
+                let _ = kcp.peek_size();
+            }
+            Op::Input(data) => {
+                let _ = kcp.input(&data);
+            }
+            Op::Segment {
+                cmd,
+                frg,
+                wnd,
+                ts,
+                sn,
+                una,
+                payload,
+            } => {
+                let seg = Segment {
+                    conv: CONV,
+                    cmd: 81 + (cmd % 5) as u32,
+                    frg: frg as u32,
+                    wnd: wnd as u32,
+                    ts,
+                    sn: sn as u32,
+                    una: una as u32,
+                    len: payload.len() as u32,
+                    ..Default::default()
+                };
+                let mut datagram = vec![0; 24];
+                seg.encode_header(&mut datagram);
+                datagram.extend_from_slice(&payload);
+                let _ = kcp.input(&datagram);
+            }
+            Op::Update(delta) => {
+                current = current.wrapping_add(delta as u32);
+                kcp.update(current);
+                let _ = kcp.check(current);
+            }
+            Op::Flush => kcp.flush(),
+            Op::SetMtu(mtu) => {
+                let _ = kcp.set_mtu(mtu as u32);
+            }
+            Op::SetWndsize(snd, rcv) => kcp.set_wndsize(snd as u32, rcv as u32),
+            Op::SetNodelay {
+                nodelay,
+                interval,
+                resend,
+                nocwnd,
+            } => kcp.set_nodelay((nodelay % 3) as u32, interval as u32, resend as i32, nocwnd),
+            Op::ToggleStreaming => kcp.streaming_mode = !kcp.streaming_mode,
+            Op::ToggleSack => kcp.set_sack(!kcp.sack()),
+            Op::ToggleExtendedFrg => kcp.set_extended_frg(!kcp.extended_frg()),
+            Op::SetMaxMessage(size) => kcp.set_max_message(size as usize),
+            Op::ToggleChecksum => kcp.set_checksum(!kcp.checksum()),
+        }
+    }
+});
@@ -0,0 +1,22 @@
+//! Arbitrary packets thrown at the FEC decoder ahead of the input path.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ultra_kcp_core::fec::FecDecoder;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&shape, mut rest)) = data.split_first() else {
+        return;
+    };
+    let data_shards = (shape & 0x0f) as usize + 1;
+    let parity_shards = (shape >> 4) as usize + 1;
+    let mut decoder = FecDecoder::new(data_shards, parity_shards).unwrap();
+
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = (len as usize).min(tail.len());
+        let (packet, tail) = tail.split_at(len);
+        rest = tail;
+        let _ = decoder.decode(packet, |_| {});
+    }
+});
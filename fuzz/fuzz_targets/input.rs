@@ -0,0 +1,41 @@
+//! Arbitrary datagrams thrown at `KcpControl::input`, followed by the
+//! receive path reassembling whatever was accepted.
+//!
+//! The first 4 bytes select the conversation id so that the fuzzer can get
+//! past the conversation check; the rest is split into datagrams by a one
+//! byte length prefix.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ultra_kcp_core::kcp::{KcpCallBack, KcpControl};
+
+struct Discard;
+
+impl KcpCallBack for Discard {}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let conv = u32::from_le_bytes(data[..4].try_into().unwrap());
+    let mut kcp = KcpControl::new_on_stack(conv, None);
+    kcp.set_callback(Box::new(Discard));
+
+    let mut current = 0;
+    let mut rest = &data[4..];
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = (len as usize * 8).min(tail.len());
+        let (datagram, tail) = tail.split_at(len);
+        rest = tail;
+
+        let _ = kcp.input(datagram);
+        current += 10;
+        kcp.update(current);
+
+        let mut buf = vec![0; kcp.peek_size().unwrap_or(0)];
+        while kcp.receive(Some(&mut buf), false).is_ok() {
+            buf.resize(kcp.peek_size().unwrap_or(0), 0);
+        }
+    }
+});
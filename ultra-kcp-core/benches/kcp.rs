@@ -0,0 +1,212 @@
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::hint::black_box;
+
+use common::{pump, Endpoint};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use ultra_kcp_core::constants::{Command, IKCP_MTU_DEF, IKCP_OVERHEAD};
+use ultra_kcp_core::kcp::Segment;
+
+/// Small and large window sizes every control block benchmark runs at (segments)
+const WINDOWS: [u32; 2] = [32, 1024];
+
+const CONV: u32 = 0x11223344;
+
+/// Messages exchanged by the loopback benchmark
+const LOOPBACK_MESSAGES: usize = 1000;
+
+/// Size of the loopback messages (bytes)
+const LOOPBACK_SIZE: usize = 1024;
+
+/// Control block with both windows set to `window`, tuned like the fast3 preset
+fn endpoint(window: u32) -> Endpoint {
+    let mut endpoint = Endpoint::new(CONV);
+    endpoint.kcp.set_wndsize(window, window);
+    endpoint.kcp.set_nodelay(1, 10, 2, true);
+    endpoint
+}
+
+/// Message of full segments filling half the window, at most the 256
+/// fragments a message may span
+fn message(window: u32) -> Vec<u8> {
+    let mss = endpoint(window).kcp.mss;
+    let fragments = (window / 2).min(256);
+    (0..mss * fragments).map(|i| i as u8).collect()
+}
+
+/// Two endpoints connected by in-memory wires, on a virtual clock
+struct Pair {
+    sender: Endpoint,
+    receiver: Endpoint,
+    now: u32,
+}
+
+impl Pair {
+    fn new(window: u32) -> Self {
+        Self {
+            sender: endpoint(window),
+            receiver: endpoint(window),
+            now: 0,
+        }
+    }
+
+    /// Advance the clock by 1 ms, update both ends and deliver what they sent
+    fn step(&mut self) {
+        self.now += 1;
+        pump(self.now, &mut self.sender, &mut self.receiver);
+    }
+
+    /// Pair whose receiver holds `message` complete in its receive queue
+    fn loaded(window: u32, message: &[u8]) -> Self {
+        let mut pair = Self::new(window);
+        pair.sender.kcp.send(message).unwrap();
+        while pair.receiver.kcp.peek_size().is_err() {
+            pair.step();
+        }
+        pair
+    }
+}
+
+fn send(c: &mut Criterion) {
+    let mut group = c.benchmark_group("send");
+    for window in WINDOWS {
+        let message = message(window);
+        group.throughput(Throughput::Bytes(message.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("fragment", window),
+            &message,
+            |b, message| {
+                b.iter_batched_ref(
+                    || endpoint(window).kcp,
+                    |kcp| kcp.send(black_box(message)).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn receive(c: &mut Criterion) {
+    let mut group = c.benchmark_group("receive");
+    for window in WINDOWS {
+        let message = message(window);
+        let mut buf = vec![0; message.len()];
+        group.throughput(Throughput::Bytes(message.len() as u64));
+        group.bench_function(BenchmarkId::new("reassemble", window), |b| {
+            b.iter_batched_ref(
+                || Pair::loaded(window, &message),
+                |pair| pair.receiver.kcp.receive(Some(&mut buf), false).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+
+        let pair = Pair::loaded(window, &message);
+        group.bench_function(BenchmarkId::new("peek_size", window), |b| {
+            b.iter(|| black_box(&pair.receiver.kcp).peek_size().unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Segments filling one datagram of the default MTU
+fn datagram_segments(payload: usize) -> Vec<Segment> {
+    let count = IKCP_MTU_DEF as usize / (IKCP_OVERHEAD as usize + payload);
+    (0..count as u32)
+        .map(|sn| Segment {
+            conv: CONV,
+            cmd: if payload == 0 {
+                Command::Ack as u32
+            } else {
+                Command::Push as u32
+            },
+            wnd: 128,
+            ts: 1000 + sn,
+            sn,
+            una: 0,
+            len: payload as u32,
+            data: vec![sn as u8; payload],
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn encode(segments: &[Segment], buf: &mut [u8]) -> usize {
+    let mut offset = 0;
+    for segment in segments {
+        offset += segment.encode_header(&mut buf[offset..]);
+        buf[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        offset += segment.data.len();
+    }
+    offset
+}
+
+fn decode(mut datagram: &[u8]) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    while !datagram.is_empty() {
+        let mut segment = Segment::decode_header(datagram).unwrap();
+        let end = IKCP_OVERHEAD as usize + segment.len as usize;
+        segment
+            .data
+            .extend_from_slice(&datagram[IKCP_OVERHEAD as usize..end]);
+        segments.push(segment);
+        datagram = &datagram[end..];
+    }
+    segments
+}
+
+fn datagram(c: &mut Criterion) {
+    let mut group = c.benchmark_group("datagram");
+    let mss = IKCP_MTU_DEF as usize - IKCP_OVERHEAD as usize;
+    for (name, payload) in [("acks", 0), ("push", mss)] {
+        let segments = datagram_segments(payload);
+        let mut buf = vec![0; IKCP_MTU_DEF as usize];
+        let len = encode(&segments, &mut buf);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_function(BenchmarkId::new("encode", name), |b| {
+            b.iter(|| encode(black_box(&segments), &mut buf))
+        });
+        let datagram = buf[..len].to_vec();
+        group.bench_function(BenchmarkId::new("decode", name), |b| {
+            b.iter(|| decode(black_box(&datagram)))
+        });
+    }
+    group.finish();
+}
+
+/// Send `LOOPBACK_MESSAGES` through a pair and receive them all
+fn exchange(window: u32, message: &[u8], buf: &mut [u8]) -> u32 {
+    let mut pair = Pair::new(window);
+    let queue_max = 2 * window as usize;
+    let (mut sent, mut received) = (0, 0);
+    while received < LOOPBACK_MESSAGES {
+        while sent < LOOPBACK_MESSAGES && pair.sender.kcp.wait_snd() < queue_max {
+            pair.sender.kcp.send(message).unwrap();
+            sent += 1;
+        }
+        pair.step();
+        while pair.receiver.kcp.receive(Some(buf), false).is_ok() {
+            received += 1;
+        }
+    }
+    pair.now
+}
+
+fn loopback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("loopback");
+    let message = vec![7; LOOPBACK_SIZE];
+    let mut buf = vec![0; LOOPBACK_SIZE];
+    group.throughput(Throughput::Bytes(
+        (LOOPBACK_MESSAGES * LOOPBACK_SIZE) as u64,
+    ));
+    for window in WINDOWS {
+        group.bench_function(BenchmarkId::new("exchange", window), |b| {
+            b.iter(|| exchange(window, &message, &mut buf))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, send, receive, datagram, loopback);
+criterion_main!(benches);
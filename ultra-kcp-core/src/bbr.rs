@@ -0,0 +1,341 @@
+use alloc::collections::VecDeque;
+
+use crate::congestion::{AckEvent, CongestionController, LossEvent, TimeoutEvent};
+use crate::serial::itimediff;
+
+/// Fixed point unit for gains (1.0 == BBR_UNIT)
+const BBR_UNIT: u64 = 256;
+
+/// Startup gain, 2/ln(2), the smallest gain doubling the delivery rate each round
+const BBR_HIGH_GAIN: u64 = BBR_UNIT * 2885 / 1000;
+
+/// Drain gain, inverse of the startup gain
+const BBR_DRAIN_GAIN: u64 = BBR_UNIT * 1000 / 2885;
+
+/// Steady state congestion window gain
+const BBR_CWND_GAIN: u64 = BBR_UNIT * 2;
+
+/// Pacing gain cycle used in `ProbeBw`: probe, drain the probe queue, cruise
+const BBR_PACING_GAINS: [u64; 8] = [
+    BBR_UNIT * 5 / 4,
+    BBR_UNIT * 3 / 4,
+    BBR_UNIT,
+    BBR_UNIT,
+    BBR_UNIT,
+    BBR_UNIT,
+    BBR_UNIT,
+    BBR_UNIT,
+];
+
+/// Number of round trips over which the maximum delivery rate is kept
+const BBR_BW_WINDOW_ROUNDS: u64 = 10;
+
+/// Lifetime of the minimum RTT estimate (ms)
+const BBR_MIN_RTT_WINDOW: u32 = 10000;
+
+/// Time spent at the minimum window while probing the RTT (ms)
+const BBR_PROBE_RTT_TIME: u32 = 200;
+
+/// Minimum congestion window (segments)
+const BBR_MIN_CWND: u32 = 4;
+
+/// Initial congestion window (segments)
+const BBR_INIT_CWND: u32 = 10;
+
+/// Round trip used before any RTT sample is available (ms)
+const BBR_INIT_RTT: u32 = 100;
+
+/// Startup ends after this many rounds without 25% bandwidth growth
+const BBR_FULL_BW_ROUNDS: u32 = 3;
+
+/// Phase of the BBR state machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BbrMode {
+    /// Exponential growth until the bottleneck bandwidth is found
+    Startup,
+    /// Drain the queue built during startup
+    Drain,
+    /// Steady state, cycling the pacing gain around the estimated bandwidth
+    ProbeBw,
+    /// Shrink the window to refresh the minimum RTT estimate
+    ProbeRtt,
+}
+
+/// BBR-style congestion controller
+///
+/// Models the path from the delivery rate measured between ACKs (bottleneck
+/// bandwidth) and the minimum RTT echoed by ACK timestamps, and sizes both
+/// the congestion window and the pacing rate from their product instead of
+/// reacting to packet loss.
+#[derive(Debug, Clone)]
+pub struct BbrController {
+    mode: BbrMode,
+    cwnd: u32,
+    mss: u32,
+    inflight: u32,
+    pacing_gain: u64,
+    cwnd_gain: u64,
+
+    /// Total bytes acknowledged
+    delivered: u64,
+    round_count: u64,
+    /// Start of the current round, `None` until the first ACK
+    round_start_time: Option<u32>,
+    round_start_delivered: u64,
+    /// Delivery rate samples (round, bytes per second)
+    bw_samples: VecDeque<(u64, u64)>,
+    btl_bw: u64,
+
+    min_rtt: u32,
+    min_rtt_stamp: u32,
+    srtt: u32,
+
+    full_bw: u64,
+    full_bw_count: u32,
+    filled_pipe: bool,
+
+    cycle_index: usize,
+    cycle_stamp: u32,
+    probe_rtt_done: Option<u32>,
+    prior_cwnd: u32,
+}
+
+impl Default for BbrController {
+    fn default() -> Self {
+        Self {
+            mode: BbrMode::Startup,
+            cwnd: BBR_INIT_CWND,
+            mss: 0,
+            inflight: 0,
+            pacing_gain: BBR_HIGH_GAIN,
+            cwnd_gain: BBR_HIGH_GAIN,
+            delivered: 0,
+            round_count: 0,
+            round_start_time: None,
+            round_start_delivered: 0,
+            bw_samples: VecDeque::new(),
+            btl_bw: 0,
+            min_rtt: 0,
+            min_rtt_stamp: 0,
+            srtt: 0,
+            full_bw: 0,
+            full_bw_count: 0,
+            filled_pipe: false,
+            cycle_index: 0,
+            cycle_stamp: 0,
+            probe_rtt_done: None,
+            prior_cwnd: 0,
+        }
+    }
+}
+
+impl BbrController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub const fn mode(&self) -> BbrMode {
+        self.mode
+    }
+
+    /// Estimated bottleneck bandwidth in bytes per second
+    pub const fn btl_bw(&self) -> u64 {
+        self.btl_bw
+    }
+
+    /// Minimum RTT observed within the estimate lifetime (ms), 0 if unknown
+    pub const fn min_rtt(&self) -> u32 {
+        self.min_rtt
+    }
+
+    /// Bandwidth-delay product in segments, scaled by `gain`
+    fn target_window(&self, gain: u64) -> u32 {
+        let rtt = if self.min_rtt > 0 {
+            self.min_rtt
+        } else {
+            BBR_INIT_RTT
+        };
+        let bdp = self.btl_bw * rtt as u64 / 1000;
+        let segments = (bdp * gain / BBR_UNIT).div_ceil(self.mss.max(1) as u64);
+        (segments.min(u32::MAX as u64) as u32).max(BBR_MIN_CWND)
+    }
+
+    fn round_time(&self) -> u32 {
+        if self.min_rtt > 0 {
+            self.min_rtt
+        } else if self.srtt > 0 {
+            self.srtt
+        } else {
+            BBR_INIT_RTT
+        }
+    }
+
+    fn update_min_rtt(&mut self, event: &AckEvent) {
+        let expired = self.min_rtt > 0
+            && itimediff(event.current, self.min_rtt_stamp) > BBR_MIN_RTT_WINDOW as i32;
+        if let Some(rtt) = event.rtt {
+            let rtt = rtt.max(1);
+            if self.min_rtt == 0 || rtt <= self.min_rtt || expired {
+                self.min_rtt = rtt;
+                self.min_rtt_stamp = event.current;
+            }
+        }
+        if expired && self.mode != BbrMode::ProbeRtt {
+            self.mode = BbrMode::ProbeRtt;
+            self.pacing_gain = BBR_UNIT;
+            self.prior_cwnd = self.cwnd;
+            self.probe_rtt_done = None;
+        }
+    }
+
+    /// Sample the delivery rate once per round trip
+    ///
+    /// # Returns
+    /// true when a new round has started
+    fn update_bandwidth(&mut self, event: &AckEvent) -> bool {
+        // the first ACK starts the first round, whatever the clock reads
+        if let Some(start) = self.round_start_time {
+            let elapsed = itimediff(event.current, start);
+            if elapsed < self.round_time() as i32 {
+                return false;
+            }
+            if elapsed > 0 {
+                let bytes = self.delivered - self.round_start_delivered;
+                let rate = bytes * 1000 / elapsed as u64;
+                self.bw_samples.push_back((self.round_count, rate));
+            }
+        }
+        while let Some(&(round, _)) = self.bw_samples.front() {
+            if round + BBR_BW_WINDOW_ROUNDS > self.round_count {
+                break;
+            }
+            self.bw_samples.pop_front();
+        }
+        self.btl_bw = self
+            .bw_samples
+            .iter()
+            .map(|&(_, rate)| rate)
+            .max()
+            .unwrap_or(0);
+
+        self.round_count += 1;
+        self.round_start_time = Some(event.current);
+        self.round_start_delivered = self.delivered;
+        true
+    }
+
+    fn check_full_pipe(&mut self) {
+        if self.filled_pipe || self.btl_bw == 0 {
+            return;
+        }
+        if self.btl_bw * 4 >= self.full_bw * 5 {
+            self.full_bw = self.btl_bw;
+            self.full_bw_count = 0;
+            return;
+        }
+        self.full_bw_count += 1;
+        if self.full_bw_count >= BBR_FULL_BW_ROUNDS {
+            self.filled_pipe = true;
+        }
+    }
+
+    fn enter_probe_bw(&mut self, current: u32) {
+        self.mode = BbrMode::ProbeBw;
+        self.cwnd_gain = BBR_CWND_GAIN;
+        self.cycle_index = 0;
+        self.cycle_stamp = current;
+        self.pacing_gain = BBR_PACING_GAINS[0];
+    }
+
+    fn update_mode(&mut self, current: u32) {
+        match self.mode {
+            BbrMode::Startup => {
+                if self.filled_pipe {
+                    self.mode = BbrMode::Drain;
+                    self.pacing_gain = BBR_DRAIN_GAIN;
+                    self.cwnd_gain = BBR_HIGH_GAIN;
+                }
+            }
+            BbrMode::Drain => {
+                if self.inflight <= self.target_window(BBR_UNIT) {
+                    self.enter_probe_bw(current);
+                }
+            }
+            BbrMode::ProbeBw => {
+                if itimediff(current, self.cycle_stamp) >= self.round_time() as i32 {
+                    self.cycle_index = (self.cycle_index + 1) % BBR_PACING_GAINS.len();
+                    self.cycle_stamp = current;
+                    self.pacing_gain = BBR_PACING_GAINS[self.cycle_index];
+                }
+            }
+            BbrMode::ProbeRtt => match self.probe_rtt_done {
+                None => {
+                    if self.inflight <= BBR_MIN_CWND {
+                        self.probe_rtt_done = Some(current.wrapping_add(BBR_PROBE_RTT_TIME));
+                    }
+                }
+                Some(done) => {
+                    if itimediff(current, done) >= 0 {
+                        self.min_rtt_stamp = current;
+                        self.probe_rtt_done = None;
+                        self.cwnd = self.cwnd.max(self.prior_cwnd);
+                        if self.filled_pipe {
+                            self.enter_probe_bw(current);
+                        } else {
+                            self.mode = BbrMode::Startup;
+                            self.pacing_gain = BBR_HIGH_GAIN;
+                            self.cwnd_gain = BBR_HIGH_GAIN;
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    fn update_cwnd(&mut self, acked_segments: u32) {
+        if self.mode == BbrMode::ProbeRtt {
+            self.cwnd = BBR_MIN_CWND;
+            return;
+        }
+        let target = self.target_window(self.cwnd_gain);
+        if self.filled_pipe {
+            self.cwnd = (self.cwnd + acked_segments).min(target);
+        } else if self.btl_bw == 0 || self.cwnd < target {
+            self.cwnd += acked_segments;
+        }
+        self.cwnd = self.cwnd.max(BBR_MIN_CWND);
+    }
+}
+
+impl CongestionController for BbrController {
+    fn on_ack(&mut self, event: &AckEvent) {
+        self.mss = event.mss;
+        self.srtt = event.srtt;
+        self.inflight = event.inflight;
+        self.delivered += event.acked_bytes as u64;
+
+        self.update_min_rtt(event);
+        if self.update_bandwidth(event) {
+            self.check_full_pipe();
+        }
+        self.update_mode(event.current);
+        self.update_cwnd(event.acked_segments);
+    }
+
+    /// Loss is not a congestion signal for BBR, the model only follows the
+    /// measured delivery rate and RTT.
+    fn on_loss(&mut self, _event: &LossEvent) {}
+
+    fn on_timeout(&mut self, _event: &TimeoutEvent) {}
+
+    fn window(&self) -> u32 {
+        self.cwnd
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        if self.btl_bw == 0 {
+            return None;
+        }
+        Some(self.btl_bw * self.pacing_gain / BBR_UNIT)
+    }
+}
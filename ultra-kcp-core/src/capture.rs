@@ -0,0 +1,287 @@
+use core::any::Any;
+use core::net::{IpAddr, SocketAddr};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::constants::KcpError;
+use crate::kcp::{KcpCallBack, KcpControl};
+use crate::trace::TraceEvent;
+
+/// Magic number of a pcap file with microsecond timestamps
+pub const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// Link type of raw IPv4/IPv6 packets (LINKTYPE_RAW)
+pub const PCAP_LINKTYPE_RAW: u32 = 101;
+
+/// Largest packet recorded, also the snapshot length of the file
+pub const PCAP_SNAPLEN: u32 = 65535;
+
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const UDP_HEADER_SIZE: usize = 8;
+const IPPROTO_UDP: u8 = 17;
+
+/// Direction of a captured datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Produced by the output path, from `local` to `remote`
+    Outgoing,
+    /// Fed to `KcpControl::input`, from `remote` to `local`
+    Incoming,
+}
+
+/// Synthetic endpoints written in the UDP/IP headers
+///
+/// Wireshark dissectors select KCP by UDP port, use the port they are
+/// configured for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureConfig {
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            local: SocketAddr::from(([10, 0, 0, 1], 4000)),
+            remote: SocketAddr::from(([10, 0, 0, 2], 4000)),
+        }
+    }
+}
+
+/// pcap file of KCP datagrams wrapped in synthetic UDP/IP headers
+///
+/// Timestamps are the `current` of the control block, in ms, so a capture
+/// lines up with `update` calls and with traces.
+pub struct PcapCapture<W> {
+    writer: W,
+    config: CaptureConfig,
+    /// IPv4 identification of the next packet
+    ip_id: u16,
+    packets: u64,
+}
+
+impl<W: Write> PcapCapture<W> {
+    /// Create a capture and write the pcap file header
+    ///
+    /// # Errors
+    /// - `InvalidInput`: `local` and `remote` belong to different address families
+    /// - Errors of `writer`
+    pub fn new(mut writer: W, config: CaptureConfig) -> io::Result<Self> {
+        if config.local.is_ipv4() != config.remote.is_ipv4() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "local and remote addresses must use the same family",
+            ));
+        }
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&PCAP_SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&PCAP_LINKTYPE_RAW.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            config,
+            ip_id: 0,
+            packets: 0,
+        })
+    }
+
+    pub const fn config(&self) -> &CaptureConfig {
+        &self.config
+    }
+
+    /// Number of datagrams recorded
+    pub const fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Record a datagram
+    ///
+    /// # Arguments
+    /// * `direction` - Selects the source and destination addresses
+    /// * `time` - Timestamp (ms)
+    /// * `datagram` - KCP datagram, the UDP payload
+    ///
+    /// # Errors
+    /// - `InvalidInput`: Datagram does not fit in a UDP packet
+    /// - Errors of the writer
+    pub fn record(&mut self, direction: Direction, time: u32, datagram: &[u8]) -> io::Result<()> {
+        let (src, dst) = match direction {
+            Direction::Outgoing => (self.config.local, self.config.remote),
+            Direction::Incoming => (self.config.remote, self.config.local),
+        };
+        let ip_header = if src.is_ipv4() {
+            IPV4_HEADER_SIZE
+        } else {
+            IPV6_HEADER_SIZE
+        };
+        let size = ip_header + UDP_HEADER_SIZE + datagram.len();
+        if size > PCAP_SNAPLEN as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram too large for a UDP packet",
+            ));
+        }
+
+        let mut packet = Vec::with_capacity(16 + size);
+        packet.extend_from_slice(&(time / 1000).to_le_bytes());
+        packet.extend_from_slice(&(time % 1000 * 1000).to_le_bytes());
+        packet.extend_from_slice(&(size as u32).to_le_bytes());
+        packet.extend_from_slice(&(size as u32).to_le_bytes());
+
+        let udp_len = (UDP_HEADER_SIZE + datagram.len()) as u16;
+        match (src.ip(), dst.ip()) {
+            (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+                let start = packet.len();
+                packet.extend_from_slice(&[0x45, 0]);
+                packet.extend_from_slice(&(size as u16).to_be_bytes());
+                packet.extend_from_slice(&self.ip_id.to_be_bytes());
+                // don't fragment, ttl 64
+                packet.extend_from_slice(&[0x40, 0, 64, IPPROTO_UDP, 0, 0]);
+                packet.extend_from_slice(&src_ip.octets());
+                packet.extend_from_slice(&dst_ip.octets());
+                let checksum = internet_checksum(&[&packet[start..]]);
+                packet[start + 10..start + 12].copy_from_slice(&checksum.to_be_bytes());
+                self.ip_id = self.ip_id.wrapping_add(1);
+            }
+            (IpAddr::V6(src_ip), IpAddr::V6(dst_ip)) => {
+                packet.extend_from_slice(&[0x60, 0, 0, 0]);
+                packet.extend_from_slice(&udp_len.to_be_bytes());
+                packet.extend_from_slice(&[IPPROTO_UDP, 64]);
+                packet.extend_from_slice(&src_ip.octets());
+                packet.extend_from_slice(&dst_ip.octets());
+            }
+            _ => unreachable!("families checked by new"),
+        }
+
+        let mut udp = [0; UDP_HEADER_SIZE];
+        udp[0..2].copy_from_slice(&src.port().to_be_bytes());
+        udp[2..4].copy_from_slice(&dst.port().to_be_bytes());
+        udp[4..6].copy_from_slice(&udp_len.to_be_bytes());
+        let checksum = udp_checksum(src.ip(), dst.ip(), &udp, datagram);
+        udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+        packet.extend_from_slice(&udp);
+        packet.extend_from_slice(datagram);
+
+        self.writer.write_all(&packet)?;
+        self.packets += 1;
+        Ok(())
+    }
+
+    /// Record an incoming datagram, then feed it to `kcp`
+    ///
+    /// # Errors
+    /// Errors of `KcpControl::input`, capture errors are ignored
+    pub fn input(&mut self, kcp: &mut KcpControl, datagram: &[u8]) -> Result<(), KcpError> {
+        let _ = self.record(Direction::Incoming, kcp.current, datagram);
+        kcp.input(datagram)
+    }
+}
+
+/// One's complement sum of 16 bit words, as used by IP and UDP
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    let mut odd = None;
+    for byte in parts.iter().flat_map(|part| part.iter().copied()) {
+        match odd.take() {
+            None => odd = Some(byte),
+            Some(high) => sum += u16::from_be_bytes([high, byte]) as u32,
+        }
+    }
+    if let Some(high) = odd {
+        sum += u16::from_be_bytes([high, 0]) as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// UDP checksum over the pseudo header, header and payload
+fn udp_checksum(src: IpAddr, dst: IpAddr, header: &[u8], payload: &[u8]) -> u16 {
+    let udp_len = (header.len() + payload.len()) as u32;
+    let mut pseudo = Vec::with_capacity(40);
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            pseudo.extend_from_slice(&src.octets());
+            pseudo.extend_from_slice(&dst.octets());
+            pseudo.extend_from_slice(&[0, IPPROTO_UDP]);
+            pseudo.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        }
+        _ => {
+            for ip in [src, dst] {
+                if let IpAddr::V6(ip) = ip {
+                    pseudo.extend_from_slice(&ip.octets());
+                }
+            }
+            pseudo.extend_from_slice(&udp_len.to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, IPPROTO_UDP]);
+        }
+    }
+    match internet_checksum(&[&pseudo, header, payload]) {
+        // zero means no checksum, it is sent as all ones
+        0 => 0xffff,
+        checksum => checksum,
+    }
+}
+
+/// Output callback recording every datagram before handing it to `inner`
+///
+/// The capture is shared with the input path, which records incoming
+/// datagrams with `PcapCapture::input`.
+///
+/// # Note
+/// Capture errors are ignored, a capture must never disturb the connection.
+pub struct CaptureOutput<C, W> {
+    capture: Arc<Mutex<PcapCapture<W>>>,
+    inner: C,
+}
+
+impl<C: KcpCallBack, W: Write + Send> CaptureOutput<C, W> {
+    pub fn new(capture: Arc<Mutex<PcapCapture<W>>>, inner: C) -> Self {
+        Self { capture, inner }
+    }
+
+    pub fn capture(&self) -> &Arc<Mutex<PcapCapture<W>>> {
+        &self.capture
+    }
+}
+
+impl<C: KcpCallBack, W: Write + Send> KcpCallBack for CaptureOutput<C, W> {
+    fn output(&self, buf: &[u8], kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {
+        let _ = self
+            .capture
+            .lock()
+            .unwrap()
+            .record(Direction::Outgoing, kcp.current, buf);
+        self.inner.output(buf, kcp, user);
+    }
+
+    fn writelog(&self, log: &str, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.writelog(log, kcp, user);
+    }
+
+    fn trace(&self, event: &TraceEvent, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.trace(event, kcp, user);
+    }
+
+    fn flushed(&self, kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.flushed(kcp, user);
+    }
+}
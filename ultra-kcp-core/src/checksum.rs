@@ -0,0 +1,40 @@
+/// Reflected CRC32C (Castagnoli) polynomial
+const CRC32C_POLY: u32 = 0x82f6_3b78;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32C of `data`, as used by iSCSI and SCTP
+pub fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (crc >> 8) ^ CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize]
+    })
+}
+
+/// Datagram checksum counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChecksumStats {
+    /// Datagrams sent with a checksum trailer
+    pub sent: u64,
+    /// Datagrams received with a valid checksum
+    pub verified: u64,
+    /// Datagrams dropped because of a checksum mismatch or a missing trailer
+    pub mismatches: u64,
+}
@@ -0,0 +1,244 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use lz4_flex::block::{
+    compress_into, compress_into_with_dict, decompress_into, decompress_into_with_dict,
+    get_maximum_output_size,
+};
+
+use crate::checksum::crc32c;
+use crate::constants::{KcpError, IKCP_MSG_MAX_DEF};
+use crate::kcp::KcpControl;
+
+/// Flags byte prefixed to every message
+pub const COMPRESS_FLAG_SIZE: usize = 1;
+
+/// Message body is an LZ4 block, preceded by the original size (4 bytes)
+pub const COMPRESS_FLAG_LZ4: u8 = 0x01;
+
+/// LZ4 block refers to the shared dictionary, whose id (4 bytes) follows the size
+pub const COMPRESS_FLAG_DICT: u8 = 0x02;
+
+/// An LZ4 block never expands to more than 255 times its size, each extra
+/// match length byte stands for at most 255 bytes
+const LZ4_MAX_RATIO: usize = 255;
+
+/// Default size below which messages are sent uncompressed (bytes)
+pub const COMPRESS_MIN_SIZE_DEF: usize = 64;
+
+/// Settings shared by both ends of a conversation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressConfig {
+    /// Messages smaller than this are sent uncompressed
+    pub min_size: usize,
+    /// Largest decompressed message accepted, protects against compression bombs
+    pub max_size: usize,
+    /// Dictionary primed with typical message content, the peer must use the same
+    pub dictionary: Option<Vec<u8>>,
+}
+
+impl Default for CompressConfig {
+    fn default() -> Self {
+        Self {
+            min_size: COMPRESS_MIN_SIZE_DEF,
+            max_size: IKCP_MSG_MAX_DEF,
+            dictionary: None,
+        }
+    }
+}
+
+/// Compression counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressStats {
+    /// Messages encoded
+    pub messages: u64,
+    /// Messages sent compressed
+    pub compressed: u64,
+    /// Messages sent uncompressed because compression did not pay off
+    pub incompressible: u64,
+    /// Bytes handed to `encode`
+    pub input_bytes: u64,
+    /// Bytes produced by `encode`, flags included
+    pub output_bytes: u64,
+}
+
+impl CompressStats {
+    /// Encoded size relative to the original size, lower is better
+    ///
+    /// # Returns
+    /// 1.0 before any message was encoded
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            return 1.0;
+        }
+        self.output_bytes as f64 / self.input_bytes as f64
+    }
+}
+
+/// LZ4 compression of messages above `KcpControl::send` and `receive`
+///
+/// Every message is prefixed with a flags byte telling whether it is
+/// compressed and with which dictionary, so each message is negotiated on
+/// its own: small or incompressible messages travel as is, and a receiver
+/// always decodes what the sender chose.
+///
+/// # Note
+/// Requires message mode, streaming mode does not preserve message boundaries.
+pub struct Compressor {
+    config: CompressConfig,
+    /// CRC32C of the dictionary, identifies it on the wire
+    dictionary_id: Option<u32>,
+    stats: CompressStats,
+}
+
+impl Compressor {
+    pub fn new(config: CompressConfig) -> Self {
+        let dictionary_id = config.dictionary.as_deref().map(crc32c);
+        Self {
+            config,
+            dictionary_id,
+            stats: CompressStats::default(),
+        }
+    }
+
+    pub const fn config(&self) -> &CompressConfig {
+        &self.config
+    }
+
+    pub const fn stats(&self) -> &CompressStats {
+        &self.stats
+    }
+
+    /// Encode a message, compressing it when it pays off
+    pub fn encode(&mut self, message: &[u8]) -> Vec<u8> {
+        let encoded = self.pack(message);
+        self.count(message, &encoded);
+        encoded
+    }
+
+    fn pack(&self, message: &[u8]) -> Vec<u8> {
+        self.compress(message)
+            .unwrap_or_else(|| [&[0][..], message].concat())
+    }
+
+    fn count(&mut self, message: &[u8], encoded: &[u8]) {
+        self.stats.messages += 1;
+        if encoded[0] & COMPRESS_FLAG_LZ4 != 0 {
+            self.stats.compressed += 1;
+        } else if message.len() >= self.config.min_size {
+            self.stats.incompressible += 1;
+        }
+        self.stats.input_bytes += message.len() as u64;
+        self.stats.output_bytes += encoded.len() as u64;
+    }
+
+    /// # Returns
+    /// `None` when the message is small or compression does not reduce its size
+    fn compress(&self, message: &[u8]) -> Option<Vec<u8>> {
+        if message.len() < self.config.min_size || message.len() > u32::MAX as usize {
+            return None;
+        }
+
+        let mut flags = COMPRESS_FLAG_LZ4;
+        let mut encoded = vec![0; COMPRESS_FLAG_SIZE + 8 + get_maximum_output_size(message.len())];
+        encoded[1..5].copy_from_slice(&(message.len() as u32).to_le_bytes());
+        let mut offset = 5;
+        if let Some(id) = self.dictionary_id {
+            flags |= COMPRESS_FLAG_DICT;
+            encoded[5..9].copy_from_slice(&id.to_le_bytes());
+            offset = 9;
+        }
+        encoded[0] = flags;
+
+        let block = match &self.config.dictionary {
+            Some(dictionary) => {
+                compress_into_with_dict(message, &mut encoded[offset..], dictionary)
+            }
+            None => compress_into(message, &mut encoded[offset..]),
+        }
+        .ok()?;
+        encoded.truncate(offset + block);
+
+        (encoded.len() < COMPRESS_FLAG_SIZE + message.len()).then_some(encoded)
+    }
+
+    /// Decode a message produced by the peer's `encode`
+    ///
+    /// # Errors
+    /// - `InvalidPacket`: Unknown flags, dictionary mismatch, corrupted LZ4 block,
+    ///   or a decompressed size the block cannot possibly expand to
+    /// - `MessageTooLarge`: Decompressed size exceeds `max_size`
+    pub fn decode(&self, encoded: &[u8]) -> Result<Vec<u8>, KcpError> {
+        let (&flags, body) = encoded.split_first().ok_or(KcpError::InvalidPacket)?;
+        if flags == 0 {
+            return Ok(body.to_vec());
+        }
+        if flags & !(COMPRESS_FLAG_LZ4 | COMPRESS_FLAG_DICT) != 0 || flags & COMPRESS_FLAG_LZ4 == 0
+        {
+            return Err(KcpError::InvalidPacket);
+        }
+
+        let field = |at: usize| -> Result<u32, KcpError> {
+            let bytes = body.get(at..at + 4).ok_or(KcpError::InvalidPacket)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let size = field(0)? as usize;
+        if size > self.config.max_size {
+            return Err(KcpError::MessageTooLarge);
+        }
+        let dictionary = if flags & COMPRESS_FLAG_DICT != 0 {
+            let id = field(4)?;
+            let dictionary = self
+                .config
+                .dictionary
+                .as_deref()
+                .filter(|_| self.dictionary_id == Some(id))
+                .ok_or(KcpError::InvalidPacket)?;
+            Some(dictionary)
+        } else {
+            None
+        };
+        let block = &body[if dictionary.is_some() { 8 } else { 4 }..];
+        // the size field is untrusted, do not allocate more than the block can hold
+        if size > block.len().saturating_mul(LZ4_MAX_RATIO) {
+            return Err(KcpError::InvalidPacket);
+        }
+
+        let mut message = vec![0; size];
+        let written = match dictionary {
+            Some(dictionary) => decompress_into_with_dict(block, &mut message, dictionary),
+            None => decompress_into(block, &mut message),
+        }
+        .map_err(|_| KcpError::InvalidPacket)?;
+
+        if written != size {
+            return Err(KcpError::InvalidPacket);
+        }
+        Ok(message)
+    }
+
+    /// Encode a message and queue it on `kcp`
+    ///
+    /// # Returns
+    /// Size of the original message
+    ///
+    /// # Errors
+    /// Errors of `KcpControl::send`
+    pub fn send(&mut self, kcp: &mut KcpControl, message: &[u8]) -> Result<usize, KcpError> {
+        let encoded = self.pack(message);
+        kcp.send(&encoded)?;
+        self.count(message, &encoded);
+        Ok(message.len())
+    }
+
+    /// Receive the next message from `kcp` and decode it
+    ///
+    /// # Errors
+    /// Errors of `KcpControl::receive`, then of `decode`
+    pub fn receive(&self, kcp: &mut KcpControl) -> Result<Vec<u8>, KcpError> {
+        let mut encoded = vec![0; kcp.peek_size()?];
+        let len = kcp.receive(Some(&mut encoded), false)?;
+        encoded.truncate(len);
+        self.decode(&encoded)
+    }
+}
@@ -0,0 +1,187 @@
+use alloc::boxed::Box;
+
+use crate::constants::{IKCP_THRESH_INIT, IKCP_THRESH_MIN};
+
+/// Acknowledgement progress reported after an input call acknowledged data
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AckEvent {
+    /// Current timestamp (ms)
+    pub current: u32,
+    /// `snd_una` moved forward, otherwise only out of order segments were acknowledged
+    pub una_advanced: bool,
+    /// Segments removed from the send buffer by this input call
+    pub acked_segments: u32,
+    /// Payload bytes removed from the send buffer by this input call
+    pub acked_bytes: u32,
+    /// Latest round trip time sample (ms), if an ACK carried a valid timestamp
+    pub rtt: Option<u32>,
+    /// Smoothed round trip time (ms)
+    pub srtt: u32,
+    /// Segments still in flight after the ACKs have been applied
+    pub inflight: u32,
+    /// Maximum segment size
+    pub mss: u32,
+    /// Remote receive window (segments)
+    pub rmt_wnd: u32,
+}
+
+/// Fast retransmission triggered by duplicate ACKs during a flush
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LossEvent {
+    /// Current timestamp (ms)
+    pub current: u32,
+    /// Segments in flight (`snd_nxt - snd_una`)
+    pub inflight: u32,
+    /// Fast resend threshold that triggered the retransmission
+    pub resent: u32,
+    /// Maximum segment size
+    pub mss: u32,
+}
+
+/// Retransmission timeout expired for at least one segment during a flush
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeoutEvent {
+    /// Current timestamp (ms)
+    pub current: u32,
+    /// Effective send window used by the flush (segments)
+    pub window: u32,
+    /// Maximum segment size
+    pub mss: u32,
+}
+
+/// Congestion control algorithm driving the KCP send window
+///
+/// `KcpControl` reports ACK progress, fast retransmissions and timeouts,
+/// and limits the number of segments in flight to `window()`, on top of the
+/// local send window and the remote receive window.
+pub trait CongestionController: Send + Sync {
+    /// Called when incoming ACKs removed segments from the send buffer
+    fn on_ack(&mut self, event: &AckEvent);
+
+    /// Called when segments were fast retransmitted
+    fn on_loss(&mut self, event: &LossEvent);
+
+    /// Called when segments were retransmitted after their RTO expired
+    fn on_timeout(&mut self, event: &TimeoutEvent);
+
+    /// Congestion window in segments
+    fn window(&self) -> u32;
+
+    /// Slow start threshold in segments, 0 when the algorithm has none
+    fn ssthresh(&self) -> u32 {
+        0
+    }
+
+    /// Bytes acknowledged towards the next window increment, 0 when the
+    /// algorithm has none
+    fn incr(&self) -> u32 {
+        0
+    }
+
+    /// Pacing rate in bytes per second, `None` when the controller does not pace
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Default for Box<dyn CongestionController> {
+    fn default() -> Self {
+        Box::new(KcpClassic::default())
+    }
+}
+
+/// The congestion control algorithm of the original ikcp
+///
+/// Slow start up to `ssthresh`, then additive increase driven by `incr`.
+/// Fast retransmissions halve the window to the inflight size, timeouts
+/// collapse it to a single segment.
+#[derive(Debug, Clone)]
+pub struct KcpClassic {
+    /// Congestion window (segments)
+    pub cwnd: u32,
+    /// Slow start threshold (segments)
+    pub ssthresh: u32,
+    /// Bytes acknowledged towards the next window increment
+    pub incr: u32,
+}
+
+impl Default for KcpClassic {
+    fn default() -> Self {
+        Self {
+            cwnd: 1,
+            ssthresh: IKCP_THRESH_INIT,
+            incr: 0,
+        }
+    }
+}
+
+impl CongestionController for KcpClassic {
+    fn on_ack(&mut self, event: &AckEvent) {
+        if !event.una_advanced || self.cwnd >= event.rmt_wnd {
+            return;
+        }
+        let mss = event.mss;
+        if self.cwnd < self.ssthresh {
+            self.cwnd += 1;
+            self.incr = self.incr.saturating_add(mss);
+        } else {
+            if self.incr < mss {
+                self.incr = mss;
+            }
+            self.incr = self
+                .incr
+                .saturating_add(mss.saturating_mul(mss) / self.incr + mss / 16);
+            if (self.cwnd + 1).saturating_mul(mss) <= self.incr {
+                self.cwnd = self.incr.div_ceil(mss.max(1));
+            }
+        }
+        if self.cwnd > event.rmt_wnd {
+            self.cwnd = event.rmt_wnd;
+            self.incr = event.rmt_wnd.saturating_mul(mss);
+        }
+    }
+
+    fn on_loss(&mut self, event: &LossEvent) {
+        self.ssthresh = (event.inflight / 2).max(IKCP_THRESH_MIN);
+        self.cwnd = self.ssthresh.saturating_add(event.resent);
+        self.incr = self.cwnd.saturating_mul(event.mss);
+    }
+
+    fn on_timeout(&mut self, event: &TimeoutEvent) {
+        self.ssthresh = (event.window / 2).max(IKCP_THRESH_MIN);
+        self.cwnd = 1;
+        self.incr = event.mss;
+    }
+
+    fn window(&self) -> u32 {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> u32 {
+        self.ssthresh
+    }
+
+    fn incr(&self) -> u32 {
+        self.incr
+    }
+}
+
+/// Disables congestion control, like `nocwnd` in `KcpControl::set_nodelay`
+///
+/// KCP sends as fast as the send window and the remote receive window
+/// allow. Useful for latency-sensitive applications that can tolerate
+/// packet loss.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoCongestion;
+
+impl CongestionController for NoCongestion {
+    fn on_ack(&mut self, _event: &AckEvent) {}
+
+    fn on_loss(&mut self, _event: &LossEvent) {}
+
+    fn on_timeout(&mut self, _event: &TimeoutEvent) {}
+
+    fn window(&self) -> u32 {
+        u32::MAX
+    }
+}
@@ -13,7 +13,7 @@ pub const IKCP_RTO_DEF: u32 = 200;
 pub const IKCP_RTO_MAX: u32 = 60000;
 
 // Command enum
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Command {
     /// Push data
     Push = 81,
@@ -23,6 +23,8 @@ pub enum Command {
     Wask = 83,
     /// window size (tell)
     Wins = 84,
+    /// selective ack, carrying received sn ranges
+    Sack = 85,
 }
 
 impl TryFrom<u32> for Command {
@@ -34,11 +36,25 @@ impl TryFrom<u32> for Command {
             82 => Ok(Command::Ack),
             83 => Ok(Command::Wask),
             84 => Ok(Command::Wins),
+            85 => Ok(Command::Sack),
             _ => Err("Invalid command value"),
         }
     }
 }
 
+impl Command {
+    /// Lowercase name, as used in traces and dumps
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Command::Push => "push",
+            Command::Ack => "ack",
+            Command::Wask => "wask",
+            Command::Wins => "wins",
+            Command::Sack => "sack",
+        }
+    }
+}
+
 impl From<Command> for u32 {
     fn from(command: Command) -> Self {
         command as u32
@@ -74,6 +90,15 @@ pub const IKCP_INTERVAL: u32 = 100;
 // Protocol overhead size
 pub const IKCP_OVERHEAD: u32 = 24;
 
+// Size of the CRC32C trailer appended to datagrams when checksums are enabled
+pub const IKCP_CHECKSUM_SIZE: u32 = 4;
+
+// Size of one [start, end) sn range in a SACK segment payload
+pub const IKCP_SACK_RANGE_SIZE: usize = 8;
+
+// Default largest message accepted in extended fragmentation mode (16 MiB)
+pub const IKCP_MSG_MAX_DEF: usize = 16 * 1024 * 1024;
+
 // Dead link threshold
 pub const IKCP_DEADLINK: u32 = 20;
 
@@ -101,6 +126,30 @@ pub enum KcpError {
     IncompleteMessage,
     /// Window size is too small to hold the data
     WindowFull,
+    /// Message exceeds the extended fragmentation size limit
+    MessageTooLarge,
+    /// Configuration parameters are out of range
+    InvalidConfig,
+    /// Datagram is truncated or carries malformed header fields
+    InvalidPacket,
+    /// Segment carries a command this implementation does not know
+    UnknownCommand,
+    /// Segment conversation id does not match this control block
+    ConversationMismatch,
+    /// Datagram checksum does not match its content
+    ChecksumMismatch,
+    /// Datagram failed authentication, or could not be sealed
+    AuthFailed,
+    /// Datagram was already received
+    Replayed,
+    /// Stream id is not open on this multiplexer
+    UnknownStream,
+    /// Stream was closed for writing
+    StreamClosed,
+    /// Stream was reset by either end
+    StreamReset,
+    /// Stream limit of the multiplexer is reached
+    TooManyStreams,
 }
 
 bitflags! {
@@ -130,5 +179,11 @@ bitflags! {
         const OUT_PROBE = 1 << 10;
         /// Log outgoing window size updates
         const OUT_WINS = 1 << 11;
+        /// Trace RTT estimator updates
+        const RTT = 1 << 12;
+        /// Trace congestion window changes
+        const CWND = 1 << 13;
+        /// Trace connection state changes
+        const STATE = 1 << 14;
     }
 }
@@ -0,0 +1,337 @@
+use alloc::vec::Vec;
+use core::net::SocketAddr;
+
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+
+use crate::constants::KcpError;
+use crate::serial::itimediff;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Conversation id reserved for handshake packets, never used by a session
+pub const COOKIE_CONV: u32 = u32::MAX;
+
+/// Handshake header size: reserved conv (4 bytes) + kind (1 byte) + requested conv (4 bytes)
+pub const COOKIE_HEADER_SIZE: usize = 9;
+
+/// Size of the truncated HMAC-SHA256 carried by challenges and echoes
+pub const COOKIE_MAC_SIZE: usize = 16;
+
+/// Size of challenge and echo packets: header + timestamp (4 bytes) + MAC
+pub const COOKIE_CHALLENGE_SIZE: usize = COOKIE_HEADER_SIZE + 4 + COOKIE_MAC_SIZE;
+
+/// Size of hello packets, padded so that a challenge is never larger than
+/// the hello that caused it
+pub const COOKIE_HELLO_SIZE: usize = COOKIE_CHALLENGE_SIZE;
+
+/// Default cookie lifetime (ms)
+pub const COOKIE_LIFETIME_DEF: u32 = 10000;
+
+/// Default rate of new conversations (per second)
+pub const COOKIE_RATE_DEF: u32 = 100;
+
+/// Default burst of new conversations accepted at once
+pub const COOKIE_BURST_DEF: u32 = 20;
+
+/// Handshake packet kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieKind {
+    /// Client asks for a cookie
+    Hello = 1,
+    /// Server hands out a cookie, without keeping any state
+    Challenge = 2,
+    /// Client proves it received the cookie at its address
+    Echo = 3,
+}
+
+impl TryFrom<u8> for CookieKind {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(CookieKind::Hello),
+            2 => Ok(CookieKind::Challenge),
+            3 => Ok(CookieKind::Echo),
+            _ => Err("Invalid cookie kind value"),
+        }
+    }
+}
+
+/// Reason a handshake packet was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieDrop {
+    /// Not a handshake packet, truncated, or unexpected kind
+    Malformed,
+    /// MAC does not match the address, conversation and timestamp
+    InvalidCookie,
+    /// Cookie is older than the configured lifetime
+    Expired,
+    /// Too many new conversations
+    RateLimited,
+}
+
+/// Decision taken by `CookieGuard::handle`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CookieVerdict {
+    /// Send this packet back to the peer, no state was kept
+    Reply(Vec<u8>),
+    /// Cookie verified, a session may be created for this conversation
+    Accept(u32),
+    /// Drop the datagram
+    Drop(CookieDrop),
+}
+
+/// Limits applied by `CookieGuard`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CookieConfig {
+    /// Time during which an issued cookie is accepted (ms)
+    pub lifetime: u32,
+    /// New conversations accepted per second
+    pub rate: u32,
+    /// New conversations accepted at once before the rate applies
+    pub burst: u32,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            lifetime: COOKIE_LIFETIME_DEF,
+            rate: COOKIE_RATE_DEF,
+            burst: COOKIE_BURST_DEF,
+        }
+    }
+}
+
+/// Cookie guard counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CookieStats {
+    /// Challenges sent in reply to hellos
+    pub challenges: u64,
+    /// Conversations accepted
+    pub accepted: u64,
+    /// Malformed handshake packets
+    pub malformed: u64,
+    /// Echoes carrying a forged or foreign cookie
+    pub invalid: u64,
+    /// Echoes carrying an expired cookie
+    pub expired: u64,
+    /// Valid echoes refused by the rate limit
+    pub rate_limited: u64,
+}
+
+/// Whether a datagram is a handshake packet rather than a KCP datagram
+pub fn is_handshake(packet: &[u8]) -> bool {
+    packet.len() >= COOKIE_HEADER_SIZE && packet[..4] == COOKIE_CONV.to_le_bytes()
+}
+
+fn header(kind: CookieKind, conv: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(COOKIE_CHALLENGE_SIZE);
+    packet.extend_from_slice(&COOKIE_CONV.to_le_bytes());
+    packet.push(kind as u8);
+    packet.extend_from_slice(&conv.to_le_bytes());
+    packet
+}
+
+/// Split a handshake packet into its kind and requested conversation
+fn parse_header(packet: &[u8]) -> Option<(CookieKind, u32)> {
+    if !is_handshake(packet) {
+        return None;
+    }
+    let kind = CookieKind::try_from(packet[4]).ok()?;
+    let conv = u32::from_le_bytes(packet[5..9].try_into().unwrap());
+    Some((kind, conv))
+}
+
+/// Build the hello packet opening conversation `conv`
+pub fn hello(conv: u32) -> Vec<u8> {
+    let mut packet = header(CookieKind::Hello, conv);
+    packet.resize(COOKIE_HELLO_SIZE, 0);
+    packet
+}
+
+/// Answer a challenge from the server
+///
+/// # Arguments
+/// * `challenge` - Packet received in reply to `hello`
+/// * `conv` - Conversation requested by the hello
+///
+/// # Returns
+/// The echo packet to send, after which the client may start its session
+///
+/// # Errors
+/// - `InvalidPacket`: Not a challenge for `conv`
+pub fn echo(challenge: &[u8], conv: u32) -> Result<Vec<u8>, KcpError> {
+    match parse_header(challenge) {
+        Some((CookieKind::Challenge, challenge_conv))
+            if challenge_conv == conv && challenge.len() == COOKIE_CHALLENGE_SIZE =>
+        {
+            let mut packet = header(CookieKind::Echo, conv);
+            packet.extend_from_slice(&challenge[COOKIE_HEADER_SIZE..]);
+            Ok(packet)
+        }
+        _ => Err(KcpError::InvalidPacket),
+    }
+}
+
+/// Stateless cookie exchange protecting a server from spoofed new conversations
+///
+/// Datagrams for unknown conversations are passed to `handle` instead of
+/// creating a `KcpControl` right away. A hello is answered with a cookie,
+/// an HMAC of the peer address, the conversation and a timestamp, and only
+/// an echo of a valid, fresh cookie from the same address is accepted, so a
+/// spoofed source never receives the cookie it needs. Nothing is stored per
+/// peer until then, and accepted conversations are rate limited.
+///
+/// # Note
+/// KCP datagrams sent by the client before its echo is accepted are dropped
+/// as malformed and later retransmitted by KCP. An echo repeated within the
+/// cookie lifetime is accepted again, the caller ignores it when the
+/// conversation already exists.
+pub struct CookieGuard {
+    config: CookieConfig,
+    secret: HmacSha256,
+    /// Secret replaced by the last rotation, accepted until its cookies expire
+    previous: Option<HmacSha256>,
+    /// Rate limit tokens, in conversations multiplied by 1000
+    tokens: u64,
+    last_refill: Option<u32>,
+    stats: CookieStats,
+}
+
+impl CookieGuard {
+    /// Create a new cookie guard
+    ///
+    /// # Arguments
+    /// * `secret` - Server secret, random and never sent on the wire
+    /// * `config` - Cookie lifetime and rate limits
+    pub fn new(secret: &[u8; 32], config: CookieConfig) -> Self {
+        Self {
+            config,
+            secret: keyed(secret),
+            previous: None,
+            tokens: config.burst as u64 * 1000,
+            last_refill: None,
+            stats: CookieStats::default(),
+        }
+    }
+
+    pub const fn config(&self) -> &CookieConfig {
+        &self.config
+    }
+
+    pub const fn stats(&self) -> &CookieStats {
+        &self.stats
+    }
+
+    /// Replace the server secret
+    ///
+    /// Cookies issued under the previous secret stay valid until they expire.
+    pub fn rotate_secret(&mut self, secret: &[u8; 32]) {
+        let previous = core::mem::replace(&mut self.secret, keyed(secret));
+        self.previous = Some(previous);
+    }
+
+    /// Process a handshake packet from `addr`
+    ///
+    /// # Arguments
+    /// * `addr` - Source address of the datagram
+    /// * `packet` - Datagram for an unknown conversation
+    /// * `current` - Current timestamp (ms), on the clock driving `KcpControl::update`
+    pub fn handle(&mut self, addr: SocketAddr, packet: &[u8], current: u32) -> CookieVerdict {
+        let verdict = match parse_header(packet) {
+            Some((CookieKind::Hello, conv))
+                if packet.len() >= COOKIE_HELLO_SIZE && conv != COOKIE_CONV =>
+            {
+                self.stats.challenges += 1;
+                CookieVerdict::Reply(self.challenge(addr, conv, current))
+            }
+            Some((CookieKind::Echo, conv)) if packet.len() == COOKIE_CHALLENGE_SIZE => {
+                self.verify(addr, conv, &packet[COOKIE_HEADER_SIZE..], current)
+            }
+            _ => CookieVerdict::Drop(CookieDrop::Malformed),
+        };
+
+        match verdict {
+            CookieVerdict::Accept(_) => self.stats.accepted += 1,
+            CookieVerdict::Drop(CookieDrop::Malformed) => self.stats.malformed += 1,
+            CookieVerdict::Drop(CookieDrop::InvalidCookie) => self.stats.invalid += 1,
+            CookieVerdict::Drop(CookieDrop::Expired) => self.stats.expired += 1,
+            CookieVerdict::Drop(CookieDrop::RateLimited) => self.stats.rate_limited += 1,
+            CookieVerdict::Reply(_) => {}
+        }
+        verdict
+    }
+
+    fn challenge(&self, addr: SocketAddr, conv: u32, current: u32) -> Vec<u8> {
+        let mac = mac(&self.secret, addr, conv, current);
+        let mut packet = header(CookieKind::Challenge, conv);
+        packet.extend_from_slice(&current.to_le_bytes());
+        packet.extend_from_slice(&mac.finalize().into_bytes()[..COOKIE_MAC_SIZE]);
+        packet
+    }
+
+    fn verify(&mut self, addr: SocketAddr, conv: u32, body: &[u8], current: u32) -> CookieVerdict {
+        let issued = u32::from_le_bytes(body[..4].try_into().unwrap());
+        let tag = &body[4..];
+
+        let authentic = [Some(&self.secret), self.previous.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|secret| {
+                mac(secret, addr, conv, issued)
+                    .verify_truncated_left(tag)
+                    .is_ok()
+            });
+        if !authentic {
+            return CookieVerdict::Drop(CookieDrop::InvalidCookie);
+        }
+
+        let age = itimediff(current, issued);
+        if age < 0 || age as u32 > self.config.lifetime {
+            return CookieVerdict::Drop(CookieDrop::Expired);
+        }
+        if !self.take_token(current) {
+            return CookieVerdict::Drop(CookieDrop::RateLimited);
+        }
+        CookieVerdict::Accept(conv)
+    }
+
+    fn take_token(&mut self, current: u32) -> bool {
+        let capacity = self.config.burst.max(1) as u64 * 1000;
+        if let Some(last) = self.last_refill {
+            let elapsed = itimediff(current, last).max(0) as u64;
+            self.tokens = (self.tokens + elapsed * self.config.rate as u64).min(capacity);
+        }
+        self.last_refill = Some(current);
+
+        if self.tokens < 1000 {
+            return false;
+        }
+        self.tokens -= 1000;
+        true
+    }
+}
+
+fn keyed(secret: &[u8; 32]) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any size")
+}
+
+/// MAC over the cookie timestamp, conversation and peer address
+fn mac(secret: &HmacSha256, addr: SocketAddr, conv: u32, issued: u32) -> HmacSha256 {
+    let mut mac = secret.clone();
+    mac.update(&issued.to_le_bytes());
+    mac.update(&conv.to_le_bytes());
+    match addr {
+        SocketAddr::V4(v4) => {
+            mac.update(&[4]);
+            mac.update(&v4.ip().octets());
+        }
+        SocketAddr::V6(v6) => {
+            mac.update(&[6]);
+            mac.update(&v6.ip().octets());
+        }
+    }
+    mac.update(&addr.port().to_le_bytes());
+    mac
+}
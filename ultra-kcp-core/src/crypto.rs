@@ -0,0 +1,376 @@
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::any::Any;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+
+use chacha20poly1305::aead::{Aead, Key, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+use crate::constants::KcpError;
+#[cfg(feature = "std")]
+use crate::kcp::KcpCallBack;
+use crate::kcp::KcpControl;
+#[cfg(feature = "std")]
+use crate::trace::TraceEvent;
+
+/// Size of a ChaCha20-Poly1305 key
+pub const CRYPTO_KEY_SIZE: usize = 32;
+
+/// Crypto header size: key id (1 byte) + datagram counter (8 bytes)
+pub const CRYPTO_HEADER_SIZE: usize = 9;
+
+/// Size of the Poly1305 authentication tag appended to every datagram
+pub const CRYPTO_TAG_SIZE: usize = 16;
+
+/// Total per-datagram overhead added by the crypto layer
+pub const CRYPTO_OVERHEAD: usize = CRYPTO_HEADER_SIZE + CRYPTO_TAG_SIZE;
+
+/// Number of counters tracked behind the highest one received, older
+/// datagrams are rejected as replays
+pub const CRYPTO_REPLAY_WINDOW: u64 = 1024;
+
+const REPLAY_WORDS: usize = (CRYPTO_REPLAY_WINDOW / 64) as usize;
+
+/// Side of the connection, keeps the nonces of both directions apart
+///
+/// Both ends share the same key, so each one must use a different role.
+/// A datagram reflected back to its sender then fails authentication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoRole {
+    /// The side that opened the connection
+    Initiator = 0,
+    /// The side that accepted the connection
+    Responder = 1,
+}
+
+impl CryptoRole {
+    const fn peer(self) -> Self {
+        match self {
+            CryptoRole::Initiator => CryptoRole::Responder,
+            CryptoRole::Responder => CryptoRole::Initiator,
+        }
+    }
+}
+
+/// Crypto layer counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CryptoStats {
+    /// Datagrams sealed
+    pub sealed: u64,
+    /// Datagrams authenticated and decrypted
+    pub opened: u64,
+    /// Datagrams failing authentication, truncated, or using an unknown key
+    pub auth_failures: u64,
+    /// Authentic datagrams rejected as replays
+    pub replays: u64,
+    /// Key rotations, local or initiated by the peer
+    pub rotations: u64,
+}
+
+/// Sliding window of received datagram counters (RFC 6479 style bitmap)
+#[derive(Clone)]
+struct ReplayWindow {
+    /// Highest counter received, `None` before the first datagram
+    highest: Option<u64>,
+    bitmap: [u64; REPLAY_WORDS],
+}
+
+impl ReplayWindow {
+    const fn new() -> Self {
+        Self {
+            highest: None,
+            bitmap: [0; REPLAY_WORDS],
+        }
+    }
+
+    const fn bit(counter: u64) -> (usize, u64) {
+        let index = counter % CRYPTO_REPLAY_WINDOW;
+        ((index / 64) as usize, 1 << (index % 64))
+    }
+
+    /// The counter has not been seen and is recent enough to be tracked
+    fn is_fresh(&self, counter: u64) -> bool {
+        let Some(highest) = self.highest else {
+            return true;
+        };
+        if counter > highest {
+            return true;
+        }
+        if highest - counter >= CRYPTO_REPLAY_WINDOW {
+            return false;
+        }
+        let (word, mask) = Self::bit(counter);
+        self.bitmap[word] & mask == 0
+    }
+
+    fn mark(&mut self, counter: u64) {
+        match self.highest {
+            Some(highest) if counter <= highest => {}
+            Some(highest) if counter - highest < CRYPTO_REPLAY_WINDOW => {
+                for skipped in highest + 1..=counter {
+                    let (word, mask) = Self::bit(skipped);
+                    self.bitmap[word] &= !mask;
+                }
+                self.highest = Some(counter);
+            }
+            _ => {
+                self.bitmap = [0; REPLAY_WORDS];
+                self.highest = Some(counter);
+            }
+        }
+        let (word, mask) = Self::bit(counter);
+        self.bitmap[word] |= mask;
+    }
+}
+
+/// One generation of the session key
+struct KeyState {
+    id: u8,
+    cipher: ChaCha20Poly1305,
+    replay: ReplayWindow,
+}
+
+impl KeyState {
+    fn new(id: u8, key: &[u8; CRYPTO_KEY_SIZE]) -> Self {
+        Self {
+            id,
+            cipher: ChaCha20Poly1305::new(&Key::<ChaCha20Poly1305>::from(*key)),
+            replay: ReplayWindow::new(),
+        }
+    }
+}
+
+fn nonce(role: CryptoRole, counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[0] = role as u8;
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    Nonce::from(nonce)
+}
+
+/// Authenticated encryption of KCP datagrams
+///
+/// Every datagram produced by `KcpCallBack::output` is sealed with
+/// ChaCha20-Poly1305 under a nonce derived from the sender role and a per
+/// key datagram counter. The header (key id + counter) travels in clear and
+/// is authenticated as associated data. Incoming datagrams are authenticated
+/// and checked against a replay window before being handed to
+/// `KcpControl::input`.
+///
+/// # Note
+/// Keys are rotated in two steps so that no datagram is dropped: both sides
+/// first install the next key with `prepare_key`, then either side switches
+/// with `rotate`. The peer switches automatically on the first datagram
+/// authenticated with the next key, and the previous key keeps opening
+/// datagrams still in flight until the following rotation.
+pub struct CryptoSession {
+    role: CryptoRole,
+    current: KeyState,
+    previous: Option<KeyState>,
+    next: Option<KeyState>,
+    /// Counter of the next sealed datagram under `current`
+    send_counter: u64,
+    stats: CryptoStats,
+}
+
+impl CryptoSession {
+    /// Create a new crypto session
+    ///
+    /// # Arguments
+    /// * `key` - Shared 256 bit key, exchanged out of band
+    /// * `role` - Side of the connection, must differ from the peer's
+    pub fn new(key: &[u8; CRYPTO_KEY_SIZE], role: CryptoRole) -> Self {
+        Self {
+            role,
+            current: KeyState::new(0, key),
+            previous: None,
+            next: None,
+            send_counter: 0,
+            stats: CryptoStats::default(),
+        }
+    }
+
+    pub const fn role(&self) -> CryptoRole {
+        self.role
+    }
+
+    /// Id of the key used to seal outgoing datagrams, incremented by every rotation
+    pub const fn key_id(&self) -> u8 {
+        self.current.id
+    }
+
+    pub const fn stats(&self) -> &CryptoStats {
+        &self.stats
+    }
+
+    /// Install the key that the next rotation switches to
+    ///
+    /// Datagrams sealed with it are accepted right away, so the peer may
+    /// rotate as soon as both sides have prepared the key.
+    pub fn prepare_key(&mut self, key: &[u8; CRYPTO_KEY_SIZE]) {
+        self.next = Some(KeyState::new(self.current.id.wrapping_add(1), key));
+    }
+
+    /// Switch outgoing datagrams to the prepared key
+    ///
+    /// # Errors
+    /// - `InvalidConfig`: No key was prepared
+    pub fn rotate(&mut self) -> Result<(), KcpError> {
+        let next = self.next.take().ok_or(KcpError::InvalidConfig)?;
+        self.previous = Some(core::mem::replace(&mut self.current, next));
+        self.send_counter = 0;
+        self.stats.rotations += 1;
+        Ok(())
+    }
+
+    /// Seal a datagram
+    ///
+    /// # Arguments
+    /// * `datagram` - Datagram emitted by the KCP output path
+    ///
+    /// # Returns
+    /// The packet to send on the wire, `CRYPTO_OVERHEAD` bytes longer
+    ///
+    /// # Errors
+    /// - `InvalidConfig`: The datagram counter of the current key is exhausted,
+    ///   the key must be rotated
+    /// - `AuthFailed`: The cipher refused to seal the datagram
+    pub fn seal(&mut self, datagram: &[u8]) -> Result<Vec<u8>, KcpError> {
+        let counter = self.send_counter;
+        self.send_counter = counter.checked_add(1).ok_or(KcpError::InvalidConfig)?;
+
+        let mut header = [0; CRYPTO_HEADER_SIZE];
+        header[0] = self.current.id;
+        header[1..].copy_from_slice(&counter.to_le_bytes());
+
+        let sealed = self
+            .current
+            .cipher
+            .encrypt(
+                &nonce(self.role, counter),
+                Payload {
+                    msg: datagram,
+                    aad: &header,
+                },
+            )
+            .map_err(|_| KcpError::AuthFailed)?;
+
+        let mut packet = Vec::with_capacity(CRYPTO_HEADER_SIZE + sealed.len());
+        packet.extend_from_slice(&header);
+        packet.extend_from_slice(&sealed);
+        self.stats.sealed += 1;
+        Ok(packet)
+    }
+
+    /// Authenticate and decrypt a packet received from the peer
+    ///
+    /// # Returns
+    /// The datagram to pass to `KcpControl::input`
+    ///
+    /// # Errors
+    /// - `AuthFailed`: Packet is truncated, tampered with, or sealed with an unknown key
+    /// - `Replayed`: Packet was already received or is older than the replay window
+    pub fn open(&mut self, packet: &[u8]) -> Result<Vec<u8>, KcpError> {
+        if packet.len() < CRYPTO_OVERHEAD {
+            self.stats.auth_failures += 1;
+            return Err(KcpError::AuthFailed);
+        }
+        let (header, sealed) = packet.split_at(CRYPTO_HEADER_SIZE);
+        let key_id = header[0];
+        let counter = u64::from_le_bytes(header[1..].try_into().unwrap());
+        let peer = self.role.peer();
+
+        let is_next = self.next.as_ref().is_some_and(|key| key.id == key_id);
+        let key = if self.current.id == key_id {
+            &mut self.current
+        } else if let Some(key) = self.previous.as_mut().filter(|key| key.id == key_id) {
+            key
+        } else if let Some(key) = self.next.as_mut().filter(|key| key.id == key_id) {
+            key
+        } else {
+            self.stats.auth_failures += 1;
+            return Err(KcpError::AuthFailed);
+        };
+
+        // cheap check first, the window is only updated once the packet is authentic
+        if !key.replay.is_fresh(counter) {
+            self.stats.replays += 1;
+            return Err(KcpError::Replayed);
+        }
+        let Ok(datagram) = key.cipher.decrypt(
+            &nonce(peer, counter),
+            Payload {
+                msg: sealed,
+                aad: header,
+            },
+        ) else {
+            self.stats.auth_failures += 1;
+            return Err(KcpError::AuthFailed);
+        };
+        key.replay.mark(counter);
+
+        if is_next {
+            // the peer rotated first, follow it
+            self.rotate()?;
+        }
+        self.stats.opened += 1;
+        Ok(datagram)
+    }
+
+    /// Open a packet and feed the datagram to `kcp`
+    ///
+    /// # Errors
+    /// Errors of `open`, then of `KcpControl::input`
+    pub fn input(&mut self, kcp: &mut KcpControl, packet: &[u8]) -> Result<(), KcpError> {
+        let datagram = self.open(packet)?;
+        kcp.input(&datagram)
+    }
+}
+
+/// Output callback sealing every datagram before handing it to `inner`
+///
+/// The session is shared with the input path, which opens the packets
+/// received from the peer with `CryptoSession::input`.
+#[cfg(feature = "std")]
+pub struct SealedOutput<C> {
+    session: Arc<Mutex<CryptoSession>>,
+    inner: C,
+}
+
+#[cfg(feature = "std")]
+impl<C: KcpCallBack> SealedOutput<C> {
+    pub fn new(session: Arc<Mutex<CryptoSession>>, inner: C) -> Self {
+        Self { session, inner }
+    }
+
+    pub fn session(&self) -> &Arc<Mutex<CryptoSession>> {
+        &self.session
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C: KcpCallBack> KcpCallBack for SealedOutput<C> {
+    fn output(&self, buf: &[u8], kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {
+        let sealed = self.session.lock().unwrap().seal(buf);
+        match sealed {
+            Ok(packet) => self.inner.output(&packet, kcp, user),
+            Err(_) => self
+                .inner
+                .writelog("crypto: datagram dropped, rotate the key", kcp, user),
+        }
+    }
+
+    fn writelog(&self, log: &str, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.writelog(log, kcp, user);
+    }
+
+    fn trace(&self, event: &TraceEvent, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.trace(event, kcp, user);
+    }
+
+    fn flushed(&self, kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.flushed(kcp, user);
+    }
+}
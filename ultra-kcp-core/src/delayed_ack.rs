@@ -0,0 +1,37 @@
+/// Delayed acknowledgement policy
+///
+/// Pending ACKs are held back across flushes until `every` segments are
+/// waiting or a segment arrives out of order, in which case they go out on
+/// the next flush. Once the oldest one has waited `max_delay` they go out
+/// right away, `check` and `update` do not wait for the next interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DelayedAck {
+    /// Acknowledge once this many segments are pending
+    pub every: u32,
+    /// Maximum time an ACK may be held back (ms)
+    pub max_delay: u32,
+    /// Drop ACKs made redundant by `una`, and duplicate ACKs for the same sn
+    pub coalesce: bool,
+}
+
+impl Default for DelayedAck {
+    fn default() -> Self {
+        Self {
+            every: 2,
+            max_delay: 20,
+            coalesce: true,
+        }
+    }
+}
+
+/// Acknowledgement counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AckStats {
+    /// Received segments that required an acknowledgement
+    pub acks_requested: u64,
+    /// ACK and SACK segments sent
+    pub acks_sent: u64,
+    /// Acknowledgements that did not need a segment of their own, thanks to
+    /// coalescing or SACK ranges
+    pub acks_saved: u64,
+}
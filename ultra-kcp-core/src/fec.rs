@@ -0,0 +1,518 @@
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::any::Any;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use crate::constants::KcpError;
+#[cfg(feature = "std")]
+use crate::kcp::KcpCallBack;
+use crate::kcp::KcpControl;
+#[cfg(feature = "std")]
+use crate::trace::TraceEvent;
+
+/// FEC header size: sequence id (4 bytes) + shard type (2 bytes)
+pub const FEC_HEADER_SIZE: usize = 6;
+
+/// Size prefix carried at the beginning of every data shard
+pub const FEC_SIZE_FIELD: usize = 2;
+
+/// Total per-datagram overhead added by the FEC layer
+pub const FEC_OVERHEAD: usize = FEC_HEADER_SIZE + FEC_SIZE_FIELD;
+
+/// Default number of FEC groups kept for reconstruction on the receive side
+pub const FEC_RX_GROUPS_DEF: usize = 16;
+
+/// Shard type carried in the FEC header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FecType {
+    /// Original datagram
+    Data = 0xf1,
+    /// Reed-Solomon parity generated from a group of data shards
+    Parity = 0xf2,
+    /// Parity of a group cut short by `FecEncoder::flush`, prefixed with the
+    /// number of data shards sent (u16), the others are zero padding
+    PartialParity = 0xf3,
+}
+
+impl TryFrom<u16> for FecType {
+    type Error = &'static str;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0xf1 => Ok(FecType::Data),
+            0xf2 => Ok(FecType::Parity),
+            0xf3 => Ok(FecType::PartialParity),
+            _ => Err("Invalid fec type value"),
+        }
+    }
+}
+
+/// Receive-side FEC counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FecStats {
+    /// Data shards received from the wire
+    pub data_received: u64,
+    /// Parity shards received from the wire
+    pub parity_received: u64,
+    /// Datagrams rebuilt from parity
+    pub recovered_datagrams: u64,
+    /// Groups in which at least one lost datagram was rebuilt
+    pub recovered_groups: u64,
+    /// Groups dropped while still missing data shards
+    pub unrecoverable_groups: u64,
+}
+
+/// Sequence ids wrap at a multiple of the group size, so that a group never
+/// straddles the wrap point.
+fn seq_limit(total_shards: usize) -> u32 {
+    (u32::MAX / total_shards as u32) * total_shards as u32
+}
+
+fn new_codec(data_shards: usize, parity_shards: usize) -> Result<ReedSolomon, KcpError> {
+    if data_shards == 0 || parity_shards == 0 {
+        return Err(KcpError::InvalidConfig);
+    }
+    ReedSolomon::new(data_shards, parity_shards).map_err(|_| KcpError::InvalidConfig)
+}
+
+/// Groups outgoing datagrams into Reed-Solomon protected shards
+///
+/// Every datagram produced by `KcpCallBack::output` is passed to `encode`,
+/// which prefixes it with an FEC header and, after `data_shards` datagrams,
+/// additionally emits `parity_shards` parity datagrams for the group. A group
+/// still waiting for datagrams is only protected once `flush` is called.
+pub struct FecEncoder {
+    codec: ReedSolomon,
+    data_shards: usize,
+    parity_shards: usize,
+    next_seq: u32,
+    seq_limit: u32,
+    /// Data shards (size field + payload) of the group being built
+    shards: Vec<Vec<u8>>,
+    max_size: usize,
+}
+
+impl FecEncoder {
+    /// Create a new FEC encoder
+    ///
+    /// # Arguments
+    /// * `data_shards` - Number of datagrams per group
+    /// * `parity_shards` - Number of parity datagrams generated per group
+    ///
+    /// # Errors
+    /// - `InvalidConfig`: Shard counts are zero or exceed the codec limits
+    pub fn new(data_shards: usize, parity_shards: usize) -> Result<Self, KcpError> {
+        let codec = new_codec(data_shards, parity_shards)?;
+        Ok(Self {
+            codec,
+            data_shards,
+            parity_shards,
+            next_seq: 0,
+            seq_limit: seq_limit(data_shards + parity_shards),
+            shards: Vec::with_capacity(data_shards),
+            max_size: 0,
+        })
+    }
+
+    pub const fn data_shards(&self) -> usize {
+        self.data_shards
+    }
+
+    pub const fn parity_shards(&self) -> usize {
+        self.parity_shards
+    }
+
+    /// Wrap a datagram into FEC packets
+    ///
+    /// # Arguments
+    /// * `datagram` - Datagram emitted by the KCP output path
+    /// * `emit` - Called for every packet that must be sent on the wire
+    ///
+    /// # Errors
+    /// - `BufferTooSmall`: Datagram does not fit in the 16 bits size field
+    pub fn encode<F: FnMut(&[u8])>(
+        &mut self,
+        datagram: &[u8],
+        mut emit: F,
+    ) -> Result<(), KcpError> {
+        let size = datagram.len() + FEC_SIZE_FIELD;
+        if size > u16::MAX as usize {
+            return Err(KcpError::BufferTooSmall);
+        }
+
+        let mut packet = Vec::with_capacity(FEC_HEADER_SIZE + size);
+        packet.extend_from_slice(&self.take_seq().to_le_bytes());
+        packet.extend_from_slice(&(FecType::Data as u16).to_le_bytes());
+        packet.extend_from_slice(&(size as u16).to_le_bytes());
+        packet.extend_from_slice(datagram);
+        emit(&packet);
+
+        self.max_size = self.max_size.max(size);
+        packet.drain(..FEC_HEADER_SIZE);
+        self.shards.push(packet);
+
+        if self.shards.len() == self.data_shards {
+            self.emit_parity(&mut emit);
+        }
+        Ok(())
+    }
+
+    /// Emit the parity of the group being built, if any
+    ///
+    /// The missing data shards are zero padding that never goes on the wire,
+    /// so the end of a burst can be rebuilt without waiting for the next
+    /// datagrams. Call it once the KCP flush ends, `FecOutput` does.
+    ///
+    /// # Arguments
+    /// * `emit` - Called for every packet that must be sent on the wire
+    pub fn flush<F: FnMut(&[u8])>(&mut self, mut emit: F) {
+        if self.shards.is_empty() {
+            return;
+        }
+        for _ in self.shards.len()..self.data_shards {
+            self.take_seq();
+        }
+        self.emit_parity(&mut emit);
+    }
+
+    fn emit_parity<F: FnMut(&[u8])>(&mut self, emit: &mut F) {
+        let sent = self.shards.len();
+        let mut shards = core::mem::take(&mut self.shards);
+        for shard in shards.iter_mut() {
+            shard.resize(self.max_size, 0);
+        }
+        shards.resize(
+            self.data_shards + self.parity_shards,
+            vec![0; self.max_size],
+        );
+        // shards are equally sized and counted, encoding cannot fail
+        self.codec.encode(&mut shards).unwrap();
+
+        let mut packet = Vec::with_capacity(FEC_OVERHEAD + self.max_size);
+        for parity in &shards[self.data_shards..] {
+            packet.clear();
+            packet.extend_from_slice(&self.take_seq().to_le_bytes());
+            if sent == self.data_shards {
+                packet.extend_from_slice(&(FecType::Parity as u16).to_le_bytes());
+            } else {
+                packet.extend_from_slice(&(FecType::PartialParity as u16).to_le_bytes());
+                packet.extend_from_slice(&(sent as u16).to_le_bytes());
+            }
+            packet.extend_from_slice(parity);
+            emit(&packet);
+        }
+
+        shards.clear();
+        self.shards = shards;
+        self.max_size = 0;
+    }
+
+    fn take_seq(&mut self) -> u32 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if self.next_seq >= self.seq_limit {
+            self.next_seq = 0;
+        }
+        seq
+    }
+}
+
+struct FecGroup {
+    id: u32,
+    shards: Vec<Option<Vec<u8>>>,
+    data_received: usize,
+    received: usize,
+    parity_len: usize,
+    /// All data shards are either received or reconstructed
+    done: bool,
+}
+
+/// Reconstructs lost datagrams from FEC packets
+///
+/// Every packet received from the wire is passed to `decode`, which hands
+/// the original datagrams (received or rebuilt) to the caller so they can be
+/// fed to the KCP input path.
+pub struct FecDecoder {
+    codec: ReedSolomon,
+    data_shards: usize,
+    parity_shards: usize,
+    groups: VecDeque<FecGroup>,
+    rx_limit: usize,
+    stats: FecStats,
+}
+
+impl FecDecoder {
+    /// Create a new FEC decoder
+    ///
+    /// # Arguments
+    /// * `data_shards` - Number of datagrams per group, must match the peer encoder
+    /// * `parity_shards` - Number of parity datagrams per group, must match the peer encoder
+    ///
+    /// # Errors
+    /// - `InvalidConfig`: Shard counts are zero or exceed the codec limits
+    pub fn new(data_shards: usize, parity_shards: usize) -> Result<Self, KcpError> {
+        let codec = new_codec(data_shards, parity_shards)?;
+        Ok(Self {
+            codec,
+            data_shards,
+            parity_shards,
+            groups: VecDeque::new(),
+            rx_limit: FEC_RX_GROUPS_DEF,
+            stats: FecStats::default(),
+        })
+    }
+
+    /// Set how many incomplete groups are kept waiting for more shards
+    ///
+    /// Older groups are dropped first and counted as unrecoverable when
+    /// data shards are still missing.
+    pub fn set_rx_limit(&mut self, limit: usize) {
+        self.rx_limit = limit.max(1);
+    }
+
+    pub const fn stats(&self) -> &FecStats {
+        &self.stats
+    }
+
+    /// Process a packet received from the wire
+    ///
+    /// # Arguments
+    /// * `packet` - FEC packet as produced by the peer `FecEncoder`
+    /// * `emit` - Called with every datagram ready for the KCP input path
+    ///
+    /// # Errors
+    /// - `InvalidPacket`: Packet is truncated or carries an unknown shard type
+    pub fn decode<F: FnMut(&[u8])>(&mut self, packet: &[u8], mut emit: F) -> Result<(), KcpError> {
+        if packet.len() < FEC_HEADER_SIZE {
+            return Err(KcpError::InvalidPacket);
+        }
+        let seq = u32::from_le_bytes(packet[0..4].try_into().unwrap());
+        let fec_type = FecType::try_from(u16::from_le_bytes(packet[4..6].try_into().unwrap()))
+            .map_err(|_| KcpError::InvalidPacket)?;
+        let shard = &packet[FEC_HEADER_SIZE..];
+
+        let total = self.data_shards + self.parity_shards;
+        let group_id = seq / total as u32;
+        let index = (seq % total as u32) as usize;
+
+        match fec_type {
+            FecType::Data => {
+                if index >= self.data_shards {
+                    return Err(KcpError::InvalidPacket);
+                }
+                let payload = Self::shard_payload(shard).ok_or(KcpError::InvalidPacket)?;
+                self.stats.data_received += 1;
+                let group = self.group_mut(group_id);
+                if group.done || group.shards[index].is_some() {
+                    // duplicate, or already rebuilt from parity
+                    return Ok(());
+                }
+                group.shards[index] = Some(shard.to_vec());
+                group.data_received += 1;
+                group.received += 1;
+                emit(payload);
+            }
+            FecType::Parity => {
+                if index < self.data_shards {
+                    return Err(KcpError::InvalidPacket);
+                }
+                self.stats.parity_received += 1;
+                let group = self.group_mut(group_id);
+                if group.done || group.shards[index].is_some() {
+                    return Ok(());
+                }
+                group.parity_len = shard.len();
+                group.shards[index] = Some(shard.to_vec());
+                group.received += 1;
+            }
+            FecType::PartialParity => {
+                if index < self.data_shards || shard.len() < FEC_SIZE_FIELD {
+                    return Err(KcpError::InvalidPacket);
+                }
+                let sent = u16::from_le_bytes([shard[0], shard[1]]) as usize;
+                if sent == 0 || sent >= self.data_shards {
+                    return Err(KcpError::InvalidPacket);
+                }
+                let parity = &shard[FEC_SIZE_FIELD..];
+                let data_shards = self.data_shards;
+                self.stats.parity_received += 1;
+                let group = self.group_mut(group_id);
+                if group.done || group.shards[index].is_some() {
+                    return Ok(());
+                }
+                // the padding shards are known, count them as received
+                for padding in &mut group.shards[sent..data_shards] {
+                    if padding.is_none() {
+                        *padding = Some(Vec::new());
+                        group.data_received += 1;
+                        group.received += 1;
+                    }
+                }
+                group.parity_len = parity.len();
+                group.shards[index] = Some(parity.to_vec());
+                group.received += 1;
+            }
+        }
+
+        self.try_recover(group_id, &mut emit);
+        Ok(())
+    }
+
+    /// Decode a packet and feed the datagrams it yields to `kcp`
+    ///
+    /// # Errors
+    /// Errors of `decode`, then the first error of `KcpControl::input`
+    pub fn input(&mut self, kcp: &mut KcpControl, packet: &[u8]) -> Result<(), KcpError> {
+        let mut result = Ok(());
+        self.decode(packet, |datagram| {
+            let input = kcp.input(datagram);
+            if result.is_ok() {
+                result = input;
+            }
+        })?;
+        result
+    }
+
+    fn try_recover<F: FnMut(&[u8])>(&mut self, group_id: u32, emit: &mut F) {
+        let data_shards = self.data_shards;
+        let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) else {
+            return;
+        };
+
+        if group.data_received == data_shards {
+            group.done = true;
+            group.shards.clear();
+            return;
+        }
+        if group.received < data_shards || group.parity_len == 0 {
+            return;
+        }
+
+        let missing: Vec<usize> = (0..data_shards)
+            .filter(|&i| group.shards[i].is_none())
+            .collect();
+
+        let mut shards = core::mem::take(&mut group.shards);
+        let mut valid = true;
+        for shard in shards.iter_mut().flatten() {
+            if shard.len() > group.parity_len {
+                valid = false;
+            }
+            shard.resize(group.parity_len, 0);
+        }
+        group.done = true;
+
+        if !valid || self.codec.reconstruct_data(&mut shards).is_err() {
+            self.stats.unrecoverable_groups += 1;
+            return;
+        }
+
+        let mut recovered = 0;
+        for i in missing {
+            if let Some(payload) = shards[i].as_deref().and_then(Self::shard_payload) {
+                emit(payload);
+                recovered += 1;
+            }
+        }
+        self.stats.recovered_datagrams += recovered;
+        self.stats.recovered_groups += 1;
+    }
+
+    /// Locate the group for `group_id`, creating it (and evicting the oldest
+    /// group beyond `rx_limit`) if needed.
+    fn group_mut(&mut self, group_id: u32) -> &mut FecGroup {
+        let pos = match self.groups.iter().position(|g| g.id == group_id) {
+            Some(pos) => pos,
+            None => {
+                if self.groups.len() >= self.rx_limit {
+                    if let Some(old) = self.groups.pop_front() {
+                        if !old.done {
+                            self.stats.unrecoverable_groups += 1;
+                        }
+                    }
+                }
+                self.groups.push_back(FecGroup {
+                    id: group_id,
+                    shards: vec![None; self.data_shards + self.parity_shards],
+                    data_received: 0,
+                    received: 0,
+                    parity_len: 0,
+                    done: false,
+                });
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[pos]
+    }
+
+    /// Extract the original datagram from a data shard (size field + payload)
+    fn shard_payload(shard: &[u8]) -> Option<&[u8]> {
+        if shard.len() < FEC_SIZE_FIELD {
+            return None;
+        }
+        let size = u16::from_le_bytes([shard[0], shard[1]]) as usize;
+        if size < FEC_SIZE_FIELD || size > shard.len() {
+            return None;
+        }
+        Some(&shard[FEC_SIZE_FIELD..size])
+    }
+}
+
+/// Output callback protecting every datagram with FEC before handing the
+/// packets to `inner`
+///
+/// The parity of a group left incomplete is sent once the KCP flush ends.
+/// The packets received from the peer go through `FecDecoder::input`.
+#[cfg(feature = "std")]
+pub struct FecOutput<C> {
+    encoder: Mutex<FecEncoder>,
+    inner: C,
+}
+
+#[cfg(feature = "std")]
+impl<C: KcpCallBack> FecOutput<C> {
+    pub fn new(encoder: FecEncoder, inner: C) -> Self {
+        Self {
+            encoder: Mutex::new(encoder),
+            inner,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C: KcpCallBack> KcpCallBack for FecOutput<C> {
+    fn output(&self, buf: &[u8], kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {
+        let encoded = self
+            .encoder
+            .lock()
+            .unwrap()
+            .encode(buf, |packet| self.inner.output(packet, kcp, user));
+        if encoded.is_err() {
+            self.inner
+                .writelog("fec: datagram dropped, larger than a shard", kcp, user);
+        }
+    }
+
+    fn writelog(&self, log: &str, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.writelog(log, kcp, user);
+    }
+
+    fn trace(&self, event: &TraceEvent, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.trace(event, kcp, user);
+    }
+
+    fn flushed(&self, kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {
+        self.encoder
+            .lock()
+            .unwrap()
+            .flush(|packet| self.inner.output(packet, kcp, user));
+        self.inner.flushed(kcp, user);
+    }
+}
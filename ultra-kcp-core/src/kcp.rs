@@ -1,15 +1,27 @@
-use std::any::Any;
+use alloc::boxed::Box;
+#[cfg(feature = "log")]
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::any::Any;
 
+use crate::checksum::{crc32c, ChecksumStats};
+use crate::congestion::{AckEvent, CongestionController, LossEvent, TimeoutEvent};
 use crate::constants::{
-    KcpError, KcpLogFlags, KcpProbeFlags, IKCP_DEADLINK, IKCP_FASTACK_LIMIT, IKCP_INTERVAL,
-    IKCP_MTU_DEF, IKCP_OVERHEAD, IKCP_RTO_DEF, IKCP_RTO_MIN, IKCP_THRESH_INIT, IKCP_WND_RCV,
-    IKCP_WND_SND,
+    Command, KcpError, KcpLogFlags, KcpProbeFlags, IKCP_CHECKSUM_SIZE, IKCP_DEADLINK,
+    IKCP_FASTACK_LIMIT, IKCP_INTERVAL, IKCP_MSG_MAX_DEF, IKCP_MTU_DEF, IKCP_OVERHEAD,
+    IKCP_PROBE_INIT, IKCP_PROBE_LIMIT, IKCP_RTO_DEF, IKCP_RTO_MAX, IKCP_RTO_MIN, IKCP_RTO_NDL,
+    IKCP_SACK_RANGE_SIZE, IKCP_WND_RCV, IKCP_WND_SND,
 };
+use crate::delayed_ack::{AckStats, DelayedAck};
+use crate::pacing::{Pacer, PacingRate};
+use crate::serial::{in_range, itimediff};
+use crate::trace::{CwndReason, SegmentTrace, TraceEvent};
 
+#[cfg(feature = "log")]
 macro_rules! ikcp_log {
     ($kcp: expr, $mask:expr, $($arg:tt)+) => {
         if $kcp.canlog($mask) {
-            let s = format!($($arg)*);
+            let s = alloc::format!($($arg)*);
             $kcp.__log(s);
         }
 
@@ -17,6 +29,15 @@ macro_rules! ikcp_log {
 
 }
 
+/// Logging compiled out, the arguments are only type checked
+#[cfg(not(feature = "log"))]
+macro_rules! ikcp_log {
+    ($kcp: expr, $mask:expr, $($arg:tt)+) => {
+        let _ = (&$kcp, $mask);
+        let _ = format_args!($($arg)*);
+    };
+}
+
 #[derive(Default)]
 pub struct KcpControl {
     /// conversation id
@@ -51,8 +72,6 @@ pub struct KcpControl {
     pub rcv_nxt: u32,
     pub ts_recent: u32,
     pub ts_lastack: u32,
-    /// slow start threshold
-    pub ssthresh: u32,
     pub rx_rttval: i32,
     pub rx_srtt: i32,
     /// Retransmission timeout (ms)
@@ -62,7 +81,6 @@ pub struct KcpControl {
     pub send_window: u32,
     pub recv_window: u32,
     pub rmt_wnd: u32,
-    pub cwnd: u32,
     pub probe: KcpProbeFlags,
     pub current: u32,
     /// Internal update interval in milliseconds.
@@ -86,7 +104,6 @@ pub struct KcpControl {
     /// Incremented when no valid packets are received, reset on successful communication.
     /// When reaches IKCP_DEADLINK (default 20), the connection is considered broken.
     pub dead_link: u32,
-    pub incr: u32,
     pub snd_queue: Vec<Segment>,
     pub rcv_queue: Vec<Segment>,
     pub snd_buf: Vec<Segment>,
@@ -98,19 +115,47 @@ pub struct KcpControl {
     /// Enable logging.
     write_log: bool,
     log_mask: KcpLogFlags,
+    /// Report structured events to `KcpCallBack::trace`, see `set_tracing`
+    tracing: bool,
     /// Fast ACK threshold for triggering fast retransmit.
     ///
     /// When receiving this number of duplicate ACKs, KCP will trigger fast retransmit
     /// without waiting for timeout. Default is 5 (IKCP_FASTACK_LIMIT).
     pub fastlimit: u32,
 
-    /// Disable congestion window control when non-zero.
+    /// Congestion control algorithm, `KcpClassic` by default.
     ///
-    /// When set to true, KCP will send data as fast as possible without
-    /// congestion control. Useful for latency-sensitive applications that
-    /// can tolerate packet loss. Default is `false` (congestion control enabled).
-    pub nocwnd: bool,
+    /// `NoCongestion` disables congestion window control for good.
+    congestion: Box<dyn CongestionController>,
+    /// Ignore the congestion window when filling the send buffer (ikcp
+    /// `nocwnd`), the controller keeps tracking it
+    nocwnd: bool,
+    /// Token bucket spreading data segments over time, disabled by default
+    pacer: Option<Pacer>,
     pub streaming_mode: bool,
+    /// Extended fragmentation mode, see `set_extended_frg`
+    extended_frg: bool,
+    /// Largest message accepted in extended fragmentation mode (bytes)
+    max_message: usize,
+    /// Message being reassembled in extended fragmentation mode
+    rcv_message: Vec<u8>,
+    /// `rcv_message` holds a complete message
+    rcv_message_ready: bool,
+    /// Dropping the remaining fragments of an oversized message
+    rcv_message_skip: bool,
+    /// Acknowledge with SACK segments, see `set_sack`
+    sack: bool,
+    /// Append a CRC32C trailer to every datagram and verify it on input,
+    /// see `set_checksum`
+    checksum: bool,
+    checksum_stats: ChecksumStats,
+    /// Delayed acknowledgement policy, ACKs are sent on every flush when `None`
+    delayed_ack: Option<DelayedAck>,
+    /// Time the oldest pending ACK was queued
+    ack_since: u32,
+    /// Pending ACKs must be sent on the next flush
+    ack_immediate: bool,
+    ack_stats: AckStats,
     pub callback: Option<Box<dyn KcpCallBack>>,
     user_data: Option<Box<dyn Any>>,
     buffer: Vec<u8>,
@@ -175,14 +220,23 @@ impl KcpControl {
         self.rx_rto = IKCP_RTO_DEF;
         self.rx_minrto = IKCP_RTO_MIN;
         self.interval = IKCP_INTERVAL;
-        self.ssthresh = IKCP_THRESH_INIT;
         self.fastlimit = IKCP_FASTACK_LIMIT;
         self.dead_link = IKCP_DEADLINK;
+        self.max_message = IKCP_MSG_MAX_DEF;
     }
 
     /// update mss by mtu
     const fn update_mss(&mut self) {
-        self.mss = self.mtu - IKCP_OVERHEAD;
+        self.mss = self.payload_mtu() - IKCP_OVERHEAD;
+    }
+
+    /// Bytes of a datagram available to segments, the MTU minus the checksum trailer
+    const fn payload_mtu(&self) -> u32 {
+        if self.checksum {
+            self.mtu - IKCP_CHECKSUM_SIZE
+        } else {
+            self.mtu
+        }
     }
 
     /// Set the callback handler for this KCP instance
@@ -215,62 +269,43 @@ impl KcpControl {
         mut data: Option<&mut [u8]>,
         is_peek: bool,
     ) -> Result<usize, KcpError> {
+        if self.reassembles() {
+            return self.receive_reassembled(data, is_peek);
+        }
+
         if self.rcv_queue.is_empty() {
             return Err(KcpError::QueueEmpty);
         }
 
-        let peeksize = self.peek_size()?;
+        let (count, total_len) = self.next_message()?;
         if let Some(buf) = &data {
-            if peeksize > buf.len() {
+            if total_len > buf.len() {
                 return Err(KcpError::BufferTooSmall);
             }
         }
 
-        let mut total_len = 0;
         let recover = self.rcv_queue.len() >= self.recv_window as usize;
 
         let mut copy_offset = 0;
 
         // merge fragments in receive queue
-        let mut i = 0;
-        while i < self.rcv_queue.len() {
-            let seg = &self.rcv_queue[i];
-
+        for seg in &self.rcv_queue[..count] {
             // Copy data if buffer provided
             if let Some(d) = data.as_mut() {
-                d[copy_offset..copy_offset + seg.len as usize]
-                    .copy_from_slice(&seg.data[..seg.len as usize]);
-                copy_offset += seg.len as usize;
+                let len = seg.data.len();
+                d[copy_offset..copy_offset + len].copy_from_slice(&seg.data);
+                copy_offset += len;
             }
 
-            total_len += seg.len as usize;
-            let is_last_fragment = seg.frg == 0;
-
             ikcp_log!(self, KcpLogFlags::DATA_RECV, "recv sn={}", seg.sn);
-
-            if !is_peek {
-                self.rcv_queue.remove(i);
-            } else {
-                i += 1;
-            }
-            if is_last_fragment {
-                break;
-            }
         }
 
-        assert_eq!(peeksize, total_len);
+        if !is_peek {
+            self.rcv_queue.drain(..count);
+        }
 
         // Move data from receive buffer to queue if space available
-        while !self.rcv_buf.is_empty() && self.rcv_queue.len() < self.recv_window as usize {
-            let seg = &self.rcv_buf[0];
-            if seg.sn == self.rcv_nxt {
-                let seg = self.rcv_buf.remove(0);
-                self.rcv_queue.push(seg);
-                self.rcv_nxt += 1;
-            } else {
-                break;
-            }
-        }
+        self.move_to_queue();
 
         // fast recover
         // Trigger window update if needed
@@ -283,6 +318,40 @@ impl KcpControl {
         Ok(total_len)
     }
 
+    /// `receive` in extended fragmentation mode, returning the message
+    /// reassembled in `rcv_message`
+    fn receive_reassembled(
+        &mut self,
+        data: Option<&mut [u8]>,
+        is_peek: bool,
+    ) -> Result<usize, KcpError> {
+        let total_len = self.reassembled_size()?;
+        if let Some(buf) = data {
+            if total_len > buf.len() {
+                return Err(KcpError::BufferTooSmall);
+            }
+            buf[..total_len].copy_from_slice(&self.rcv_message);
+        }
+        ikcp_log!(
+            self,
+            KcpLogFlags::DATA_RECV,
+            "recv message {} bytes",
+            total_len
+        );
+
+        if !is_peek {
+            let recover = self.rcv_queue.len() >= self.recv_window as usize;
+            self.rcv_message.clear();
+            self.rcv_message_ready = false;
+            self.move_to_queue();
+            if self.rcv_queue.len() < self.recv_window as usize && recover {
+                self.probe |= KcpProbeFlags::ASK_TELL;
+            }
+        }
+
+        Ok(total_len)
+    }
+
     /// Send data through KCP protocol
     ///
     /// # Arguments
@@ -291,6 +360,8 @@ impl KcpControl {
     /// # Returns
     /// - Ok(usize): Number of bytes successfully sent
     /// - Err(KcpError::WindowFull): When data exceeds receive window size
+    /// - Err(KcpError::MessageTooLarge): When data exceeds `max_message` in
+    ///   extended fragmentation mode
     ///
     /// # Note
     /// This method handles both streaming and packet mode, with flow control
@@ -324,7 +395,7 @@ impl KcpControl {
             }
 
             // Return early if all data was appended to existing segment
-            if data_ptr.len() == 0 {
+            if data_ptr.is_empty() {
                 return Ok(sent);
             }
         }
@@ -334,11 +405,17 @@ impl KcpControl {
             count = 1; // Single segment
         } else {
             // Round up division to get total segments needed
-            count = (data_ptr.len() + self.mss as usize - 1) / self.mss as usize;
+            count = data_ptr.len().div_ceil(self.mss as usize);
         }
 
-        // Check if total segments exceed receive window size
-        if count >= self.recv_window as usize {
+        if self.extended_frg && !self.streaming_mode {
+            // Extended fragmentation: any number of segments, bounded by size
+            if data_ptr.len() > self.max_message {
+                return Err(KcpError::MessageTooLarge);
+            }
+        } else if count >= self.recv_window as usize || count > u8::MAX as usize + 1 {
+            // Check if total segments exceed receive window size or the
+            // one byte fragment counter
             // In streaming mode, return partial success if some data was sent
             if self.streaming_mode && sent > 0 {
                 return Ok(sent);
@@ -366,7 +443,7 @@ impl KcpControl {
             let mut seg = Segment::new(size);
 
             // Copy data to segment if there's data remaining
-            if data_ptr.len() > 0 {
+            if !data_ptr.is_empty() {
                 seg.data[..size].copy_from_slice(&data_ptr[..size]);
             }
 
@@ -375,8 +452,9 @@ impl KcpControl {
 
             // Set fragment number (only in non-streaming mode)
             seg.frg = if !self.streaming_mode {
-                // Fragments are numbered in reverse order (last fragment is 0)
-                (count - i - 1) as u32
+                // Fragments are numbered in reverse order (last fragment is 0),
+                // the one byte wire field saturates in extended fragmentation mode
+                (count - i - 1).min(u8::MAX as usize) as u32
             } else {
                 // No fragmentation in streaming mode
                 0
@@ -393,7 +471,1126 @@ impl KcpControl {
         }
 
         // Return total bytes successfully queued for sending
-        return Ok(sent);
+        Ok(sent)
+    }
+
+    /// Input a datagram received from the lower level (e.g. UDP)
+    ///
+    /// # Arguments
+    /// * `data` - The raw datagram, possibly carrying several segments
+    ///
+    /// # Returns
+    /// - Ok(()): All segments in the datagram were processed
+    ///
+    /// # Errors
+    /// - `InvalidPacket`: Datagram is truncated or a segment is malformed
+    /// - `UnknownCommand`: Segment carries an unknown command
+    /// - `ConversationMismatch`: Segment belongs to another conversation
+    pub fn input(&mut self, mut data: &[u8]) -> Result<(), KcpError> {
+        let prev_una = self.snd_una;
+        let mut maxack = 0;
+        let mut latest_ts = 0;
+        let mut flag = false;
+        let mut acked = (0, 0);
+        let mut rtt = None;
+
+        ikcp_log!(self, KcpLogFlags::INPUT, "[RI] {} bytes", data.len());
+
+        if self.checksum {
+            data = self.verify_checksum(data)?;
+        }
+        if data.len() < IKCP_OVERHEAD as usize {
+            return Err(KcpError::InvalidPacket);
+        }
+
+        while data.len() >= IKCP_OVERHEAD as usize {
+            let mut seg = Segment::decode_header(data)?;
+            let len = seg.len as usize;
+            if seg.conv != self.conversation_id {
+                return Err(KcpError::ConversationMismatch);
+            }
+            data = &data[IKCP_OVERHEAD as usize..];
+            if data.len() < seg.len as usize {
+                return Err(KcpError::InvalidPacket);
+            }
+            let cmd = Command::try_from(seg.cmd).map_err(|_| KcpError::UnknownCommand)?;
+            self.trace(TraceEvent::SegmentReceived(SegmentTrace::new(&seg)));
+
+            self.rmt_wnd = seg.wnd;
+            let freed = self.parse_una(seg.una);
+            acked = (acked.0 + freed.0, acked.1 + freed.1);
+            self.shrink_buf();
+
+            match cmd {
+                Command::Ack => {
+                    if itimediff(self.current, seg.ts) >= 0 {
+                        let sample = itimediff(self.current, seg.ts);
+                        self.update_ack(sample);
+                        rtt = Some(sample as u32);
+                    }
+                    let freed = self.parse_ack(seg.sn);
+                    acked = (acked.0 + freed.0, acked.1 + freed.1);
+                    self.shrink_buf();
+                    if !flag {
+                        flag = true;
+                        maxack = seg.sn;
+                        latest_ts = seg.ts;
+                    } else if itimediff(seg.sn, maxack) > 0 {
+                        maxack = seg.sn;
+                        latest_ts = seg.ts;
+                    }
+                    ikcp_log!(
+                        self,
+                        KcpLogFlags::IN_ACK,
+                        "input ack: sn={} rtt={} rto={}",
+                        seg.sn,
+                        itimediff(self.current, seg.ts),
+                        self.rx_rto
+                    );
+                }
+                Command::Push => {
+                    ikcp_log!(
+                        self,
+                        KcpLogFlags::IN_DATA,
+                        "input psh: sn={} ts={}",
+                        seg.sn,
+                        seg.ts
+                    );
+                    if itimediff(seg.sn, self.rcv_nxt.wrapping_add(self.recv_window)) < 0 {
+                        if seg.sn != self.rcv_nxt {
+                            // gap or duplicate, the sender is waiting on us
+                            self.ack_immediate = true;
+                        }
+                        self.ack_push(seg.sn, seg.ts);
+                        if itimediff(seg.sn, self.rcv_nxt) >= 0 {
+                            seg.data.extend_from_slice(&data[..seg.len as usize]);
+                            self.parse_data(seg);
+                        }
+                    }
+                }
+                Command::Sack => {
+                    if !len.is_multiple_of(IKCP_SACK_RANGE_SIZE) {
+                        return Err(KcpError::InvalidPacket);
+                    }
+                    if itimediff(self.current, seg.ts) >= 0 {
+                        let sample = itimediff(self.current, seg.ts);
+                        self.update_ack(sample);
+                        rtt = Some(sample as u32);
+                    }
+                    for range in data[..len].chunks_exact(IKCP_SACK_RANGE_SIZE) {
+                        let start = u32::from_le_bytes(range[0..4].try_into().unwrap());
+                        let end = u32::from_le_bytes(range[4..8].try_into().unwrap());
+                        let freed = self.parse_sack(start, end);
+                        acked = (acked.0 + freed.0, acked.1 + freed.1);
+
+                        // the highest selectively acknowledged sn drives fast retransmit
+                        let sn = end.wrapping_sub(1);
+                        if !flag || itimediff(sn, maxack) > 0 {
+                            flag = true;
+                            maxack = sn;
+                            latest_ts = seg.ts;
+                        }
+                    }
+                    self.shrink_buf();
+                    ikcp_log!(
+                        self,
+                        KcpLogFlags::IN_ACK,
+                        "input sack: una={} ranges={} rto={}",
+                        seg.una,
+                        len / IKCP_SACK_RANGE_SIZE,
+                        self.rx_rto
+                    );
+                }
+                Command::Wask => {
+                    // ready to send back IKCP_CMD_WINS in flush
+                    // tell remote my window size
+                    self.probe |= KcpProbeFlags::ASK_TELL;
+                    ikcp_log!(self, KcpLogFlags::IN_PROBE, "input probe");
+                }
+                Command::Wins => {
+                    // do nothing
+                    ikcp_log!(self, KcpLogFlags::IN_WINS, "input wins: {}", seg.wnd);
+                }
+            }
+
+            data = &data[len..];
+        }
+
+        if flag {
+            self.parse_fastack(maxack, latest_ts);
+        }
+
+        if acked.0 > 0 {
+            let event = AckEvent {
+                current: self.current,
+                una_advanced: itimediff(self.snd_una, prev_una) > 0,
+                acked_segments: acked.0,
+                acked_bytes: acked.1,
+                rtt,
+                srtt: self.rx_srtt as u32,
+                inflight: self.snd_nxt.wrapping_sub(self.snd_una),
+                mss: self.mss,
+                rmt_wnd: self.rmt_wnd,
+            };
+            let cwnd = self.congestion.window();
+            self.congestion.on_ack(&event);
+            self.trace_cwnd(cwnd, CwndReason::Ack);
+        }
+
+        Ok(())
+    }
+
+    /// Update the RTT estimator and the retransmission timeout
+    fn update_ack(&mut self, rtt: i32) {
+        // bound the sample so a bogus echoed timestamp cannot overflow the estimator
+        let rtt = rtt.min(IKCP_RTO_MAX as i32);
+        if self.rx_srtt == 0 {
+            self.rx_srtt = rtt;
+            self.rx_rttval = rtt / 2;
+        } else {
+            let delta = (rtt - self.rx_srtt).abs();
+            self.rx_rttval = (3 * self.rx_rttval + delta) / 4;
+            self.rx_srtt = (7 * self.rx_srtt + rtt) / 8;
+            if self.rx_srtt < 1 {
+                self.rx_srtt = 1;
+            }
+        }
+        let rto = self.rx_srtt as u32 + self.interval.max(4 * self.rx_rttval as u32);
+        self.rx_rto = rto.clamp(self.rx_minrto, IKCP_RTO_MAX);
+        self.trace(TraceEvent::RttUpdated {
+            sample: rtt,
+            srtt: self.rx_srtt,
+            rttval: self.rx_rttval,
+            rto: self.rx_rto,
+        });
+    }
+
+    fn shrink_buf(&mut self) {
+        self.snd_una = match self.snd_buf.first() {
+            Some(seg) => seg.sn,
+            None => self.snd_nxt,
+        };
+    }
+
+    /// Remove the acknowledged segment from the send buffer
+    ///
+    /// # Returns
+    /// Number of segments and payload bytes removed
+    fn parse_ack(&mut self, sn: u32) -> (u32, u32) {
+        if !in_range(sn, self.snd_una, self.snd_nxt) {
+            return (0, 0);
+        }
+        for i in 0..self.snd_buf.len() {
+            let seg_sn = self.snd_buf[i].sn;
+            if sn == seg_sn {
+                let seg = self.snd_buf.remove(i);
+                return (1, seg.len);
+            }
+            if itimediff(sn, seg_sn) < 0 {
+                break;
+            }
+        }
+        (0, 0)
+    }
+
+    /// Remove every segment below `una` from the send buffer
+    ///
+    /// # Returns
+    /// Number of segments and payload bytes removed
+    fn parse_una(&mut self, una: u32) -> (u32, u32) {
+        let count = self
+            .snd_buf
+            .iter()
+            .take_while(|seg| itimediff(una, seg.sn) > 0)
+            .count();
+        let bytes = self.snd_buf.drain(..count).map(|seg| seg.len).sum();
+        (count as u32, bytes)
+    }
+
+    /// Remove the segments within the selectively acknowledged range
+    /// `[start, end)` from the send buffer
+    ///
+    /// # Returns
+    /// Number of segments and payload bytes removed
+    fn parse_sack(&mut self, start: u32, end: u32) -> (u32, u32) {
+        let mut freed = (0, 0);
+        self.snd_buf.retain(|seg| {
+            let acked = in_range(seg.sn, start, end);
+            if acked {
+                freed = (freed.0 + 1, freed.1 + seg.len);
+            }
+            !acked
+        });
+        freed
+    }
+
+    fn parse_fastack(&mut self, sn: u32, _ts: u32) {
+        if !in_range(sn, self.snd_una, self.snd_nxt) {
+            return;
+        }
+        for seg in self.snd_buf.iter_mut() {
+            if itimediff(sn, seg.sn) < 0 {
+                break;
+            } else if sn != seg.sn {
+                seg.fastack += 1;
+            }
+        }
+    }
+
+    /// Queue an ACK for `sn`, sent back on the next flush
+    fn ack_push(&mut self, sn: u32, ts: u32) {
+        let newsize = self.ackcount + 1;
+        if newsize > self.ackblock {
+            let mut newblock = 8;
+            while newblock < newsize {
+                newblock <<= 1;
+            }
+            self.acklist
+                .reserve((newblock * 2) as usize - self.acklist.len());
+            self.ackblock = newblock;
+        }
+        if self.ackcount == 0 {
+            self.ack_since = self.current;
+        }
+        self.acklist.push(sn);
+        self.acklist.push(ts);
+        self.ackcount += 1;
+        self.ack_stats.acks_requested += 1;
+    }
+
+    fn ack_get(&self, p: usize) -> (u32, u32) {
+        (self.acklist[p * 2], self.acklist[p * 2 + 1])
+    }
+
+    /// Whether the pending ACKs must go out on this flush
+    fn ack_due(&self) -> bool {
+        if self.ackcount == 0 {
+            return false;
+        }
+        let Some(policy) = self.delayed_ack else {
+            return true;
+        };
+        self.ack_immediate
+            || self.ackcount >= policy.every
+            || itimediff(self.current, self.ack_since) >= policy.max_delay as i32
+    }
+
+    /// Time at which pending ACKs held back by the delayed ACK policy reach
+    /// their maximum delay
+    fn ack_deadline(&self) -> Option<u32> {
+        let policy = self.delayed_ack?;
+        (self.ackcount > 0).then(|| self.ack_since.wrapping_add(policy.max_delay))
+    }
+
+    /// Shrink the pending ACKs to the ones carrying information
+    ///
+    /// Segments below `rcv_nxt` are covered by the `una` field of any ACK,
+    /// so only the highest of them is kept to echo a timestamp. Duplicate
+    /// ACKs for the same sn keep the most recent timestamp.
+    fn coalesce_acks(&mut self) {
+        let mut kept: Vec<(u32, u32)> = Vec::with_capacity(self.ackcount as usize);
+        let mut in_order: Option<(u32, u32)> = None;
+        for i in 0..self.ackcount as usize {
+            let (sn, ts) = self.ack_get(i);
+            if itimediff(sn, self.rcv_nxt) < 0 {
+                if in_order.is_none_or(|(max, _)| itimediff(sn, max) >= 0) {
+                    in_order = Some((sn, ts));
+                }
+            } else if let Some(dup) = kept.iter_mut().find(|(kept_sn, _)| *kept_sn == sn) {
+                dup.1 = ts;
+            } else {
+                kept.push((sn, ts));
+            }
+        }
+        kept.extend(in_order);
+
+        self.ack_stats.acks_saved += (self.ackcount as usize - kept.len()) as u64;
+        self.acklist.clear();
+        for (sn, ts) in &kept {
+            self.acklist.push(*sn);
+            self.acklist.push(*ts);
+        }
+        self.ackcount = kept.len() as u32;
+    }
+
+    /// Insert a received data segment into the receive buffer and move
+    /// contiguous segments to the receive queue
+    fn parse_data(&mut self, newseg: Segment) {
+        let sn = newseg.sn;
+        if !in_range(
+            sn,
+            self.rcv_nxt,
+            self.rcv_nxt.wrapping_add(self.recv_window),
+        ) {
+            return;
+        }
+
+        let mut repeat = false;
+        let mut insert_at = 0;
+        for i in (0..self.rcv_buf.len()).rev() {
+            let seg = &self.rcv_buf[i];
+            if seg.sn == sn {
+                repeat = true;
+                break;
+            }
+            if itimediff(sn, seg.sn) > 0 {
+                insert_at = i + 1;
+                break;
+            }
+        }
+
+        if !repeat {
+            self.rcv_buf.insert(insert_at, newseg);
+        }
+
+        self.move_to_queue();
+    }
+
+    /// Move contiguous segments from `rcv_buf` to `rcv_queue` while the
+    /// receive window has room
+    ///
+    /// # Note
+    /// In extended fragmentation mode the queue is drained into the message
+    /// being reassembled as it fills, so a message is not limited by the
+    /// receive window.
+    fn move_to_queue(&mut self) {
+        loop {
+            while !self.rcv_buf.is_empty() && self.rcv_queue.len() < self.recv_window as usize {
+                if self.rcv_buf[0].sn == self.rcv_nxt {
+                    let seg = self.rcv_buf.remove(0);
+                    self.rcv_queue.push(seg);
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                } else {
+                    break;
+                }
+            }
+
+            let queued = self.rcv_queue.len();
+            self.reassemble();
+            if self.rcv_queue.len() == queued {
+                break;
+            }
+        }
+    }
+
+    /// Extended fragmentation mode is active for received data
+    const fn reassembles(&self) -> bool {
+        self.extended_frg && !self.streaming_mode
+    }
+
+    /// Append queued fragments to `rcv_message` until it holds a complete message
+    ///
+    /// # Note
+    /// Messages growing beyond `max_message` are dropped up to their last
+    /// fragment, so that a peer cannot make the receiver buffer unbounded data.
+    fn reassemble(&mut self) {
+        if !self.reassembles() {
+            return;
+        }
+        let mut taken = 0;
+        for seg in &self.rcv_queue {
+            if self.rcv_message_ready {
+                break;
+            }
+            taken += 1;
+
+            if !self.rcv_message_skip {
+                if self.rcv_message.len() + seg.data.len() > self.max_message {
+                    self.rcv_message = Vec::new();
+                    self.rcv_message_skip = true;
+                } else {
+                    self.rcv_message.extend_from_slice(&seg.data);
+                }
+            }
+            if seg.frg == 0 {
+                if self.rcv_message_skip {
+                    self.rcv_message_skip = false;
+                    ikcp_log!(
+                        self,
+                        KcpLogFlags::DATA_RECV,
+                        "drop oversized message sn={}",
+                        seg.sn
+                    );
+                } else {
+                    self.rcv_message_ready = true;
+                }
+            }
+        }
+        self.rcv_queue.drain(..taken);
+    }
+
+    /// Size of the reassembled message in extended fragmentation mode
+    fn reassembled_size(&self) -> Result<usize, KcpError> {
+        if self.rcv_message_ready {
+            Ok(self.rcv_message.len())
+        } else if self.rcv_message.is_empty() && self.rcv_queue.is_empty() {
+            Err(KcpError::QueueEmpty)
+        } else {
+            Err(KcpError::IncompleteMessage)
+        }
+    }
+
+    fn wnd_unused(&self) -> u32 {
+        if self.rcv_queue.len() < self.recv_window as usize {
+            self.recv_window - self.rcv_queue.len() as u32
+        } else {
+            0
+        }
+    }
+
+    /// Check and strip the checksum trailer of an incoming datagram
+    ///
+    /// # Errors
+    /// - `ChecksumMismatch`: Trailer is missing or does not match the datagram
+    fn verify_checksum<'a>(&mut self, data: &'a [u8]) -> Result<&'a [u8], KcpError> {
+        let valid = data
+            .len()
+            .checked_sub(IKCP_CHECKSUM_SIZE as usize)
+            .map(|len| data.split_at(len))
+            .filter(|(datagram, trailer)| crc32c(datagram).to_le_bytes() == **trailer);
+        match valid {
+            Some((datagram, _)) => {
+                self.checksum_stats.verified += 1;
+                Ok(datagram)
+            }
+            None => {
+                self.checksum_stats.mismatches += 1;
+                ikcp_log!(self, KcpLogFlags::INPUT, "drop datagram, checksum mismatch");
+                Err(KcpError::ChecksumMismatch)
+            }
+        }
+    }
+
+    /// Hand `len` bytes of the internal buffer to the output callback
+    fn output(&mut self, mut len: usize) {
+        if len == 0 {
+            return;
+        }
+        if self.checksum {
+            let crc = crc32c(&self.buffer[..len]);
+            self.buffer[len..len + IKCP_CHECKSUM_SIZE as usize].copy_from_slice(&crc.to_le_bytes());
+            len += IKCP_CHECKSUM_SIZE as usize;
+            self.checksum_stats.sent += 1;
+        }
+        ikcp_log!(self, KcpLogFlags::OUTPUT, "[RO] {} bytes", len);
+        let Some(callback) = self.callback.take() else {
+            return;
+        };
+        let buffer = core::mem::take(&mut self.buffer);
+        let user_data = self.user_data.take();
+        callback.output(&buffer[..len], self, user_data.as_ref());
+        self.buffer = buffer;
+        if self.user_data.is_none() {
+            self.user_data = user_data;
+        }
+        if self.callback.is_none() {
+            self.callback = Some(callback);
+        }
+    }
+
+    /// Tell the output callback that a flush ended
+    fn flushed(&mut self) {
+        let Some(callback) = self.callback.take() else {
+            return;
+        };
+        let user_data = self.user_data.take();
+        callback.flushed(self, user_data.as_ref());
+        if self.user_data.is_none() {
+            self.user_data = user_data;
+        }
+        if self.callback.is_none() {
+            self.callback = Some(callback);
+        }
+    }
+
+    /// Flush pending ACKs, window probes and data segments
+    ///
+    /// # Note
+    /// Does nothing until `update` has been called at least once.
+    pub fn flush(&mut self) {
+        if self.updated == 0 {
+            return;
+        }
+
+        let mtu = self.payload_mtu() as usize;
+        let overhead = IKCP_OVERHEAD as usize;
+        let current = self.current;
+        let mut offset = 0;
+        let mut change = 0;
+        let mut lost = false;
+
+        let mut seg = Segment {
+            conv: self.conversation_id,
+            cmd: Command::Ack.into(),
+            wnd: self.wnd_unused(),
+            una: self.rcv_nxt,
+            ..Default::default()
+        };
+
+        // flush acknowledges
+        if self.ack_due() {
+            if self.delayed_ack.is_some_and(|policy| policy.coalesce) {
+                self.coalesce_acks();
+            }
+            if self.sack {
+                offset = self.flush_sack(&mut seg, offset);
+            } else {
+                for i in 0..self.ackcount as usize {
+                    if offset + overhead > mtu {
+                        self.output(offset);
+                        offset = 0;
+                    }
+                    (seg.sn, seg.ts) = self.ack_get(i);
+                    offset += seg.encode_header(&mut self.buffer[offset..]);
+                    self.trace(TraceEvent::SegmentSent(SegmentTrace::new(&seg)));
+                }
+                self.ack_stats.acks_sent += self.ackcount as u64;
+            }
+            self.ackcount = 0;
+            self.acklist.clear();
+            self.ack_immediate = false;
+        }
+
+        // probe window size (if remote window size equals zero)
+        if self.rmt_wnd == 0 {
+            if self.probe_wait == 0 {
+                self.probe_wait = IKCP_PROBE_INIT;
+                self.ts_probe = current.wrapping_add(self.probe_wait);
+            } else if itimediff(current, self.ts_probe) >= 0 {
+                if self.probe_wait < IKCP_PROBE_INIT {
+                    self.probe_wait = IKCP_PROBE_INIT;
+                }
+                self.probe_wait += self.probe_wait / 2;
+                if self.probe_wait > IKCP_PROBE_LIMIT {
+                    self.probe_wait = IKCP_PROBE_LIMIT;
+                }
+                self.ts_probe = current.wrapping_add(self.probe_wait);
+                self.probe |= KcpProbeFlags::ASK_SEND;
+            }
+        } else {
+            self.ts_probe = 0;
+            self.probe_wait = 0;
+        }
+
+        // flush window probing commands
+        for (flag, cmd) in [
+            (KcpProbeFlags::ASK_SEND, Command::Wask),
+            (KcpProbeFlags::ASK_TELL, Command::Wins),
+        ] {
+            if self.probe.contains(flag) {
+                seg.cmd = cmd.into();
+                if offset + overhead > mtu {
+                    self.output(offset);
+                    offset = 0;
+                }
+                offset += seg.encode_header(&mut self.buffer[offset..]);
+                self.trace(TraceEvent::SegmentSent(SegmentTrace::new(&seg)));
+            }
+        }
+        self.probe = KcpProbeFlags::NONE;
+
+        // calculate window size
+        let mut cwnd = self.send_window.min(self.rmt_wnd);
+        if !self.nocwnd {
+            cwnd = cwnd.min(self.congestion.window());
+        }
+
+        // move data from snd_queue to snd_buf
+        while itimediff(self.snd_nxt, self.snd_una.wrapping_add(cwnd)) < 0
+            && !self.snd_queue.is_empty()
+        {
+            let mut newseg = self.snd_queue.remove(0);
+            newseg.conv = self.conversation_id;
+            newseg.cmd = Command::Push.into();
+            newseg.wnd = seg.wnd;
+            newseg.ts = current;
+            newseg.sn = self.snd_nxt;
+            newseg.una = self.rcv_nxt;
+            newseg.resendts = current;
+            newseg.rto = self.rx_rto;
+            newseg.fastack = 0;
+            newseg.xmit = 0;
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.snd_buf.push(newseg);
+        }
+
+        // calculate resent
+        let resent = if self.fastresend > 0 {
+            self.fastresend as u32
+        } else {
+            u32::MAX
+        };
+        let rtomin = if self.nodelay == 0 {
+            self.rx_rto >> 3
+        } else {
+            0
+        };
+
+        // refill the pacing bucket
+        if let Some(pacer) = self.pacer.as_mut() {
+            let rate =
+                pacer.resolve_rate(self.congestion.pacing_rate(), cwnd, self.mss, self.rx_srtt);
+            pacer.refill(current, rate, self.mtu);
+        }
+
+        // flush data segments
+        for i in 0..self.snd_buf.len() {
+            let segment = &self.snd_buf[i];
+            let due = segment.xmit == 0
+                || itimediff(current, segment.resendts) >= 0
+                || (segment.fastack >= resent
+                    && (segment.xmit <= self.fastlimit || self.fastlimit == 0));
+            if due {
+                let need = IKCP_OVERHEAD + segment.len;
+                if let Some(pacer) = self.pacer.as_mut() {
+                    if !pacer.consume(current, need) {
+                        // out of tokens, the remaining segments wait for the release time
+                        break;
+                    }
+                }
+            }
+
+            let segment = &mut self.snd_buf[i];
+            let mut needsend = false;
+            if segment.xmit == 0 {
+                needsend = true;
+                segment.xmit += 1;
+                segment.rto = self.rx_rto;
+                segment.resendts = current.wrapping_add(segment.rto + rtomin);
+            } else if itimediff(current, segment.resendts) >= 0 {
+                needsend = true;
+                segment.xmit += 1;
+                self.xmit += 1;
+                if self.nodelay == 0 {
+                    segment.rto = segment.rto.saturating_add(segment.rto.max(self.rx_rto));
+                } else {
+                    let step = if self.nodelay < 2 {
+                        segment.rto
+                    } else {
+                        self.rx_rto
+                    };
+                    segment.rto = segment.rto.saturating_add(step / 2);
+                }
+                // keep the deadline within the comparable half of the u32 range
+                segment.resendts = current.wrapping_add(segment.rto.min(i32::MAX as u32));
+                lost = true;
+            } else if segment.fastack >= resent
+                && (segment.xmit <= self.fastlimit || self.fastlimit == 0)
+            {
+                needsend = true;
+                segment.xmit += 1;
+                segment.fastack = 0;
+                segment.resendts = current.wrapping_add(segment.rto);
+                change += 1;
+            }
+
+            if needsend {
+                segment.ts = current;
+                segment.wnd = seg.wnd;
+                segment.una = self.rcv_nxt;
+
+                let need = overhead + segment.len as usize;
+                if offset + need > mtu {
+                    self.output(offset);
+                    offset = 0;
+                }
+
+                let segment = &self.snd_buf[i];
+                offset += segment.encode_header(&mut self.buffer[offset..]);
+                let len = segment.len as usize;
+                self.buffer[offset..offset + len].copy_from_slice(&segment.data[..len]);
+                offset += len;
+                self.trace(TraceEvent::SegmentSent(SegmentTrace::new(segment)));
+
+                if segment.xmit >= self.dead_link {
+                    // dead link
+                    self.set_state(u32::MAX);
+                }
+            }
+        }
+
+        // flush remaining segments
+        self.output(offset);
+
+        // update congestion window
+        if change > 0 {
+            let event = LossEvent {
+                current,
+                inflight: self.snd_nxt.wrapping_sub(self.snd_una),
+                resent,
+                mss: self.mss,
+            };
+            let cwnd = self.congestion.window();
+            self.congestion.on_loss(&event);
+            self.trace_cwnd(cwnd, CwndReason::Loss);
+        }
+        if lost {
+            let event = TimeoutEvent {
+                current,
+                window: cwnd,
+                mss: self.mss,
+            };
+            let cwnd = self.congestion.window();
+            self.congestion.on_timeout(&event);
+            self.trace_cwnd(cwnd, CwndReason::Timeout);
+        }
+
+        self.flushed();
+    }
+
+    /// Write a single SACK segment acknowledging everything below `rcv_nxt`
+    /// and the out of order ranges held in `rcv_buf`
+    ///
+    /// # Returns
+    /// The new offset in the output buffer
+    fn flush_sack(&mut self, seg: &mut Segment, mut offset: usize) -> usize {
+        let max_ranges = (self.payload_mtu() - IKCP_OVERHEAD) as usize / IKCP_SACK_RANGE_SIZE;
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for received in &self.rcv_buf {
+            match ranges.last_mut() {
+                Some((_, end)) if *end == received.sn => *end = end.wrapping_add(1),
+                _ => {
+                    if ranges.len() == max_ranges {
+                        break;
+                    }
+                    ranges.push((received.sn, received.sn.wrapping_add(1)));
+                }
+            }
+        }
+
+        self.ack_stats.acks_sent += 1;
+        self.ack_stats.acks_saved += self.ackcount as u64 - 1;
+
+        // echo the most recent timestamp for RTT estimation
+        (seg.sn, seg.ts) = self.ack_get(self.ackcount as usize - 1);
+        seg.cmd = Command::Sack.into();
+        seg.len = (ranges.len() * IKCP_SACK_RANGE_SIZE) as u32;
+
+        if offset + IKCP_OVERHEAD as usize + seg.len as usize > self.payload_mtu() as usize {
+            self.output(offset);
+            offset = 0;
+        }
+        offset += seg.encode_header(&mut self.buffer[offset..]);
+        for (start, end) in ranges {
+            self.buffer[offset..offset + 4].copy_from_slice(&start.to_le_bytes());
+            self.buffer[offset + 4..offset + 8].copy_from_slice(&end.to_le_bytes());
+            offset += IKCP_SACK_RANGE_SIZE;
+        }
+        self.trace(TraceEvent::SegmentSent(SegmentTrace::new(seg)));
+
+        ikcp_log!(
+            self,
+            KcpLogFlags::OUT_ACK,
+            "output sack: una={} ranges={}",
+            seg.una,
+            seg.len as usize / IKCP_SACK_RANGE_SIZE
+        );
+        seg.len = 0;
+        offset
+    }
+
+    /// Update state (call it repeatedly, every 10ms-100ms)
+    ///
+    /// # Arguments
+    /// * `current` - Current timestamp in milliseconds
+    ///
+    /// # Note
+    /// Alternatively call `check` to learn when `update` needs to be
+    /// invoked next.
+    pub fn update(&mut self, current: u32) {
+        self.current = current;
+
+        if self.updated == 0 {
+            self.updated = 1;
+            self.ts_flush = current;
+        }
+
+        let mut slap = itimediff(current, self.ts_flush);
+        if !(-10000..10000).contains(&slap) {
+            self.ts_flush = current;
+            slap = 0;
+        }
+
+        if slap >= 0 {
+            self.ts_flush = self.ts_flush.wrapping_add(self.interval);
+            if itimediff(current, self.ts_flush) >= 0 {
+                self.ts_flush = current.wrapping_add(self.interval);
+            }
+            self.flush();
+        } else {
+            // segments held back by the pacer and delayed ACKs may be due
+            // before the next interval
+            let release = self.pacer.as_ref().and_then(Pacer::release);
+            if [release, self.ack_deadline()]
+                .into_iter()
+                .flatten()
+                .any(|due| itimediff(current, due) >= 0)
+            {
+                self.flush();
+            }
+        }
+    }
+
+    /// Determine when `update` should be invoked next
+    ///
+    /// # Arguments
+    /// * `current` - Current timestamp in milliseconds
+    ///
+    /// # Returns
+    /// Timestamp at which `update` should be called, if there is no `input`
+    /// or `send` calling in between. Useful to schedule many connections
+    /// without calling `update` on each of them every interval.
+    pub fn check(&self, current: u32) -> u32 {
+        if self.updated == 0 {
+            return current;
+        }
+
+        let mut ts_flush = self.ts_flush;
+        if !(-10000..10000).contains(&itimediff(current, ts_flush)) {
+            ts_flush = current;
+        }
+        if itimediff(current, ts_flush) >= 0 {
+            return current;
+        }
+
+        let mut tm_flush = itimediff(ts_flush, current);
+
+        // delayed ACKs go out once they have waited long enough
+        if let Some(deadline) = self.ack_deadline() {
+            let tm_ack = itimediff(deadline, current);
+            if tm_ack <= 0 {
+                return current;
+            }
+            tm_flush = tm_flush.min(tm_ack);
+        }
+
+        // while the pacer holds segments back, nothing is sent before its release time
+        if let Some(release) = self.pacer.as_ref().and_then(Pacer::release) {
+            let tm_release = itimediff(release, current);
+            if tm_release <= 0 {
+                return current;
+            }
+            let minimal = (tm_release.min(tm_flush) as u32).min(self.interval);
+            return current.wrapping_add(minimal);
+        }
+
+        let mut tm_packet = i32::MAX;
+        for seg in &self.snd_buf {
+            let diff = itimediff(seg.resendts, current);
+            if diff <= 0 {
+                return current;
+            }
+            tm_packet = tm_packet.min(diff);
+        }
+
+        let minimal = (tm_packet.min(tm_flush) as u32).min(self.interval);
+        current.wrapping_add(minimal)
+    }
+
+    /// Change the maximum transmission unit
+    ///
+    /// # Errors
+    /// - `InvalidConfig`: MTU is smaller than 50 bytes or the protocol overhead
+    pub fn set_mtu(&mut self, mtu: u32) -> Result<(), KcpError> {
+        if mtu < 50 || mtu < IKCP_OVERHEAD {
+            return Err(KcpError::InvalidConfig);
+        }
+        self.mtu = mtu;
+        self.update_mss();
+        // never shrink, segments queued under the previous mss must still fit
+        let size = (mtu as usize + IKCP_OVERHEAD as usize) * 3;
+        if self.buffer.len() < size {
+            self.buffer.resize(size, 0);
+        }
+        Ok(())
+    }
+
+    /// Set the internal update interval, clamped to 10..=5000 ms
+    pub fn set_interval(&mut self, interval: u32) {
+        self.interval = interval.clamp(10, 5000);
+    }
+
+    /// Configure the nodelay mode
+    ///
+    /// # Arguments
+    /// * `nodelay` - 0: disable (default), 1: enable, 2: enable with a less aggressive RTO backoff
+    /// * `interval` - Internal update interval in milliseconds
+    /// * `resend` - Fast resend threshold, 0 disables fast resend
+    /// * `nocwnd` - Ignore the congestion window, the congestion controller
+    ///   is left in place
+    ///
+    /// Fastest: `set_nodelay(1, 20, 2, true)`
+    pub fn set_nodelay(&mut self, nodelay: u32, interval: u32, resend: i32, nocwnd: bool) {
+        self.nodelay = nodelay;
+        self.rx_minrto = if nodelay != 0 {
+            IKCP_RTO_NDL
+        } else {
+            IKCP_RTO_MIN
+        };
+        self.set_interval(interval);
+        self.fastresend = resend.max(0);
+        self.nocwnd = nocwnd;
+    }
+
+    /// Set the maximum send and receive window sizes (in segments)
+    ///
+    /// Zero leaves the corresponding window unchanged. The receive window
+    /// cannot be smaller than `IKCP_WND_RCV`, the maximum fragment count.
+    pub fn set_wndsize(&mut self, sndwnd: u32, rcvwnd: u32) {
+        if sndwnd > 0 {
+            self.send_window = sndwnd;
+        }
+        if rcvwnd > 0 {
+            self.recv_window = rcvwnd.max(IKCP_WND_RCV);
+        }
+    }
+
+    /// Enable or disable the CRC32C datagram trailer
+    ///
+    /// Every datagram emitted by `flush` then ends with the CRC32C of its
+    /// content, and `input` drops datagrams whose trailer does not match
+    /// before they reach the receive buffer. The trailer is taken from the
+    /// MTU, so the mss shrinks by `IKCP_CHECKSUM_SIZE`.
+    ///
+    /// # Note
+    /// Both sides must use the same setting, chosen before any data is
+    /// exchanged. Leave it disabled to talk to plain ikcp peers.
+    pub fn set_checksum(&mut self, enable: bool) {
+        self.checksum = enable;
+        self.update_mss();
+    }
+
+    pub const fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    pub const fn checksum_stats(&self) -> &ChecksumStats {
+        &self.checksum_stats
+    }
+
+    /// Enable or disable selective acknowledgements
+    ///
+    /// Received segments are then acknowledged with a single SACK segment
+    /// carrying the received ranges, instead of one ACK segment each. SACK
+    /// segments from the peer are accepted either way.
+    ///
+    /// # Note
+    /// Both sides must enable it, plain ikcp peers reject the SACK command.
+    pub const fn set_sack(&mut self, enable: bool) {
+        self.sack = enable;
+    }
+
+    pub const fn sack(&self) -> bool {
+        self.sack
+    }
+
+    /// Enable or disable extended fragmentation mode
+    ///
+    /// Messages may then span any number of segments, up to `max_message`
+    /// bytes, instead of being limited by the receive window and the one byte
+    /// fragment counter. Fragments still count down to 0 on the wire, saturating
+    /// at 255, and the receiver reassembles them outside `rcv_queue` so that the
+    /// receive window keeps sliding while a large message arrives.
+    ///
+    /// # Note
+    /// Both sides must use the same mode, set before any message is exchanged.
+    /// Streaming mode takes precedence, segments are not fragmented there.
+    pub fn set_extended_frg(&mut self, enable: bool) {
+        self.extended_frg = enable;
+        self.move_to_queue();
+    }
+
+    pub const fn extended_frg(&self) -> bool {
+        self.extended_frg
+    }
+
+    /// Set the largest message accepted in extended fragmentation mode
+    ///
+    /// # Arguments
+    /// * `size` - Limit in bytes, `IKCP_MSG_MAX_DEF` by default
+    ///
+    /// # Note
+    /// `send` rejects larger messages with `MessageTooLarge`, and the receiver
+    /// drops incoming messages exceeding the limit instead of buffering them.
+    pub fn set_max_message(&mut self, size: usize) {
+        self.max_message = size;
+    }
+
+    pub const fn max_message(&self) -> usize {
+        self.max_message
+    }
+
+    /// Number of segments waiting to be sent or acknowledged
+    pub fn wait_snd(&self) -> usize {
+        self.snd_buf.len() + self.snd_queue.len()
+    }
+
+    /// Select the delayed acknowledgement policy
+    ///
+    /// # Arguments
+    /// * `policy` - `None` acknowledges every received segment on the next flush
+    pub fn set_delayed_ack(&mut self, policy: Option<DelayedAck>) {
+        self.delayed_ack = policy;
+    }
+
+    pub const fn delayed_ack(&self) -> Option<DelayedAck> {
+        self.delayed_ack
+    }
+
+    pub const fn ack_stats(&self) -> &AckStats {
+        &self.ack_stats
+    }
+
+    /// Enable or disable pacing of data segments
+    ///
+    /// # Arguments
+    /// * `rate` - Source of the pacing rate, `None` disables pacing
+    ///
+    /// # Note
+    /// Paced segments are released between update intervals, so the caller
+    /// should schedule `update` with `check` rather than a fixed interval.
+    pub fn set_pacing(&mut self, rate: Option<PacingRate>) {
+        self.pacer = rate.map(Pacer::new);
+    }
+
+    pub fn pacer(&self) -> Option<&Pacer> {
+        self.pacer.as_ref()
+    }
+
+    /// Replace the congestion control algorithm
+    pub fn set_congestion(&mut self, congestion: Box<dyn CongestionController>) {
+        self.congestion = congestion;
+    }
+
+    pub fn congestion(&self) -> &dyn CongestionController {
+        self.congestion.as_ref()
+    }
+
+    /// Current congestion window in segments
+    pub fn cwnd(&self) -> u32 {
+        self.congestion.window()
+    }
+
+    /// Slow start threshold in segments, see `CongestionController::ssthresh`
+    pub fn ssthresh(&self) -> u32 {
+        self.congestion.ssthresh()
+    }
+
+    /// Bytes acknowledged towards the next window increment, see
+    /// `CongestionController::incr`
+    pub fn incr(&self) -> u32 {
+        self.congestion.incr()
+    }
+
+    /// The congestion window is ignored, see `set_nodelay`
+    pub const fn nocwnd(&self) -> bool {
+        self.nocwnd
+    }
+
+    /// Ignore the congestion window or not, leaving the other `set_nodelay`
+    /// settings alone
+    pub const fn set_nocwnd(&mut self, nocwnd: bool) {
+        self.nocwnd = nocwnd;
     }
 
     pub fn set_logging(&mut self, enable: bool) {
@@ -411,6 +1608,19 @@ impl KcpControl {
         self.write_log
     }
 
+    /// Enable or disable the structured event trace
+    ///
+    /// Events selected by the log mask are passed to `KcpCallBack::trace`,
+    /// independently of `set_logging`. See `trace::TraceOutput` to write
+    /// them as JSON lines.
+    pub fn set_tracing(&mut self, enable: bool) {
+        self.tracing = enable;
+    }
+
+    pub const fn tracing(&self) -> bool {
+        self.tracing
+    }
+
     /// Get the size of next message in receive queue without removing it
     ///
     /// # Returns
@@ -421,44 +1631,91 @@ impl KcpControl {
     /// # Note
     /// This checks both single-segment messages and multi-segment fragmented messages
     pub fn peek_size(&self) -> Result<usize, KcpError> {
-        if self.rcv_queue.is_empty() {
-            return Err(KcpError::QueueEmpty);
+        if self.reassembles() {
+            return self.reassembled_size();
         }
+        self.next_message().map(|(_, size)| size)
+    }
 
-        let first_seg = &self.rcv_queue[0];
+    /// Locate the next complete message in the receive queue
+    ///
+    /// # Returns
+    /// Number of segments and total bytes of the message
+    ///
+    /// # Note
+    /// The message ends at the first segment with `frg == 0`, and never spans
+    /// more than the `frg + 1` segments announced by its first fragment, so
+    /// that a peer sending inconsistent fragment numbers cannot make a message
+    /// swallow the whole queue.
+    fn next_message(&self) -> Result<(usize, usize), KcpError> {
+        let Some(first_seg) = self.rcv_queue.first() else {
+            return Err(KcpError::QueueEmpty);
+        };
 
         // Single segment message
         if first_seg.frg == 0 {
-            return Ok(first_seg.len as usize);
+            return Ok((1, first_seg.data.len()));
         }
 
         // Check if all fragments are present
-        if self.rcv_queue.len() < (first_seg.frg + 1) as usize {
+        let count = first_seg.frg as usize + 1;
+        if self.rcv_queue.len() < count {
             return Err(KcpError::IncompleteMessage);
         }
 
         // Calculate total length of fragmented message
         let mut total_len = 0;
-        for seg in &self.rcv_queue {
-            total_len += seg.len as usize;
+        for (i, seg) in self.rcv_queue[..count].iter().enumerate() {
+            total_len += seg.data.len();
             if seg.frg == 0 {
-                break;
+                return Ok((i + 1, total_len));
             }
         }
 
-        Ok(total_len)
+        Ok((count, total_len))
     }
 
+    #[cfg(feature = "log")]
     fn __log(&self, s: String) {
         if let Some(ref callback) = self.callback {
             callback.writelog(&s, self, self.user_data.as_ref());
         }
     }
 
+    /// Pass `event` to the callback when tracing is enabled and the log mask selects it
+    fn trace(&self, event: TraceEvent) {
+        if !self.tracing || !self.log_mask.intersects(event.flag()) {
+            return;
+        }
+        if let Some(ref callback) = self.callback {
+            callback.trace(&event, self, self.user_data.as_ref());
+        }
+    }
+
+    /// Trace the congestion window if it moved away from `from`
+    fn trace_cwnd(&self, from: u32, reason: CwndReason) {
+        let to = self.congestion.window();
+        if to != from {
+            self.trace(TraceEvent::CwndChanged { from, to, reason });
+        }
+    }
+
+    /// Change the connection state, `u32::MAX` marks a dead link
+    fn set_state(&mut self, state: u32) {
+        if self.state != state {
+            self.trace(TraceEvent::StateChanged {
+                from: self.state,
+                to: state,
+            });
+            self.state = state;
+        }
+    }
+
     /// Check if logging is enabled and the log mask matches the specified log flags
     ///
     /// # Arguments
     /// * `mask` - The log flags to check against
+    #[cfg(feature = "log")]
     fn canlog(&self, mask: KcpLogFlags) -> bool {
         self.log_mask.intersects(mask) && self.write_log && self.callback.is_some()
     }
@@ -491,7 +1748,30 @@ pub trait KcpCallBack: Send + Sync {
     ///
     /// # Note
     /// This is optional and can be left unimplemented if logging is not needed.
+    /// Only called when the `log` feature is enabled, as it is by `std`.
     fn writelog(&self, log: &str, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {}
+
+    /// Called for every trace event selected by the log mask
+    ///
+    /// # Arguments
+    /// * `event` - The event, which happened at `kcp.current`
+    /// * `kcp` - Reference to the KCP control block
+    /// * `user` - Optional user data associated with the KCP instance
+    ///
+    /// # Note
+    /// Only called once tracing is enabled with `KcpControl::set_tracing`.
+    fn trace(&self, event: &TraceEvent, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {}
+
+    /// Called at the end of every flush, once its datagrams went to `output`
+    ///
+    /// # Arguments
+    /// * `kcp` - Reference to the KCP control block
+    /// * `user` - Optional user data associated with the KCP instance
+    ///
+    /// # Note
+    /// Layers holding datagrams back, like `FecOutput`, send what they hold
+    /// here, so that the end of a burst is not delayed until the next one.
+    fn flushed(&self, kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {}
 }
 
 #[derive(Clone, Default)]
@@ -517,4 +1797,44 @@ impl Segment {
         x.data.resize(data_size, 0);
         x
     }
+
+    /// Encode the segment header into `buf` (little endian, ikcp layout)
+    ///
+    /// # Returns
+    /// Number of bytes written, always `IKCP_OVERHEAD`
+    pub fn encode_header(&self, buf: &mut [u8]) -> usize {
+        buf[0..4].copy_from_slice(&self.conv.to_le_bytes());
+        buf[4] = self.cmd as u8;
+        buf[5] = self.frg as u8;
+        buf[6..8].copy_from_slice(&(self.wnd as u16).to_le_bytes());
+        buf[8..12].copy_from_slice(&self.ts.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.sn.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.una.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.len.to_le_bytes());
+        IKCP_OVERHEAD as usize
+    }
+
+    /// Decode a segment header from the beginning of `buf`
+    ///
+    /// The payload is left empty, `len` tells how many bytes follow the header.
+    ///
+    /// # Errors
+    /// - `InvalidPacket`: Buffer is shorter than `IKCP_OVERHEAD`
+    pub fn decode_header(buf: &[u8]) -> Result<Self, KcpError> {
+        if buf.len() < IKCP_OVERHEAD as usize {
+            return Err(KcpError::InvalidPacket);
+        }
+        let read_u32 = |at: usize| u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+        Ok(Self {
+            conv: read_u32(0),
+            cmd: buf[4] as u32,
+            frg: buf[5] as u32,
+            wnd: u16::from_le_bytes([buf[6], buf[7]]) as u32,
+            ts: read_u32(8),
+            sn: read_u32(12),
+            una: read_u32(16),
+            len: read_u32(20),
+            ..Default::default()
+        })
+    }
 }
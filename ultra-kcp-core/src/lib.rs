@@ -1,2 +1,29 @@
+#![no_std]
+
+#[cfg(not(feature = "alloc"))]
+compile_error!("ultra-kcp-core requires the `alloc` feature");
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod bbr;
+#[cfg(feature = "std")]
+pub mod capture;
+pub mod checksum;
+#[cfg(feature = "compress")]
+pub mod compress;
+pub mod congestion;
 pub mod constants;
+#[cfg(feature = "cookie")]
+pub mod cookie;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod delayed_ack;
+#[cfg(feature = "fec")]
+pub mod fec;
 pub mod kcp;
+pub mod mux;
+pub mod pacing;
+pub mod serial;
+pub mod trace;
@@ -0,0 +1,798 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::ops::Bound;
+
+#[cfg(feature = "std")]
+use alloc::format;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use crate::constants::KcpError;
+use crate::kcp::KcpControl;
+
+/// Bytes before the body of every frame: command and stream id
+pub const MUX_HEADER_SIZE: usize = 5;
+
+/// Default receive window of a stream (bytes)
+pub const MUX_WINDOW_DEF: u32 = 256 * 1024;
+
+/// Default largest data frame body (bytes)
+pub const MUX_FRAME_SIZE_DEF: usize = 16 * 1024;
+
+/// Default limit of streams open at once
+pub const MUX_STREAMS_MAX_DEF: usize = 1024;
+
+/// Frame command, the first byte of every frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuxCommand {
+    /// Stream opened by the sender of the frame
+    Open = 0,
+    /// Stream data
+    Data = 1,
+    /// Sender of the frame will not write to the stream anymore
+    Close = 2,
+    /// Stream aborted in both directions
+    Reset = 3,
+    /// Total bytes read from the stream (u32), grants the writer more window
+    Update = 4,
+}
+
+impl TryFrom<u8> for MuxCommand {
+    type Error = KcpError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MuxCommand::Open),
+            1 => Ok(MuxCommand::Data),
+            2 => Ok(MuxCommand::Close),
+            3 => Ok(MuxCommand::Reset),
+            4 => Ok(MuxCommand::Update),
+            _ => Err(KcpError::InvalidPacket),
+        }
+    }
+}
+
+/// Side of the session, the two ends open stream ids of different parity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuxRole {
+    /// The side that opened the connection, opens odd stream ids
+    Initiator,
+    /// The side that accepted the connection, opens even stream ids
+    Responder,
+}
+
+/// Settings of a multiplexer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MuxConfig {
+    /// Receive window of every stream (bytes), the peer must use the same
+    pub window: u32,
+    /// Largest data frame body (bytes), smaller frames interleave streams more finely
+    pub frame_size: usize,
+    /// Streams open at once, streams opened by the peer beyond it are reset
+    pub max_streams: usize,
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        Self {
+            window: MUX_WINDOW_DEF,
+            frame_size: MUX_FRAME_SIZE_DEF,
+            max_streams: MUX_STREAMS_MAX_DEF,
+        }
+    }
+}
+
+/// Multiplexer counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MuxStats {
+    /// Streams opened locally
+    pub opened: u64,
+    /// Streams opened by the peer
+    pub accepted: u64,
+    /// Streams opened by the peer and reset because of `max_streams`
+    pub refused: u64,
+    /// Streams reset by either end
+    pub resets: u64,
+    /// Stream bytes queued by `write`
+    pub bytes_sent: u64,
+    /// Stream bytes received from the peer
+    pub bytes_received: u64,
+}
+
+#[derive(Default)]
+struct Stream {
+    /// Bytes received and not read yet
+    received: VecDeque<u8>,
+    /// The peer closed the stream for writing
+    read_closed: bool,
+    write_closed: bool,
+    reset: bool,
+    /// Given up by the application, data still received is discarded
+    released: bool,
+    /// Bytes written, wrapping
+    sent: u32,
+    /// Bytes the peer reported read, wrapping
+    peer_consumed: u32,
+    /// Bytes read or discarded, wrapping
+    consumed: u32,
+    /// `consumed` last reported to the peer
+    reported: u32,
+}
+
+impl Stream {
+    /// Bytes the peer's window still accepts
+    fn credit(&self, window: u32) -> usize {
+        window.saturating_sub(self.sent.wrapping_sub(self.peer_consumed)) as usize
+    }
+
+    /// Count `len` bytes consumed
+    ///
+    /// # Returns
+    /// Total to send in an update, once half a window was consumed since the last one
+    fn consume(&mut self, len: usize, window: u32) -> Option<u32> {
+        self.consumed = self.consumed.wrapping_add(len as u32);
+        if self.consumed.wrapping_sub(self.reported) < window / 2 {
+            return None;
+        }
+        self.reported = self.consumed;
+        Some(self.consumed)
+    }
+}
+
+/// Streams multiplexed over the messages of one KCP session, like smux
+///
+/// Every KCP message carries one frame: a command, a stream id and a body.
+/// Each stream has its own receive window: a writer never has more than
+/// `window` bytes unread by the peer, so that a stream whose reader stalls
+/// cannot hold up the others, and the multiplexer can always drain KCP.
+///
+/// The multiplexer only queues frames, `send` and `receive` exchange them
+/// with a control block. Frames of different streams take turns, and `send`
+/// leaves them queued here once KCP holds enough, so that a stream writing a
+/// lot cannot delay the frames other streams write after it.
+///
+/// # Note
+/// Requires message mode, streaming mode does not preserve frame boundaries.
+pub struct Mux {
+    config: MuxConfig,
+    role: MuxRole,
+    next_id: u32,
+    streams: BTreeMap<u32, Stream>,
+    /// Streams opened by the peer and not accepted yet
+    incoming: VecDeque<u32>,
+    /// Window updates and resets, sent ahead of stream frames
+    control: VecDeque<Vec<u8>>,
+    /// Open, data and close frames waiting for `send`, in order per stream
+    pending: BTreeMap<u32, VecDeque<Vec<u8>>>,
+    /// Stream whose frame was sent last, the next turn goes to the following one
+    turn: u32,
+    stats: MuxStats,
+}
+
+impl Mux {
+    pub fn new(config: MuxConfig, role: MuxRole) -> Self {
+        Self {
+            config,
+            role,
+            next_id: match role {
+                MuxRole::Initiator => 1,
+                MuxRole::Responder => 2,
+            },
+            streams: BTreeMap::new(),
+            incoming: VecDeque::new(),
+            control: VecDeque::new(),
+            pending: BTreeMap::new(),
+            turn: 0,
+            stats: MuxStats::default(),
+        }
+    }
+
+    pub const fn config(&self) -> &MuxConfig {
+        &self.config
+    }
+
+    pub const fn role(&self) -> MuxRole {
+        self.role
+    }
+
+    pub const fn stats(&self) -> &MuxStats {
+        &self.stats
+    }
+
+    /// Streams held by the multiplexer, released ones included until the peer closes them
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    fn frame(&mut self, command: MuxCommand, id: u32, body: &[u8]) {
+        let mut frame = Vec::with_capacity(MUX_HEADER_SIZE + body.len());
+        frame.push(command as u8);
+        frame.extend_from_slice(&id.to_le_bytes());
+        frame.extend_from_slice(body);
+        match command {
+            MuxCommand::Open | MuxCommand::Data | MuxCommand::Close => {
+                self.pending.entry(id).or_default().push_back(frame)
+            }
+            MuxCommand::Update => self.control.push_back(frame),
+            MuxCommand::Reset => {
+                // what the stream still had to say is moot
+                self.pending.remove(&id);
+                self.control.push_back(frame);
+            }
+        }
+    }
+
+    /// Stream whose turn it is to send a frame
+    fn next_stream(&self) -> Option<u32> {
+        self.pending
+            .range((Bound::Excluded(self.turn), Bound::Unbounded))
+            .next()
+            .or_else(|| self.pending.iter().next())
+            .map(|(&id, _)| id)
+    }
+
+    /// Take the next frame of stream `id`, which must have one
+    fn pop_stream_frame(&mut self, id: u32) -> Vec<u8> {
+        let frames = self.pending.get_mut(&id).unwrap();
+        let frame = frames.pop_front().unwrap();
+        if frames.is_empty() {
+            self.pending.remove(&id);
+        }
+        self.turn = id;
+        frame
+    }
+
+    /// Stream the application may still use
+    fn stream(&mut self, id: u32) -> Result<&mut Stream, KcpError> {
+        self.streams
+            .get_mut(&id)
+            .filter(|stream| !stream.released)
+            .ok_or(KcpError::UnknownStream)
+    }
+
+    /// Open a stream, the peer accepts it when its first frame arrives
+    ///
+    /// # Returns
+    /// Id of the new stream
+    ///
+    /// # Errors
+    /// - `TooManyStreams`: `max_streams` streams are open
+    pub fn open(&mut self) -> Result<u32, KcpError> {
+        if self.streams.len() >= self.config.max_streams {
+            return Err(KcpError::TooManyStreams);
+        }
+        // once ids wrap, skip the ones of streams still alive or with frames queued
+        let mut id = self.next_id;
+        while self.streams.contains_key(&id) || self.pending.contains_key(&id) {
+            id = id.wrapping_add(2);
+        }
+        self.next_id = id.wrapping_add(2);
+        self.streams.insert(id, Stream::default());
+        self.frame(MuxCommand::Open, id, &[]);
+        self.stats.opened += 1;
+        Ok(id)
+    }
+
+    /// Take the next stream opened by the peer
+    pub fn accept(&mut self) -> Option<u32> {
+        self.incoming.pop_front()
+    }
+
+    /// Bytes `write` accepts now on stream `id`
+    ///
+    /// # Errors
+    /// - `UnknownStream`: Stream is not open or was released
+    /// - `StreamReset`: Stream was reset
+    /// - `StreamClosed`: Stream was closed for writing
+    pub fn writable(&mut self, id: u32) -> Result<usize, KcpError> {
+        let window = self.config.window;
+        let stream = self.stream(id)?;
+        if stream.reset {
+            return Err(KcpError::StreamReset);
+        }
+        if stream.write_closed {
+            return Err(KcpError::StreamClosed);
+        }
+        Ok(stream.credit(window))
+    }
+
+    /// Queue `data` on stream `id`, as much as the peer's window allows
+    ///
+    /// # Returns
+    /// Bytes queued, less than `data.len()` when the window fills up
+    ///
+    /// # Errors
+    /// - Errors of `writable`
+    /// - `WindowFull`: The peer's window is full, nothing was queued
+    pub fn write(&mut self, id: u32, data: &[u8]) -> Result<usize, KcpError> {
+        let len = data.len().min(self.writable(id)?);
+        if len == 0 && !data.is_empty() {
+            return Err(KcpError::WindowFull);
+        }
+        let stream = self.stream(id)?;
+        stream.sent = stream.sent.wrapping_add(len as u32);
+        for chunk in data[..len].chunks(self.config.frame_size.max(1)) {
+            self.frame(MuxCommand::Data, id, chunk);
+        }
+        self.stats.bytes_sent += len as u64;
+        Ok(len)
+    }
+
+    /// Bytes waiting to be read on stream `id`
+    ///
+    /// # Errors
+    /// - `UnknownStream`: Stream is not open or was released
+    pub fn readable(&mut self, id: u32) -> Result<usize, KcpError> {
+        Ok(self.stream(id)?.received.len())
+    }
+
+    /// Read the data received on stream `id`
+    ///
+    /// # Returns
+    /// Bytes copied into `buf`, 0 once the peer closed the stream and
+    /// everything before was read
+    ///
+    /// # Errors
+    /// - `UnknownStream`: Stream is not open or was released
+    /// - `StreamReset`: Stream was reset, unread data is lost
+    /// - `QueueEmpty`: Nothing received yet, the stream is still open
+    pub fn read(&mut self, id: u32, buf: &mut [u8]) -> Result<usize, KcpError> {
+        let window = self.config.window;
+        let stream = self.stream(id)?;
+        if stream.reset {
+            return Err(KcpError::StreamReset);
+        }
+        if stream.received.is_empty() {
+            if stream.read_closed || buf.is_empty() {
+                return Ok(0);
+            }
+            return Err(KcpError::QueueEmpty);
+        }
+
+        let len = buf.len().min(stream.received.len());
+        for (byte, received) in buf.iter_mut().zip(stream.received.drain(..len)) {
+            *byte = received;
+        }
+        if let Some(consumed) = stream.consume(len, window) {
+            self.frame(MuxCommand::Update, id, &consumed.to_le_bytes());
+        }
+        Ok(len)
+    }
+
+    /// Close stream `id` for writing, the peer reads the end of the stream
+    /// after the data written before
+    ///
+    /// # Errors
+    /// - `UnknownStream`: Stream is not open or was released
+    /// - `StreamReset`: Stream was reset
+    pub fn close(&mut self, id: u32) -> Result<(), KcpError> {
+        let stream = self.stream(id)?;
+        if stream.reset {
+            return Err(KcpError::StreamReset);
+        }
+        if !stream.write_closed {
+            stream.write_closed = true;
+            self.frame(MuxCommand::Close, id, &[]);
+        }
+        Ok(())
+    }
+
+    /// Abort stream `id` in both directions and forget it
+    ///
+    /// # Errors
+    /// - `UnknownStream`: Stream is not open or was released
+    pub fn reset(&mut self, id: u32) -> Result<(), KcpError> {
+        let reset_by_peer = self.stream(id)?.reset;
+        self.streams.remove(&id);
+        if !reset_by_peer {
+            self.frame(MuxCommand::Reset, id, &[]);
+            self.stats.resets += 1;
+        }
+        Ok(())
+    }
+
+    /// Give stream `id` up: close it for writing and discard what the peer
+    /// still sends, the stream is forgotten once the peer closes it too
+    ///
+    /// # Errors
+    /// - `UnknownStream`: Stream is not open or was released
+    pub fn release(&mut self, id: u32) -> Result<(), KcpError> {
+        let window = self.config.window;
+        let stream = self.stream(id)?;
+        let close = !stream.reset && !stream.write_closed;
+        let done = stream.reset || stream.read_closed;
+        stream.released = true;
+        let discarded = stream.received.len();
+        stream.received = VecDeque::new();
+        let update = stream.consume(discarded, window);
+
+        if close {
+            self.frame(MuxCommand::Close, id, &[]);
+        }
+        if done {
+            self.streams.remove(&id);
+        } else if let Some(consumed) = update {
+            self.frame(MuxCommand::Update, id, &consumed.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Handle a frame sent by the peer's multiplexer
+    ///
+    /// Frames for streams that no longer exist are ignored, they may have
+    /// been sent before the peer learned of a reset.
+    ///
+    /// # Errors
+    /// - `InvalidPacket`: Truncated frame, unknown command, stream opened
+    ///   with an id of our parity or already in use, data after close
+    pub fn input(&mut self, frame: &[u8]) -> Result<(), KcpError> {
+        if frame.len() < MUX_HEADER_SIZE {
+            return Err(KcpError::InvalidPacket);
+        }
+        let command = MuxCommand::try_from(frame[0])?;
+        let id = u32::from_le_bytes(frame[1..MUX_HEADER_SIZE].try_into().unwrap());
+        let body = &frame[MUX_HEADER_SIZE..];
+
+        if command == MuxCommand::Open {
+            let parity = match self.role {
+                MuxRole::Initiator => 1,
+                MuxRole::Responder => 0,
+            };
+            if id % 2 == parity || self.streams.contains_key(&id) {
+                return Err(KcpError::InvalidPacket);
+            }
+            if self.streams.len() >= self.config.max_streams {
+                self.stats.refused += 1;
+                self.frame(MuxCommand::Reset, id, &[]);
+                return Ok(());
+            }
+            self.streams.insert(id, Stream::default());
+            self.incoming.push_back(id);
+            self.stats.accepted += 1;
+            return Ok(());
+        }
+
+        let window = self.config.window;
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(());
+        };
+        let mut reply = None;
+        let mut forget = false;
+        match command {
+            MuxCommand::Data => {
+                if stream.read_closed {
+                    return Err(KcpError::InvalidPacket);
+                }
+                self.stats.bytes_received += body.len() as u64;
+                if stream.released {
+                    reply = stream
+                        .consume(body.len(), window)
+                        .map(|consumed| (MuxCommand::Update, consumed));
+                } else if stream.reset {
+                    // sent before the peer saw our reset
+                } else if stream.received.len() + body.len() > window as usize {
+                    // the peer ignored the window, the stream cannot be trusted anymore
+                    stream.reset = true;
+                    stream.received = VecDeque::new();
+                    self.stats.resets += 1;
+                    reply = Some((MuxCommand::Reset, 0));
+                } else {
+                    stream.received.extend(body);
+                }
+            }
+            MuxCommand::Close => {
+                stream.read_closed = true;
+                forget = stream.released;
+            }
+            MuxCommand::Reset => {
+                if !stream.reset {
+                    stream.reset = true;
+                    stream.received = VecDeque::new();
+                    self.stats.resets += 1;
+                }
+                self.pending.remove(&id);
+                forget = stream.released;
+            }
+            MuxCommand::Update => {
+                let consumed = body.try_into().map_err(|_| KcpError::InvalidPacket)?;
+                stream.peer_consumed = u32::from_le_bytes(consumed);
+            }
+            MuxCommand::Open => unreachable!(),
+        }
+
+        match reply {
+            Some((MuxCommand::Update, consumed)) => {
+                self.frame(MuxCommand::Update, id, &consumed.to_le_bytes())
+            }
+            Some((command, _)) => self.frame(command, id, &[]),
+            None => {}
+        }
+        if forget {
+            self.streams.remove(&id);
+        }
+        Ok(())
+    }
+
+    /// Take the next frame for the peer
+    ///
+    /// Window updates and resets come first, then streams take turns, one
+    /// frame each. Frames of a stream keep their order.
+    pub fn poll_frame(&mut self) -> Option<Vec<u8>> {
+        if let Some(frame) = self.control.pop_front() {
+            return Some(frame);
+        }
+        let id = self.next_stream()?;
+        Some(self.pop_stream_frame(id))
+    }
+
+    /// Queue pending frames on `kcp`, one message each, in the order of
+    /// `poll_frame`
+    ///
+    /// Stream frames stop once `kcp` holds twice its send window in
+    /// segments, the rest waits for a later call.
+    ///
+    /// # Errors
+    /// Errors of `KcpControl::send`, the frame is kept for the next call
+    pub fn send(&mut self, kcp: &mut KcpControl) -> Result<(), KcpError> {
+        while let Some(frame) = self.control.front() {
+            kcp.send(frame)?;
+            self.control.pop_front();
+        }
+        let limit = 2 * kcp.send_window.max(1) as usize;
+        while kcp.wait_snd() < limit {
+            let Some(id) = self.next_stream() else {
+                break;
+            };
+            kcp.send(self.pending[&id].front().unwrap())?;
+            self.pop_stream_frame(id);
+        }
+        Ok(())
+    }
+
+    /// Handle every message received by `kcp`
+    ///
+    /// # Errors
+    /// Errors of `KcpControl::receive`, then of `input`, the following
+    /// messages are left in `kcp`
+    pub fn receive(&mut self, kcp: &mut KcpControl) -> Result<(), KcpError> {
+        let mut frame = Vec::new();
+        while let Ok(size) = kcp.peek_size() {
+            frame.resize(size, 0);
+            let len = kcp.receive(Some(&mut frame), false)?;
+            self.input(&frame[..len])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+struct Shared {
+    mux: Mutex<Mux>,
+    changed: Condvar,
+}
+
+#[cfg(feature = "std")]
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Mux> {
+        self.mux.lock().unwrap()
+    }
+
+    /// Wait until `SharedMux::pump` ran, or `deadline`
+    ///
+    /// # Errors
+    /// - `TimedOut`: `deadline` passed
+    fn wait<'a>(
+        &self,
+        guard: MutexGuard<'a, Mux>,
+        deadline: Option<Instant>,
+    ) -> io::Result<MutexGuard<'a, Mux>> {
+        let Some(deadline) = deadline else {
+            return Ok(self.changed.wait(guard).unwrap());
+        };
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "stream timed out"));
+        }
+        Ok(self.changed.wait_timeout(guard, deadline - now).unwrap().0)
+    }
+}
+
+/// Map a stream error to the `io::Error` of a socket in the same state
+#[cfg(feature = "std")]
+fn io_error(error: KcpError) -> io::Error {
+    let kind = match error {
+        KcpError::StreamReset => io::ErrorKind::ConnectionReset,
+        KcpError::StreamClosed => io::ErrorKind::BrokenPipe,
+        KcpError::UnknownStream => io::ErrorKind::NotConnected,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{error:?}"))
+}
+
+/// Multiplexer shared between the thread driving KCP and blocking stream handles
+///
+/// `KcpControl` stays on the thread that owns it, which calls `pump` after
+/// `input` and `update`. Stream handles on any thread wait for the data and
+/// window updates `pump` brings in.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct SharedMux(Arc<Shared>);
+
+#[cfg(feature = "std")]
+impl SharedMux {
+    pub fn new(config: MuxConfig, role: MuxRole) -> Self {
+        Self(Arc::new(Shared {
+            mux: Mutex::new(Mux::new(config, role)),
+            changed: Condvar::new(),
+        }))
+    }
+
+    /// Run `f` on the multiplexer, for its statistics or non-blocking calls
+    pub fn with<R>(&self, f: impl FnOnce(&mut Mux) -> R) -> R {
+        let result = f(&mut self.0.lock());
+        self.0.changed.notify_all();
+        result
+    }
+
+    fn stream(&self, id: u32) -> MuxStream {
+        MuxStream {
+            shared: self.0.clone(),
+            handles: Arc::new(AtomicUsize::new(1)),
+            id,
+            timeout: None,
+        }
+    }
+
+    /// Open a stream
+    ///
+    /// # Errors
+    /// - `TooManyStreams`: `max_streams` streams are open
+    pub fn open(&self) -> Result<MuxStream, KcpError> {
+        let id = self.0.lock().open()?;
+        Ok(self.stream(id))
+    }
+
+    /// Wait for a stream opened by the peer
+    ///
+    /// # Arguments
+    /// * `timeout` - Longest wait, forever when `None`
+    ///
+    /// # Returns
+    /// `None` when no stream was opened in time
+    pub fn accept(&self, timeout: Option<Duration>) -> Option<MuxStream> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut mux = self.0.lock();
+        loop {
+            if let Some(id) = mux.accept() {
+                drop(mux);
+                return Some(self.stream(id));
+            }
+            mux = self.0.wait(mux, deadline).ok()?;
+        }
+    }
+
+    /// Hand the frames received by `kcp` to the streams and queue the
+    /// pending frames on `kcp`, then wake the waiting handles
+    ///
+    /// # Errors
+    /// Errors of `Mux::receive` and `Mux::send`
+    pub fn pump(&self, kcp: &mut KcpControl) -> Result<(), KcpError> {
+        let mut mux = self.0.lock();
+        let result = mux.receive(kcp).and_then(|()| mux.send(kcp));
+        drop(mux);
+        self.0.changed.notify_all();
+        result
+    }
+}
+
+/// Blocking handle of a multiplexed stream
+///
+/// Dropping the last handle of a stream releases it, see `Mux::release`.
+#[cfg(feature = "std")]
+pub struct MuxStream {
+    shared: Arc<Shared>,
+    /// Handles of the stream, shared by `try_clone`
+    handles: Arc<AtomicUsize>,
+    id: u32,
+    timeout: Option<Duration>,
+}
+
+#[cfg(feature = "std")]
+impl MuxStream {
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Another handle of the stream, so that one thread can read while
+    /// another writes
+    ///
+    /// # Errors
+    /// - `NotConnected`: Stream is already gone
+    pub fn try_clone(&self) -> io::Result<MuxStream> {
+        self.shared.lock().readable(self.id).map_err(io_error)?;
+        self.handles.fetch_add(1, Ordering::Relaxed);
+        Ok(MuxStream {
+            shared: self.shared.clone(),
+            handles: self.handles.clone(),
+            id: self.id,
+            timeout: self.timeout,
+        })
+    }
+
+    /// Limit how long `read` and `write` wait, forever when `None`
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Close the stream for writing, the peer reads its end
+    ///
+    /// # Errors
+    /// - `ConnectionReset`: Stream was reset
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.shared.lock().close(self.id).map_err(io_error)
+    }
+
+    /// Abort the stream in both directions
+    ///
+    /// # Errors
+    /// - `NotConnected`: Stream is already gone
+    pub fn reset(self) -> io::Result<()> {
+        self.shared.lock().reset(self.id).map_err(io_error)
+    }
+
+    /// Run `op` until it stops failing with `again`, waiting for `pump` in between
+    fn blocking<R>(
+        &self,
+        again: KcpError,
+        mut op: impl FnMut(&mut Mux) -> Result<R, KcpError>,
+    ) -> io::Result<R> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut mux = self.shared.lock();
+        loop {
+            match op(&mut mux) {
+                Err(error) if error == again => mux = self.shared.wait(mux, deadline)?,
+                result => return result.map_err(io_error),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Read for MuxStream {
+    /// Wait for data, 0 at the end of the stream
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let id = self.id;
+        self.blocking(KcpError::QueueEmpty, |mux| mux.read(id, buf))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Write for MuxStream {
+    /// Wait for window, then queue as much of `buf` as it allows
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let id = self.id;
+        self.blocking(KcpError::WindowFull, |mux| mux.write(id, buf))
+    }
+
+    /// Frames are handed to KCP by `SharedMux::pump`, nothing to do
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        if self.handles.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        // already gone after `reset`
+        let _ = self.shared.lock().release(self.id);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::serial::itimediff;
+
+/// Default bucket depth, in MTU sized datagrams
+pub const PACING_BURST_DEF: u32 = 2;
+
+/// Headroom applied to the rate derived from cwnd/srtt, so that pacing
+/// does not become the bottleneck itself (5/4)
+const PACING_GAIN_NUM: u64 = 5;
+const PACING_GAIN_DEN: u64 = 4;
+
+/// Where the pacing rate comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacingRate {
+    /// Use the congestion controller's pacing rate, or `cwnd * mss / srtt`
+    /// when the controller does not provide one
+    Auto,
+    /// Fixed rate in bytes per second
+    Fixed(u64),
+}
+
+/// Token bucket spreading data segments over time
+///
+/// Tokens are accounted in bytes multiplied by 1000, so that sub-millisecond
+/// refills at low rates are not lost to rounding.
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    mode: PacingRate,
+    /// Bucket depth, in MTU sized datagrams
+    pub burst: u32,
+    rate: u64,
+    credit: u64,
+    last_refill: Option<u32>,
+    release: Option<u32>,
+}
+
+impl Pacer {
+    pub fn new(mode: PacingRate) -> Self {
+        Self {
+            mode,
+            burst: PACING_BURST_DEF,
+            rate: 0,
+            credit: u64::MAX,
+            last_refill: None,
+            release: None,
+        }
+    }
+
+    pub const fn mode(&self) -> PacingRate {
+        self.mode
+    }
+
+    /// Rate used by the last flush in bytes per second, 0 when unlimited
+    pub const fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Time at which the next deferred segment may be released
+    ///
+    /// # Returns
+    /// `None` when the last flush was not limited by the pacer
+    pub const fn release(&self) -> Option<u32> {
+        self.release
+    }
+
+    /// Resolve the rate for this flush
+    ///
+    /// # Arguments
+    /// * `controller_rate` - Pacing rate provided by the congestion controller
+    /// * `cwnd` - Effective send window (segments)
+    /// * `mss` - Maximum segment size
+    /// * `srtt` - Smoothed round trip time (ms), 0 if unknown
+    pub(crate) fn resolve_rate(
+        &self,
+        controller_rate: Option<u64>,
+        cwnd: u32,
+        mss: u32,
+        srtt: i32,
+    ) -> u64 {
+        match self.mode {
+            PacingRate::Fixed(rate) => rate,
+            PacingRate::Auto => controller_rate.unwrap_or_else(|| {
+                if srtt <= 0 {
+                    return 0;
+                }
+                let rate = cwnd as u64 * mss as u64 * 1000 / srtt as u64;
+                rate * PACING_GAIN_NUM / PACING_GAIN_DEN
+            }),
+        }
+    }
+
+    /// Add the tokens accumulated since the last refill
+    ///
+    /// # Arguments
+    /// * `current` - Current timestamp in milliseconds
+    /// * `rate` - Rate in bytes per second, 0 disables the limit
+    /// * `mtu` - Datagram size, the bucket holds `burst` of them
+    ///
+    /// # Note
+    /// The first refill fills the bucket, and clears the release time of the
+    /// previous flush.
+    pub fn refill(&mut self, current: u32, rate: u64, mtu: u32) {
+        let capacity = self.burst.max(1) as u64 * mtu as u64 * 1000;
+        let elapsed = match self.last_refill {
+            Some(last) => itimediff(current, last).max(0) as u64,
+            None => u64::MAX,
+        };
+        self.credit = self
+            .credit
+            .saturating_add(rate.saturating_mul(elapsed))
+            .min(capacity);
+        self.rate = rate;
+        self.last_refill = Some(current);
+        self.release = None;
+    }
+
+    /// Take tokens for `bytes`
+    ///
+    /// # Returns
+    /// false when the bucket is short, `release` then tells when enough
+    /// tokens will be available
+    pub fn consume(&mut self, current: u32, bytes: u32) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        let need = bytes as u64 * 1000;
+        if self.credit >= need {
+            self.credit -= need;
+            return true;
+        }
+        let wait = (need - self.credit).div_ceil(self.rate);
+        self.release = Some(current.wrapping_add(wait.min(u32::MAX as u64) as u32));
+        false
+    }
+}
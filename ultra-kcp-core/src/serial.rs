@@ -0,0 +1,15 @@
+/// Signed distance from `earlier` to `later` (ikcp `_itimediff`)
+///
+/// Sequence numbers and millisecond timestamps are u32 counters that wrap on
+/// long-lived connections, so they are never compared directly. The result
+/// is correct as long as both values are less than 2^31 apart.
+#[inline]
+pub const fn itimediff(later: u32, earlier: u32) -> i32 {
+    later.wrapping_sub(earlier) as i32
+}
+
+/// `sn` lies within the half-open range `[start, end)`, across the wraparound
+#[inline]
+pub const fn in_range(sn: u32, start: u32, end: u32) -> bool {
+    itimediff(sn, start) >= 0 && itimediff(sn, end) < 0
+}
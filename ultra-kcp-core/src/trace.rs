@@ -0,0 +1,203 @@
+use core::fmt;
+
+#[cfg(feature = "std")]
+use core::any::Any;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use alloc::string::String;
+
+use crate::constants::{Command, KcpLogFlags};
+use crate::kcp::Segment;
+#[cfg(feature = "std")]
+use crate::kcp::{KcpCallBack, KcpControl};
+
+/// Header fields of a traced segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentTrace {
+    pub cmd: u32,
+    pub sn: u32,
+    pub una: u32,
+    pub frg: u32,
+    pub wnd: u32,
+    pub len: u32,
+    /// Transmissions of the segment so far, 0 for control segments
+    pub xmit: u32,
+}
+
+impl SegmentTrace {
+    pub(crate) fn new(seg: &Segment) -> Self {
+        Self {
+            cmd: seg.cmd,
+            sn: seg.sn,
+            una: seg.una,
+            frg: seg.frg,
+            wnd: seg.wnd,
+            len: seg.len,
+            xmit: seg.xmit,
+        }
+    }
+
+    /// Log flag selecting the segment, by command and direction
+    pub fn flag(&self, outgoing: bool) -> KcpLogFlags {
+        match (Command::try_from(self.cmd), outgoing) {
+            (Ok(Command::Push), true) => KcpLogFlags::OUT_DATA,
+            (Ok(Command::Push), false) => KcpLogFlags::IN_DATA,
+            (Ok(Command::Ack | Command::Sack), true) => KcpLogFlags::OUT_ACK,
+            (Ok(Command::Ack | Command::Sack), false) => KcpLogFlags::IN_ACK,
+            (Ok(Command::Wask), true) => KcpLogFlags::OUT_PROBE,
+            (Ok(Command::Wask), false) => KcpLogFlags::IN_PROBE,
+            (_, true) => KcpLogFlags::OUT_WINS,
+            (_, false) => KcpLogFlags::IN_WINS,
+        }
+    }
+}
+
+/// Congestion event that changed the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CwndReason {
+    Ack,
+    Loss,
+    Timeout,
+}
+
+/// Event reported to `KcpCallBack::trace`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// Segment written to an outgoing datagram
+    SegmentSent(SegmentTrace),
+    /// Segment parsed from an incoming datagram
+    SegmentReceived(SegmentTrace),
+    /// RTT sample taken from an acknowledgement
+    RttUpdated {
+        sample: i32,
+        srtt: i32,
+        rttval: i32,
+        rto: u32,
+    },
+    /// Congestion window changed (segments)
+    CwndChanged {
+        from: u32,
+        to: u32,
+        reason: CwndReason,
+    },
+    /// `KcpControl::state` changed, `u32::MAX` marks a dead link
+    StateChanged { from: u32, to: u32 },
+}
+
+impl TraceEvent {
+    /// Log flag selecting the event
+    pub fn flag(&self) -> KcpLogFlags {
+        match self {
+            TraceEvent::SegmentSent(seg) => seg.flag(true),
+            TraceEvent::SegmentReceived(seg) => seg.flag(false),
+            TraceEvent::RttUpdated { .. } => KcpLogFlags::RTT,
+            TraceEvent::CwndChanged { .. } => KcpLogFlags::CWND,
+            TraceEvent::StateChanged { .. } => KcpLogFlags::STATE,
+        }
+    }
+
+    /// Event name in the trace
+    pub const fn name(&self) -> &'static str {
+        match self {
+            TraceEvent::SegmentSent(_) => "segment_sent",
+            TraceEvent::SegmentReceived(_) => "segment_received",
+            TraceEvent::RttUpdated { .. } => "rtt_updated",
+            TraceEvent::CwndChanged { .. } => "cwnd_changed",
+            TraceEvent::StateChanged { .. } => "state_changed",
+        }
+    }
+
+    /// Write the event as a single line JSON object, without the newline
+    ///
+    /// # Arguments
+    /// * `time` - Timestamp of the event, `KcpControl::current`
+    /// * `out` - Destination
+    ///
+    /// # Note
+    /// The layout follows qlog: `{"time":..,"name":..,"data":{..}}`.
+    pub fn write_json(&self, time: u32, out: &mut impl fmt::Write) -> fmt::Result {
+        write!(out, r#"{{"time":{time},"name":"{}","data":{{"#, self.name())?;
+        match self {
+            TraceEvent::SegmentSent(seg) | TraceEvent::SegmentReceived(seg) => {
+                let cmd = Command::try_from(seg.cmd).map_or("unknown", |cmd| cmd.name());
+                write!(
+                    out,
+                    r#""cmd":"{cmd}","sn":{},"una":{},"frg":{},"wnd":{},"len":{},"xmit":{}"#,
+                    seg.sn, seg.una, seg.frg, seg.wnd, seg.len, seg.xmit
+                )?;
+            }
+            TraceEvent::RttUpdated {
+                sample,
+                srtt,
+                rttval,
+                rto,
+            } => write!(
+                out,
+                r#""sample":{sample},"srtt":{srtt},"rttval":{rttval},"rto":{rto}"#
+            )?,
+            TraceEvent::CwndChanged { from, to, reason } => {
+                let reason = match reason {
+                    CwndReason::Ack => "ack",
+                    CwndReason::Loss => "loss",
+                    CwndReason::Timeout => "timeout",
+                };
+                write!(out, r#""from":{from},"to":{to},"reason":"{reason}""#)?;
+            }
+            TraceEvent::StateChanged { from, to } => write!(out, r#""from":{from},"to":{to}"#)?,
+        }
+        out.write_str("}}")
+    }
+}
+
+/// Output callback writing every trace event as a JSON line to `writer`
+///
+/// Datagrams and log lines are forwarded to `inner`. Tracing must be
+/// enabled with `KcpControl::set_tracing`, and the events selected with
+/// `KcpControl::set_log_mask`.
+///
+/// # Note
+/// Write errors are ignored, a trace must never disturb the connection.
+#[cfg(feature = "std")]
+pub struct TraceOutput<C, W> {
+    inner: C,
+    writer: Mutex<W>,
+}
+
+#[cfg(feature = "std")]
+impl<C: KcpCallBack, W: Write + Send> TraceOutput<C, W> {
+    pub fn new(inner: C, writer: W) -> Self {
+        Self {
+            inner,
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C: KcpCallBack, W: Write + Send> KcpCallBack for TraceOutput<C, W> {
+    fn output(&self, buf: &[u8], kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.output(buf, kcp, user);
+    }
+
+    fn writelog(&self, log: &str, kcp: &KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.writelog(log, kcp, user);
+    }
+
+    fn trace(&self, event: &TraceEvent, kcp: &KcpControl, _user: Option<&Box<dyn Any>>) {
+        let mut line = String::with_capacity(128);
+        if event.write_json(kcp.current, &mut line).is_ok() {
+            line.push('\n');
+            let _ = self.writer.lock().unwrap().write_all(line.as_bytes());
+        }
+    }
+
+    fn flushed(&self, kcp: &mut KcpControl, user: Option<&Box<dyn Any>>) {
+        self.inner.flushed(kcp, user);
+    }
+}
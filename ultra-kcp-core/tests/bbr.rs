@@ -0,0 +1,37 @@
+use ultra_kcp_core::bbr::BbrController;
+use ultra_kcp_core::congestion::{AckEvent, CongestionController};
+
+/// Feed `rounds` round trips of ACKs, starting the clock at `start`
+///
+/// Returns the bandwidth estimate after every ACK.
+fn ack_clock(start: u32, rounds: u32) -> Vec<u64> {
+    let mut bbr = BbrController::new();
+    let mut estimates = Vec::new();
+    for i in 1..=rounds * 10 {
+        bbr.on_ack(&AckEvent {
+            current: start.wrapping_add(i * 5),
+            una_advanced: true,
+            acked_segments: 1,
+            acked_bytes: 1000,
+            rtt: Some(50),
+            srtt: 50,
+            inflight: 10,
+            mss: 1000,
+            rmt_wnd: 128,
+        });
+        estimates.push(bbr.btl_bw());
+    }
+    estimates
+}
+
+#[test]
+fn bbr_rounds_do_not_depend_on_the_clock_origin() {
+    let origin = ack_clock(0, 5);
+    // 1000 bytes every 5ms, measured from the second round on
+    assert_eq!(origin.iter().position(|&bw| bw > 0), Some(10));
+    assert_eq!(*origin.last().unwrap(), 200_000);
+
+    // timestamps with the top bit set, and wrapping during the run
+    assert_eq!(ack_clock(0x9000_0000, 5), origin);
+    assert_eq!(ack_clock(u32::MAX - 60, 5), origin);
+}
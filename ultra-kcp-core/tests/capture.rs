@@ -0,0 +1,169 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use common::{drain, Endpoint};
+use ultra_kcp_core::capture::{
+    CaptureConfig, CaptureOutput, Direction, PcapCapture, PCAP_LINKTYPE_RAW, PCAP_MAGIC,
+};
+
+/// Record of a pcap file
+struct Packet {
+    ts_sec: u32,
+    ts_usec: u32,
+    data: Vec<u8>,
+}
+
+fn u16_be(buf: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([buf[at], buf[at + 1]])
+}
+
+fn u32_le(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn parse(file: &[u8]) -> Vec<Packet> {
+    assert_eq!(u32_le(file, 0), PCAP_MAGIC);
+    assert_eq!(u32_le(file, 20), PCAP_LINKTYPE_RAW);
+    let mut packets = Vec::new();
+    let mut at = 24;
+    while at < file.len() {
+        let caplen = u32_le(file, at + 8) as usize;
+        assert_eq!(caplen, u32_le(file, at + 12) as usize);
+        packets.push(Packet {
+            ts_sec: u32_le(file, at),
+            ts_usec: u32_le(file, at + 4),
+            data: file[at + 16..at + 16 + caplen].to_vec(),
+        });
+        at += 16 + caplen;
+    }
+    assert_eq!(at, file.len());
+    packets
+}
+
+/// One's complement sum, 0 over a block carrying a valid checksum
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let bytes: Vec<u8> = parts.concat();
+    let mut sum = bytes
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Check the IPv4 and UDP headers, return source, destination and payload
+fn parse_ipv4(packet: &[u8]) -> (SocketAddr, SocketAddr, &[u8]) {
+    assert_eq!(packet[0], 0x45);
+    assert_eq!(packet[9], 17);
+    assert_eq!(u16_be(packet, 2) as usize, packet.len());
+    assert_eq!(checksum(&[&packet[..20]]), 0, "bad IPv4 header checksum");
+    let udp = &packet[20..];
+    assert_eq!(u16_be(udp, 4) as usize, udp.len());
+    let mut pseudo = packet[12..20].to_vec();
+    pseudo.extend_from_slice(&[0, 17]);
+    pseudo.extend_from_slice(&udp[4..6]);
+    assert_eq!(checksum(&[&pseudo, udp]), 0, "bad UDP checksum");
+    let src: [u8; 4] = packet[12..16].try_into().unwrap();
+    let dst: [u8; 4] = packet[16..20].try_into().unwrap();
+    (
+        SocketAddr::from((src, u16_be(udp, 0))),
+        SocketAddr::from((dst, u16_be(udp, 2))),
+        &udp[8..],
+    )
+}
+
+#[test]
+fn capture_records_both_directions() {
+    let config = CaptureConfig::default();
+    let capture = Arc::new(Mutex::new(PcapCapture::new(Vec::new(), config).unwrap()));
+    let Endpoint { mut kcp, wire } =
+        Endpoint::wrapped(9, |output| CaptureOutput::new(capture.clone(), output));
+    kcp.set_nodelay(1, 10, 2, false);
+
+    let Endpoint {
+        kcp: mut peer,
+        wire: peer_wire,
+    } = Endpoint::new(9);
+    peer.set_nodelay(1, 10, 2, false);
+
+    kcp.send(&[0x5a; 3000]).unwrap();
+    let mut outgoing = Vec::new();
+    let mut incoming = Vec::new();
+    for current in (1230..2000).step_by(10) {
+        kcp.update(current);
+        peer.update(current);
+        for packet in drain(&wire) {
+            peer.input(&packet).unwrap();
+            outgoing.push(packet);
+        }
+        for packet in drain(&peer_wire) {
+            capture.lock().unwrap().input(&mut kcp, &packet).unwrap();
+            incoming.push(packet);
+        }
+    }
+    assert!(!outgoing.is_empty() && !incoming.is_empty());
+
+    drop(kcp);
+    let capture = Arc::try_unwrap(capture).ok().unwrap().into_inner().unwrap();
+    assert_eq!(capture.packets() as usize, outgoing.len() + incoming.len());
+    let packets = parse(&capture.into_inner());
+    assert_eq!(packets.len(), outgoing.len() + incoming.len());
+    assert_eq!(packets[0].ts_sec, 1);
+    assert_eq!(packets[0].ts_usec, 230_000);
+
+    let (mut sent, mut received) = (outgoing.iter(), incoming.iter());
+    for packet in &packets {
+        let (src, dst, payload) = parse_ipv4(&packet.data);
+        if src == config.local {
+            assert_eq!(dst, config.remote);
+            assert_eq!(payload, sent.next().unwrap().as_slice());
+        } else {
+            assert_eq!((src, dst), (config.remote, config.local));
+            assert_eq!(payload, received.next().unwrap().as_slice());
+        }
+    }
+    assert!(sent.next().is_none() && received.next().is_none());
+}
+
+#[test]
+fn capture_writes_ipv6_headers() {
+    let config = CaptureConfig {
+        local: "[fd00::1]:5000".parse().unwrap(),
+        remote: "[fd00::2]:6000".parse().unwrap(),
+    };
+    let mut capture = PcapCapture::new(Vec::new(), config).unwrap();
+    capture.record(Direction::Incoming, 2500, b"odd").unwrap();
+    let packets = parse(&capture.into_inner());
+    assert_eq!(packets.len(), 1);
+    let packet = &packets[0].data;
+    assert_eq!((packets[0].ts_sec, packets[0].ts_usec), (2, 500_000));
+
+    assert_eq!(packet[0] >> 4, 6);
+    assert_eq!(packet[6], 17);
+    assert_eq!(u16_be(packet, 4) as usize, packet.len() - 40);
+    assert_eq!(
+        &packet[8..24],
+        &"fd00::2".parse::<std::net::Ipv6Addr>().unwrap().octets()
+    );
+    let udp = &packet[40..];
+    assert_eq!((u16_be(udp, 0), u16_be(udp, 2)), (6000, 5000));
+    let mut pseudo = packet[8..40].to_vec();
+    pseudo.extend_from_slice(&(udp.len() as u32).to_be_bytes());
+    pseudo.extend_from_slice(&[0, 0, 0, 17]);
+    assert_eq!(checksum(&[&pseudo, udp]), 0, "bad UDP checksum");
+    assert_eq!(&udp[8..], b"odd");
+}
+
+#[test]
+fn capture_rejects_mixed_families() {
+    let config = CaptureConfig {
+        local: "10.0.0.1:4000".parse().unwrap(),
+        remote: "[::1]:4000".parse().unwrap(),
+    };
+    let error = PcapCapture::new(Vec::new(), config).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,90 @@
+//! Loopback fixture shared by the integration tests and the benchmarks
+
+// every test crate compiles its own copy and uses part of it
+#![allow(dead_code)]
+
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+use ultra_kcp_core::constants::IKCP_OVERHEAD;
+use ultra_kcp_core::kcp::{KcpCallBack, KcpControl, Segment};
+
+/// Datagrams output by a control block, oldest first
+pub type Wire = Arc<Mutex<Vec<Vec<u8>>>>;
+
+/// Output callback appending every datagram to a `Wire`
+pub struct WireOutput(pub Wire);
+
+impl KcpCallBack for WireOutput {
+    fn output(&self, buf: &[u8], _kcp: &mut KcpControl, _user: Option<&Box<dyn Any>>) {
+        self.0.lock().unwrap().push(buf.to_vec());
+    }
+}
+
+/// Take the datagrams on `wire`, without holding its lock afterwards
+pub fn drain(wire: &Wire) -> Vec<Vec<u8>> {
+    wire.lock().unwrap().drain(..).collect()
+}
+
+/// Every segment of the datagrams on `wire`, header and raw bytes
+pub fn segments(wire: &Wire) -> Vec<(Segment, Vec<u8>)> {
+    let mut segments = Vec::new();
+    for datagram in drain(wire) {
+        let mut rest = &datagram[..];
+        while !rest.is_empty() {
+            let segment = Segment::decode_header(rest).unwrap();
+            let size = IKCP_OVERHEAD as usize + segment.len as usize;
+            segments.push((segment, rest[..size].to_vec()));
+            rest = &rest[size..];
+        }
+    }
+    segments
+}
+
+/// Control block writing its output to `wire`
+pub struct Endpoint {
+    pub kcp: KcpControl,
+    pub wire: Wire,
+}
+
+impl Endpoint {
+    pub fn new(conv: u32) -> Self {
+        Self::wrapped(conv, |output| output)
+    }
+
+    /// Control block for `conv` with the callback made by `wrap` around the
+    /// output to the wire
+    pub fn wrapped<C: KcpCallBack + 'static>(
+        conv: u32,
+        wrap: impl FnOnce(WireOutput) -> C,
+    ) -> Self {
+        let wire = Wire::default();
+        let mut kcp = KcpControl::new_on_stack(conv, None);
+        kcp.set_callback(Box::new(wrap(WireOutput(wire.clone()))));
+        Self { kcp, wire }
+    }
+}
+
+/// Update both endpoints at `current`, then input the output of each one
+/// into the other
+pub fn pump(current: u32, a: &mut Endpoint, b: &mut Endpoint) {
+    a.kcp.update(current);
+    b.kcp.update(current);
+    for datagram in drain(&a.wire) {
+        b.kcp.input(&datagram).unwrap();
+    }
+    for datagram in drain(&b.wire) {
+        a.kcp.input(&datagram).unwrap();
+    }
+}
+
+/// Every complete message waiting in `kcp`
+pub fn receive_all(kcp: &mut KcpControl) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    while let Ok(size) = kcp.peek_size() {
+        let mut buf = vec![0; size];
+        kcp.receive(Some(&mut buf), false).unwrap();
+        messages.push(buf);
+    }
+    messages
+}
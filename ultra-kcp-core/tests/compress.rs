@@ -0,0 +1,200 @@
+mod common;
+
+use common::{pump, Endpoint};
+use ultra_kcp_core::compress::{
+    CompressConfig, Compressor, COMPRESS_FLAG_DICT, COMPRESS_FLAG_LZ4, COMPRESS_FLAG_SIZE,
+};
+use ultra_kcp_core::constants::KcpError;
+
+fn endpoint() -> Endpoint {
+    let mut endpoint = Endpoint::new(1);
+    endpoint.kcp.set_nodelay(1, 10, 2, true);
+    endpoint
+}
+
+fn json_record(id: usize) -> Vec<u8> {
+    format!(
+        r#"{{"id":{id},"type":"position","player":"player-{}","x":{},"y":{},"velocity":{{"x":0,"y":-1}},"flags":["visible","moving"]}}"#,
+        id % 7,
+        id * 3,
+        id * 5
+    )
+    .into_bytes()
+}
+
+/// Bytes without repetitions, which LZ4 cannot shrink
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x9e37_79b9_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn compressed_messages_round_trip_through_kcp() {
+    let mut client = endpoint();
+    let mut server = endpoint();
+    let mut sender = Compressor::new(CompressConfig::default());
+    let receiver = Compressor::new(CompressConfig::default());
+
+    let batch = (0..20).flat_map(json_record).collect::<Vec<_>>();
+    let messages = vec![batch.clone(), b"ping".to_vec(), batch, noise(2000)];
+    for message in &messages {
+        assert_eq!(sender.send(&mut client.kcp, message), Ok(message.len()));
+    }
+
+    let mut received = Vec::new();
+    for step in 0..100 {
+        pump(step * 10, &mut client, &mut server);
+        while let Ok(message) = receiver.receive(&mut server.kcp) {
+            received.push(message);
+        }
+    }
+    assert_eq!(received, messages);
+
+    let stats = sender.stats();
+    assert_eq!(stats.messages, 4);
+    assert_eq!(stats.compressed, 2);
+    assert_eq!(stats.incompressible, 1);
+    assert!(stats.ratio() < 0.7, "ratio {}", stats.ratio());
+}
+
+#[test]
+fn small_and_incompressible_messages_are_sent_raw() {
+    let mut compressor = Compressor::new(CompressConfig::default());
+
+    let small = b"{\"id\":1}".to_vec();
+    let encoded = compressor.encode(&small);
+    assert_eq!(encoded[0], 0);
+    assert_eq!(&encoded[COMPRESS_FLAG_SIZE..], &small[..]);
+
+    let random = noise(4096);
+    let encoded = compressor.encode(&random);
+    assert_eq!(encoded[0], 0);
+    assert_eq!(encoded.len(), random.len() + COMPRESS_FLAG_SIZE);
+    assert_eq!(compressor.decode(&encoded).unwrap(), random);
+
+    assert_eq!(compressor.stats().compressed, 0);
+    assert_eq!(compressor.stats().incompressible, 1);
+    assert_eq!(compressor.decode(&[]), Err(KcpError::InvalidPacket));
+}
+
+#[test]
+fn dictionary_improves_small_messages() {
+    let dictionary = (100..140).flat_map(json_record).collect::<Vec<_>>();
+    let mut plain = Compressor::new(CompressConfig::default());
+    let mut primed = Compressor::new(CompressConfig {
+        dictionary: Some(dictionary.clone()),
+        ..Default::default()
+    });
+
+    for id in 0..50 {
+        let message = json_record(id);
+        let encoded = primed.encode(&message);
+        assert_eq!(encoded[0], COMPRESS_FLAG_LZ4 | COMPRESS_FLAG_DICT);
+        assert_eq!(primed.decode(&encoded).unwrap(), message);
+        plain.encode(&message);
+    }
+
+    assert!(
+        primed.stats().ratio() < plain.stats().ratio() * 0.7,
+        "primed {} plain {}",
+        primed.stats().ratio(),
+        plain.stats().ratio()
+    );
+}
+
+#[test]
+fn mismatched_or_corrupted_messages_are_rejected() {
+    let config = CompressConfig {
+        dictionary: Some(b"shared dictionary of the conversation".repeat(4)),
+        ..Default::default()
+    };
+    let mut sender = Compressor::new(config.clone());
+    let encoded = sender.encode(&json_record(1).repeat(4));
+
+    let other = Compressor::new(CompressConfig {
+        dictionary: Some(b"a different dictionary".repeat(4)),
+        ..Default::default()
+    });
+    assert_eq!(other.decode(&encoded), Err(KcpError::InvalidPacket));
+    let without = Compressor::new(CompressConfig::default());
+    assert_eq!(without.decode(&encoded), Err(KcpError::InvalidPacket));
+
+    let receiver = Compressor::new(config);
+    let mut flags = encoded.clone();
+    flags[0] |= 0x80;
+    assert_eq!(receiver.decode(&flags), Err(KcpError::InvalidPacket));
+    assert_eq!(receiver.decode(&encoded[..6]), Err(KcpError::InvalidPacket));
+    assert_eq!(
+        receiver.decode(&encoded[..encoded.len() - 3]),
+        Err(KcpError::InvalidPacket)
+    );
+}
+
+#[test]
+fn declared_size_is_bounded() {
+    let mut sender = Compressor::new(CompressConfig::default());
+    let message = vec![b'a'; 64 * 1024];
+    let encoded = sender.encode(&message);
+    assert!(encoded.len() < 1024);
+
+    let receiver = Compressor::new(CompressConfig {
+        max_size: 4096,
+        ..Default::default()
+    });
+    assert_eq!(receiver.decode(&encoded), Err(KcpError::MessageTooLarge));
+
+    // a forged size must not be trusted for the allocation
+    let mut forged = encoded;
+    forged[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        Compressor::new(CompressConfig::default()).decode(&forged),
+        Err(KcpError::MessageTooLarge)
+    );
+}
+
+#[test]
+fn declared_size_is_checked_against_the_block() {
+    let config = CompressConfig {
+        max_size: 1 << 20,
+        ..Default::default()
+    };
+    let mut sender = Compressor::new(config.clone());
+    let receiver = Compressor::new(config);
+
+    // the most compressible input still decodes
+    let zeros = vec![0; 1 << 20];
+    let encoded = sender.encode(&zeros);
+    assert_eq!(receiver.decode(&encoded).unwrap(), zeros);
+
+    // within max_size, but far more than the block can expand to
+    let encoded = sender.encode(&[b'a'; 200]);
+    let mut forged = encoded.clone();
+    forged[1..5].copy_from_slice(&(1u32 << 20).to_le_bytes());
+    assert_eq!(receiver.decode(&forged), Err(KcpError::InvalidPacket));
+}
+
+#[test]
+fn failed_sends_are_not_counted() {
+    let mut kcp = endpoint().kcp;
+    let mut compressor = Compressor::new(CompressConfig::default());
+
+    // more fragments than the receive window
+    let message = noise(200_000);
+    assert_eq!(
+        compressor.send(&mut kcp, &message),
+        Err(KcpError::WindowFull)
+    );
+    assert_eq!(compressor.stats().messages, 0);
+    assert_eq!(compressor.stats().input_bytes, 0);
+
+    assert_eq!(compressor.send(&mut kcp, b"ping"), Ok(4));
+    assert_eq!(compressor.stats().messages, 1);
+    assert_eq!(compressor.stats().output_bytes, 5);
+}
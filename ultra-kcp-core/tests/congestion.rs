@@ -0,0 +1,346 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{drain, Endpoint};
+use ultra_kcp_core::congestion::{
+    AckEvent, CongestionController, KcpClassic, LossEvent, NoCongestion, TimeoutEvent,
+};
+use ultra_kcp_core::constants::{IKCP_THRESH_INIT, IKCP_THRESH_MIN};
+
+/// The cwnd, ssthresh and incr updates of ikcp.c, transcribed as they are
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Ikcp {
+    cwnd: u32,
+    ssthresh: u32,
+    incr: u32,
+}
+
+impl Ikcp {
+    /// End of `ikcp_input`, when `snd_una` moved forward
+    fn input(&mut self, rmt_wnd: u32, mss: u32) {
+        if self.cwnd < rmt_wnd {
+            if self.cwnd < self.ssthresh {
+                self.cwnd += 1;
+                self.incr += mss;
+            } else {
+                if self.incr < mss {
+                    self.incr = mss;
+                }
+                self.incr += (mss * mss) / self.incr + (mss / 16);
+                if (self.cwnd + 1) * mss <= self.incr {
+                    self.cwnd = (self.incr + mss - 1) / if mss > 0 { mss } else { 1 };
+                }
+            }
+            if self.cwnd > rmt_wnd {
+                self.cwnd = rmt_wnd;
+                self.incr = rmt_wnd * mss;
+            }
+        }
+    }
+
+    /// End of `ikcp_flush`, after a fast retransmission
+    fn fast_resend(&mut self, inflight: u32, resent: u32, mss: u32) {
+        self.ssthresh = inflight / 2;
+        if self.ssthresh < IKCP_THRESH_MIN {
+            self.ssthresh = IKCP_THRESH_MIN;
+        }
+        self.cwnd = self.ssthresh + resent;
+        self.incr = self.cwnd * mss;
+    }
+
+    /// End of `ikcp_flush`, after a retransmission timeout
+    fn lost(&mut self, cwnd: u32, mss: u32) {
+        self.ssthresh = cwnd / 2;
+        if self.ssthresh < IKCP_THRESH_MIN {
+            self.ssthresh = IKCP_THRESH_MIN;
+        }
+        self.cwnd = 1;
+        self.incr = mss;
+    }
+}
+
+#[derive(Default)]
+struct Log {
+    acks: usize,
+    losses: usize,
+    timeouts: usize,
+    reference: Ikcp,
+}
+
+/// `KcpClassic` applying every event to the ikcp reference as well
+struct Checked {
+    classic: KcpClassic,
+    log: Arc<Mutex<Log>>,
+}
+
+impl Checked {
+    fn check(&self, log: &Log) {
+        let classic = Ikcp {
+            cwnd: self.classic.cwnd,
+            ssthresh: self.classic.ssthresh,
+            incr: self.classic.incr,
+        };
+        assert_eq!(classic, log.reference);
+    }
+}
+
+impl CongestionController for Checked {
+    fn on_ack(&mut self, event: &AckEvent) {
+        self.classic.on_ack(event);
+        let mut log = self.log.lock().unwrap();
+        log.acks += 1;
+        if event.una_advanced {
+            log.reference.input(event.rmt_wnd, event.mss);
+        }
+        self.check(&log);
+    }
+
+    fn on_loss(&mut self, event: &LossEvent) {
+        self.classic.on_loss(event);
+        let mut log = self.log.lock().unwrap();
+        log.losses += 1;
+        log.reference
+            .fast_resend(event.inflight, event.resent, event.mss);
+        self.check(&log);
+    }
+
+    fn on_timeout(&mut self, event: &TimeoutEvent) {
+        self.classic.on_timeout(event);
+        let mut log = self.log.lock().unwrap();
+        log.timeouts += 1;
+        log.reference.lost(event.window, event.mss);
+        self.check(&log);
+    }
+
+    fn window(&self) -> u32 {
+        self.classic.window()
+    }
+
+    fn ssthresh(&self) -> u32 {
+        self.classic.ssthresh()
+    }
+
+    fn incr(&self) -> u32 {
+        self.classic.incr()
+    }
+}
+
+#[test]
+fn classic_reproduces_ikcp_windows() {
+    let Endpoint {
+        kcp: mut sender,
+        wire: forward,
+    } = Endpoint::new(7);
+    let Endpoint {
+        kcp: mut receiver,
+        wire: backward,
+    } = Endpoint::new(7);
+    for kcp in [&mut sender, &mut receiver] {
+        kcp.set_nodelay(1, 10, 2, false);
+        kcp.set_wndsize(128, 128);
+    }
+
+    assert_eq!(sender.cwnd(), 1);
+    assert_eq!(sender.ssthresh(), IKCP_THRESH_INIT);
+    assert_eq!(sender.incr(), 0);
+    assert!(!sender.nocwnd());
+
+    let log = Arc::new(Mutex::new(Log {
+        reference: Ikcp {
+            cwnd: 1,
+            ssthresh: IKCP_THRESH_INIT,
+            incr: 0,
+        },
+        ..Default::default()
+    }));
+    sender.set_congestion(Box::new(Checked {
+        classic: KcpClassic::default(),
+        log: log.clone(),
+    }));
+
+    let message = vec![3; 1000];
+    let mut buf = vec![0; 1000];
+    let (mut sent, mut received, mut datagrams) = (0, 0, 0);
+    let mut max_cwnd = 0;
+    for now in 1..120_000 {
+        if now == 5000 {
+            // reconfiguring keeps the controller and its window
+            sender.set_nodelay(1, 10, 2, false);
+        }
+        while sent < 3000 && sender.wait_snd() < 256 {
+            sender.send(&message).unwrap();
+            sent += 1;
+        }
+        sender.update(now);
+        receiver.update(now);
+        for datagram in drain(&forward) {
+            // lose one datagram in 23, and a burst now and then for timeouts
+            datagrams += 1;
+            if datagrams % 23 == 0 || (datagrams % 500) < 6 {
+                continue;
+            }
+            receiver.input(&datagram).unwrap();
+        }
+        for datagram in drain(&backward) {
+            sender.input(&datagram).unwrap();
+        }
+        while receiver.receive(Some(&mut buf), false).is_ok() {
+            received += 1;
+        }
+        max_cwnd = max_cwnd.max(sender.cwnd());
+        if received == 3000 {
+            break;
+        }
+    }
+    assert_eq!(received, 3000);
+
+    let log = log.lock().unwrap();
+    assert!(log.acks > 0 && log.losses > 0 && log.timeouts > 0);
+    assert!(max_cwnd > IKCP_THRESH_INIT);
+    assert_eq!(sender.cwnd(), log.reference.cwnd);
+    assert_eq!(sender.ssthresh(), log.reference.ssthresh);
+    assert_eq!(sender.incr(), log.reference.incr);
+}
+
+#[test]
+fn classic_slow_start_then_avoidance() {
+    let mut classic = KcpClassic::default();
+    let ack = AckEvent {
+        una_advanced: true,
+        acked_segments: 1,
+        mss: 1376,
+        rmt_wnd: 128,
+        ..Default::default()
+    };
+
+    // slow start up to ssthresh, one segment per acknowledging input
+    classic.on_ack(&ack);
+    assert_eq!((classic.cwnd, classic.ssthresh, classic.incr), (2, 2, 1376));
+    // congestion avoidance, about one segment per window of ACKs
+    classic.on_ack(&ack);
+    assert_eq!((classic.cwnd, classic.incr), (2, 1376 + 1376 + 86));
+    classic.on_ack(&ack);
+    assert_eq!((classic.cwnd, classic.incr), (2, 2838 + 667 + 86));
+    // incr reaches (cwnd + 1) * mss, rounded up to whole segments
+    classic.on_ack(&ack);
+    assert_eq!((classic.cwnd, classic.incr), (4, 3591 + 527 + 86));
+
+    // ACKs of out of order segments only do not grow the window
+    let cwnd = classic.cwnd;
+    classic.on_ack(&AckEvent {
+        una_advanced: false,
+        ..ack
+    });
+    assert_eq!(classic.cwnd, cwnd);
+
+    // the remote window caps it
+    for _ in 0..10_000 {
+        classic.on_ack(&AckEvent { rmt_wnd: 16, ..ack });
+    }
+    assert_eq!(classic.cwnd, 16);
+
+    classic.on_loss(&LossEvent {
+        inflight: 12,
+        resent: 2,
+        mss: 1376,
+        ..Default::default()
+    });
+    assert_eq!((classic.cwnd, classic.ssthresh), (8, 6));
+
+    classic.on_timeout(&TimeoutEvent {
+        window: 8,
+        mss: 1376,
+        ..Default::default()
+    });
+    assert_eq!((classic.cwnd, classic.ssthresh, classic.incr), (1, 4, 1376));
+}
+
+/// Controller holding the window at a fixed size
+struct Fixed(u32);
+
+impl CongestionController for Fixed {
+    fn on_ack(&mut self, _event: &AckEvent) {}
+
+    fn on_loss(&mut self, _event: &LossEvent) {}
+
+    fn on_timeout(&mut self, _event: &TimeoutEvent) {}
+
+    fn window(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Segments the sender puts on the wire in its first flush, out of `queued`
+fn first_flight(nocwnd: bool, queued: usize) -> usize {
+    first_flight_with(nocwnd, queued, None)
+}
+
+fn first_flight_with(
+    nocwnd: bool,
+    queued: usize,
+    congestion: Option<Box<dyn CongestionController>>,
+) -> usize {
+    let mut sender = Endpoint::new(7).kcp;
+    if let Some(congestion) = congestion {
+        sender.set_congestion(congestion);
+    }
+    sender.set_nodelay(0, 10, 0, nocwnd);
+    sender.set_wndsize(64, 128);
+    for _ in 0..queued {
+        sender.send(&[1; 100]).unwrap();
+    }
+    sender.update(0);
+    sender.snd_nxt as usize
+}
+
+#[test]
+fn nocwnd_ignores_cwnd() {
+    assert_eq!(first_flight(false, 100), 1);
+    // limited by the send window only
+    assert_eq!(first_flight(true, 100), 64);
+    assert_eq!(first_flight(true, 10), 10);
+
+    let mut kcp = Endpoint::new(7).kcp;
+    kcp.set_nodelay(1, 10, 2, true);
+    assert!(kcp.nocwnd());
+    // the controller keeps tracking its window, as in ikcp
+    assert_eq!(kcp.cwnd(), 1);
+    assert_eq!((kcp.ssthresh(), kcp.incr()), (IKCP_THRESH_INIT, 0));
+    kcp.set_nodelay(1, 10, 2, false);
+    assert!(!kcp.nocwnd());
+    assert_eq!(kcp.cwnd(), 1);
+}
+
+#[test]
+fn set_nodelay_keeps_the_controller() {
+    assert_eq!(first_flight_with(false, 100, Some(Box::new(Fixed(5)))), 5);
+    assert_eq!(first_flight_with(true, 100, Some(Box::new(Fixed(5)))), 64);
+
+    let mut kcp = Endpoint::new(7).kcp;
+    kcp.set_congestion(Box::new(Fixed(5)));
+    kcp.set_nodelay(1, 10, 2, true);
+    kcp.set_nodelay(0, 100, 0, false);
+    assert_eq!(kcp.cwnd(), 5);
+}
+
+#[test]
+fn no_congestion_has_no_window() {
+    assert_eq!(
+        first_flight_with(false, 100, Some(Box::new(NoCongestion))),
+        64
+    );
+
+    let mut kcp = Endpoint::new(7).kcp;
+    kcp.set_congestion(Box::new(NoCongestion));
+    // an unbounded window is not the nocwnd setting
+    assert!(!kcp.nocwnd());
+    assert_eq!(kcp.cwnd(), u32::MAX);
+    assert_eq!((kcp.ssthresh(), kcp.incr()), (0, 0));
+
+    // losses and timeouts leave it alone
+    let mut controller = NoCongestion;
+    controller.on_timeout(&TimeoutEvent::default());
+    controller.on_loss(&LossEvent::default());
+    assert_eq!(controller.window(), u32::MAX);
+}
@@ -0,0 +1,165 @@
+use std::net::SocketAddr;
+
+use ultra_kcp_core::constants::KcpError;
+use ultra_kcp_core::cookie::{
+    echo, hello, is_handshake, CookieConfig, CookieDrop, CookieGuard, CookieVerdict,
+    COOKIE_CHALLENGE_SIZE,
+};
+
+const SECRET: [u8; 32] = [3; 32];
+const CONV: u32 = 0x1234;
+
+fn client() -> SocketAddr {
+    "192.0.2.10:40000".parse().unwrap()
+}
+
+fn guard() -> CookieGuard {
+    CookieGuard::new(
+        &SECRET,
+        CookieConfig {
+            lifetime: 5000,
+            rate: 10,
+            burst: 3,
+        },
+    )
+}
+
+fn challenge(guard: &mut CookieGuard, addr: SocketAddr, conv: u32, current: u32) -> Vec<u8> {
+    let hello = hello(conv);
+    match guard.handle(addr, &hello, current) {
+        CookieVerdict::Reply(challenge) => {
+            assert!(challenge.len() <= hello.len());
+            challenge
+        }
+        verdict => panic!("unexpected {verdict:?}"),
+    }
+}
+
+#[test]
+fn handshake_accepts_conversation() {
+    let mut guard = guard();
+    let challenge = challenge(&mut guard, client(), CONV, 100);
+    assert!(is_handshake(&challenge));
+    assert_eq!(challenge.len(), COOKIE_CHALLENGE_SIZE);
+
+    let echo = echo(&challenge, CONV).unwrap();
+    assert_eq!(
+        guard.handle(client(), &echo, 200),
+        CookieVerdict::Accept(CONV)
+    );
+    assert_eq!(guard.stats().challenges, 1);
+    assert_eq!(guard.stats().accepted, 1);
+}
+
+#[test]
+fn cookie_is_bound_to_address_and_conversation() {
+    let mut guard = guard();
+    let challenge = challenge(&mut guard, client(), CONV, 100);
+    let echo_packet = echo(&challenge, CONV).unwrap();
+
+    let spoofed: SocketAddr = "192.0.2.10:40001".parse().unwrap();
+    assert_eq!(
+        guard.handle(spoofed, &echo_packet, 200),
+        CookieVerdict::Drop(CookieDrop::InvalidCookie)
+    );
+
+    let mut other_conv = echo_packet.clone();
+    other_conv[5..9].copy_from_slice(&(CONV + 1).to_le_bytes());
+    assert_eq!(
+        guard.handle(client(), &other_conv, 200),
+        CookieVerdict::Drop(CookieDrop::InvalidCookie)
+    );
+
+    for i in 9..echo_packet.len() {
+        let mut tampered = echo_packet.clone();
+        tampered[i] ^= 1;
+        assert_eq!(
+            guard.handle(client(), &tampered, 200),
+            CookieVerdict::Drop(CookieDrop::InvalidCookie)
+        );
+    }
+    assert_eq!(echo(&challenge, CONV + 1), Err(KcpError::InvalidPacket));
+}
+
+#[test]
+fn cookie_expires() {
+    let mut guard = guard();
+    let start = u32::MAX - 1000;
+    let echo = echo(&challenge(&mut guard, client(), CONV, start), CONV).unwrap();
+
+    assert_eq!(
+        guard.handle(client(), &echo, start.wrapping_add(5001)),
+        CookieVerdict::Drop(CookieDrop::Expired)
+    );
+    assert_eq!(
+        guard.handle(client(), &echo, start.wrapping_add(5000)),
+        CookieVerdict::Accept(CONV)
+    );
+}
+
+#[test]
+fn malformed_packets_are_dropped() {
+    let mut guard = guard();
+    let challenge = challenge(&mut guard, client(), CONV, 0);
+    let kcp_datagram = [0x34, 0x12, 0, 0, 81, 0, 32, 0];
+
+    for packet in [
+        &kcp_datagram[..],
+        &hello(CONV)[..20],
+        &challenge[..],
+        &echo(&challenge, CONV).unwrap()[..COOKIE_CHALLENGE_SIZE - 1],
+    ] {
+        assert_eq!(
+            guard.handle(client(), packet, 0),
+            CookieVerdict::Drop(CookieDrop::Malformed)
+        );
+    }
+    assert_eq!(guard.stats().malformed, 4);
+}
+
+#[test]
+fn new_conversations_are_rate_limited() {
+    let mut guard = guard();
+    let accept = |guard: &mut CookieGuard, conv: u32, current: u32| {
+        let echo = echo(&challenge(guard, client(), conv, current), conv).unwrap();
+        guard.handle(client(), &echo, current)
+    };
+
+    // burst of 3, then one conversation every 100ms
+    for conv in 0..3 {
+        assert_eq!(accept(&mut guard, conv, 0), CookieVerdict::Accept(conv));
+    }
+    assert_eq!(
+        accept(&mut guard, 3, 50),
+        CookieVerdict::Drop(CookieDrop::RateLimited)
+    );
+    assert_eq!(accept(&mut guard, 4, 100), CookieVerdict::Accept(4));
+    assert_eq!(
+        accept(&mut guard, 5, 150),
+        CookieVerdict::Drop(CookieDrop::RateLimited)
+    );
+    assert_eq!(guard.stats().rate_limited, 2);
+}
+
+#[test]
+fn rotated_secret_honours_issued_cookies() {
+    let mut guard = guard();
+    let old = echo(&challenge(&mut guard, client(), CONV, 0), CONV).unwrap();
+
+    guard.rotate_secret(&[4; 32]);
+    let new = echo(&challenge(&mut guard, client(), CONV + 1, 10), CONV + 1).unwrap();
+    assert_eq!(
+        guard.handle(client(), &old, 20),
+        CookieVerdict::Accept(CONV)
+    );
+    assert_eq!(
+        guard.handle(client(), &new, 20),
+        CookieVerdict::Accept(CONV + 1)
+    );
+
+    guard.rotate_secret(&[5; 32]);
+    assert_eq!(
+        guard.handle(client(), &old, 30),
+        CookieVerdict::Drop(CookieDrop::InvalidCookie)
+    );
+}
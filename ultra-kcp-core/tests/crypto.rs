@@ -0,0 +1,176 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{drain, receive_all, Wire};
+use ultra_kcp_core::constants::KcpError;
+use ultra_kcp_core::crypto::{
+    CryptoRole, CryptoSession, SealedOutput, CRYPTO_OVERHEAD, CRYPTO_REPLAY_WINDOW,
+};
+use ultra_kcp_core::kcp::KcpControl;
+
+const KEY: [u8; 32] = [7; 32];
+const NEXT_KEY: [u8; 32] = [9; 32];
+
+/// Control block sealing its output with `session`
+struct Endpoint {
+    kcp: KcpControl,
+    session: Arc<Mutex<CryptoSession>>,
+    wire: Wire,
+}
+
+impl Endpoint {
+    fn new(role: CryptoRole) -> Self {
+        let session = Arc::new(Mutex::new(CryptoSession::new(&KEY, role)));
+        let common::Endpoint { mut kcp, wire } =
+            common::Endpoint::wrapped(1, |output| SealedOutput::new(session.clone(), output));
+        kcp.set_nodelay(1, 10, 2, true);
+        Self { kcp, session, wire }
+    }
+
+    fn input(&mut self, packet: &[u8]) -> Result<(), KcpError> {
+        self.session.lock().unwrap().input(&mut self.kcp, packet)
+    }
+}
+
+fn pump(current: u32, a: &mut Endpoint, b: &mut Endpoint) {
+    a.kcp.update(current);
+    b.kcp.update(current);
+    for packet in drain(&a.wire) {
+        b.input(&packet).unwrap();
+    }
+    for packet in drain(&b.wire) {
+        a.input(&packet).unwrap();
+    }
+}
+
+fn sealed_packet(session: &mut CryptoSession) -> Vec<u8> {
+    session.seal(b"sealed kcp datagram").unwrap()
+}
+
+#[test]
+fn transfer_is_sealed_end_to_end() {
+    let mut client = Endpoint::new(CryptoRole::Initiator);
+    let mut server = Endpoint::new(CryptoRole::Responder);
+    let secret = b"the treasure is buried under the third palm tree".to_vec();
+
+    client.kcp.send(&secret).unwrap();
+    client.kcp.update(0);
+    let packets = client.wire.lock().unwrap().clone();
+    assert!(!packets.is_empty());
+    assert!(packets
+        .iter()
+        .all(|packet| !packet.windows(8).any(|w| secret.windows(8).any(|s| s == w))));
+
+    let mut received = Vec::new();
+    for current in (10..1000).step_by(10) {
+        pump(current, &mut client, &mut server);
+        received.extend(receive_all(&mut server.kcp));
+    }
+    assert_eq!(received, vec![secret.clone()]);
+    // one segment: KCP header + payload + crypto overhead
+    assert_eq!(packets[0].len(), 24 + secret.len() + CRYPTO_OVERHEAD);
+}
+
+#[test]
+fn tampered_packets_are_rejected() {
+    let mut client = CryptoSession::new(&KEY, CryptoRole::Initiator);
+    let mut server = Endpoint::new(CryptoRole::Responder);
+    let packet = sealed_packet(&mut client);
+
+    for i in 0..packet.len() {
+        let mut tampered = packet.clone();
+        tampered[i] ^= 0x20;
+        assert_eq!(server.input(&tampered), Err(KcpError::AuthFailed));
+    }
+    assert_eq!(
+        server.input(&packet[..CRYPTO_OVERHEAD - 1]),
+        Err(KcpError::AuthFailed)
+    );
+    assert_eq!(server.kcp.rcv_nxt, 0);
+    assert_eq!(
+        server.session.lock().unwrap().stats().auth_failures,
+        packet.len() as u64 + 1
+    );
+}
+
+#[test]
+fn reflected_packets_are_rejected() {
+    let mut client = CryptoSession::new(&KEY, CryptoRole::Initiator);
+    let packet = sealed_packet(&mut client);
+    assert_eq!(client.open(&packet), Err(KcpError::AuthFailed));
+}
+
+#[test]
+fn replayed_packets_are_rejected() {
+    let mut client = CryptoSession::new(&KEY, CryptoRole::Initiator);
+    let mut server = CryptoSession::new(&KEY, CryptoRole::Responder);
+
+    let packets: Vec<_> = (0..CRYPTO_REPLAY_WINDOW + 10)
+        .map(|_| sealed_packet(&mut client))
+        .collect();
+
+    // out of order within the window is fine, once
+    assert!(server.open(&packets[5]).is_ok());
+    assert!(server.open(&packets[3]).is_ok());
+    assert_eq!(server.open(&packets[5]), Err(KcpError::Replayed));
+    assert_eq!(server.open(&packets[3]), Err(KcpError::Replayed));
+    assert!(server.open(&packets[4]).is_ok());
+
+    // anything older than the window is refused
+    assert!(server.open(packets.last().unwrap()).is_ok());
+    assert_eq!(server.open(&packets[6]), Err(KcpError::Replayed));
+    assert!(server.open(&packets[12]).is_ok());
+    assert_eq!(server.stats().replays, 3);
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let mut client = CryptoSession::new(&KEY, CryptoRole::Initiator);
+    let mut server = CryptoSession::new(&KEY, CryptoRole::Responder);
+    client.prepare_key(&NEXT_KEY);
+    client.rotate().unwrap();
+
+    // the server did not prepare the next key
+    assert_eq!(
+        server.open(&sealed_packet(&mut client)),
+        Err(KcpError::AuthFailed)
+    );
+    assert_eq!(server.rotate(), Err(KcpError::InvalidConfig));
+}
+
+#[test]
+fn rotation_keeps_the_session() {
+    let mut client = Endpoint::new(CryptoRole::Initiator);
+    let mut server = Endpoint::new(CryptoRole::Responder);
+    client.session.lock().unwrap().prepare_key(&NEXT_KEY);
+    server.session.lock().unwrap().prepare_key(&NEXT_KEY);
+
+    let messages: Vec<Vec<u8>> = (0..200u32).map(|i| i.to_le_bytes().repeat(100)).collect();
+    let mut received = Vec::new();
+    let mut current = 0;
+    for (i, message) in messages.iter().enumerate() {
+        client.kcp.send(message).unwrap();
+        if i == 100 {
+            // datagrams sealed with the old key are still on the wire
+            client.kcp.update(current);
+            client.session.lock().unwrap().rotate().unwrap();
+        }
+        current += 10;
+        pump(current, &mut client, &mut server);
+        received.extend(receive_all(&mut server.kcp));
+    }
+    for _ in 0..100 {
+        current += 10;
+        pump(current, &mut client, &mut server);
+        received.extend(receive_all(&mut server.kcp));
+    }
+
+    assert_eq!(received, messages);
+    for endpoint in [&client, &server] {
+        let session = endpoint.session.lock().unwrap();
+        assert_eq!(session.key_id(), 1);
+        assert_eq!(session.stats().rotations, 1);
+        assert_eq!(session.stats().auth_failures, 0);
+    }
+}
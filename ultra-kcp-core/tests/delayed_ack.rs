@@ -0,0 +1,150 @@
+mod common;
+
+use common::{segments, Endpoint, Wire};
+use ultra_kcp_core::constants::{Command, IKCP_OVERHEAD};
+use ultra_kcp_core::delayed_ack::{AckStats, DelayedAck};
+use ultra_kcp_core::kcp::Segment;
+
+/// Receiver flushing every 100 ms, already updated once at 0
+fn receiver(policy: Option<DelayedAck>) -> Endpoint {
+    let mut endpoint = Endpoint::new(3);
+    endpoint.kcp.set_nodelay(0, 100, 0, false);
+    endpoint.kcp.set_delayed_ack(policy);
+    endpoint.kcp.update(0);
+    endpoint
+}
+
+/// A push segment for `sn`, sent at `ts`
+fn push(sn: u32, ts: u32) -> Vec<u8> {
+    let mut buf = vec![0; IKCP_OVERHEAD as usize + 4];
+    Segment {
+        conv: 3,
+        cmd: Command::Push as u32,
+        wnd: 128,
+        ts,
+        sn,
+        len: 4,
+        ..Default::default()
+    }
+    .encode_header(&mut buf);
+    buf
+}
+
+/// The (sn, ts) of the ACK segments on `wire`
+fn acks(wire: &Wire) -> Vec<(u32, u32)> {
+    segments(wire)
+        .into_iter()
+        .map(|(segment, _)| {
+            assert_eq!(segment.cmd, Command::Ack as u32);
+            (segment.sn, segment.ts)
+        })
+        .collect()
+}
+
+fn held(every: u32, max_delay: u32, coalesce: bool) -> Option<DelayedAck> {
+    Some(DelayedAck {
+        every,
+        max_delay,
+        coalesce,
+    })
+}
+
+#[test]
+fn acks_wait_for_every_segments() {
+    let Endpoint { mut kcp, wire } = receiver(held(3, 1000, false));
+    kcp.input(&push(0, 0)).unwrap();
+    kcp.input(&push(1, 0)).unwrap();
+    kcp.update(100);
+    assert_eq!(acks(&wire), []);
+
+    kcp.input(&push(2, 50)).unwrap();
+    kcp.update(200);
+    assert_eq!(acks(&wire), [(0, 0), (1, 0), (2, 50)]);
+}
+
+#[test]
+fn acks_leave_at_max_delay_between_intervals() {
+    let Endpoint { mut kcp, wire } = receiver(held(100, 25, false));
+    kcp.update(10);
+    kcp.input(&push(0, 7)).unwrap();
+    // the deadline comes before the next interval at 100
+    assert_eq!(kcp.check(10), 35);
+    assert_eq!(kcp.check(30), 35);
+    assert_eq!(kcp.check(40), 40);
+
+    kcp.update(34);
+    assert_eq!(acks(&wire), []);
+    kcp.update(35);
+    assert_eq!(acks(&wire), [(0, 7)]);
+    // nothing pending, back to the interval
+    assert_eq!(kcp.check(35), 100);
+}
+
+#[test]
+fn check_ignores_the_deadline_without_pending_acks() {
+    let idle = receiver(held(100, 25, false));
+    assert_eq!(idle.kcp.check(10), 100);
+
+    // without a policy ACKs simply leave on the next interval
+    let Endpoint { mut kcp, wire } = receiver(None);
+    kcp.input(&push(0, 0)).unwrap();
+    assert_eq!(kcp.check(10), 100);
+    kcp.update(100);
+    assert_eq!(acks(&wire), [(0, 0)]);
+}
+
+#[test]
+fn gap_is_acknowledged_on_the_next_flush() {
+    let Endpoint { mut kcp, wire } = receiver(held(100, 1000, false));
+    kcp.input(&push(0, 0)).unwrap();
+    kcp.update(100);
+    assert_eq!(acks(&wire), []);
+
+    // sn 1 is missing, the sender needs to hear about it
+    kcp.input(&push(2, 90)).unwrap();
+    kcp.update(200);
+    assert_eq!(acks(&wire), [(0, 0), (2, 90)]);
+
+    // a duplicate too
+    kcp.input(&push(0, 150)).unwrap();
+    kcp.update(300);
+    assert_eq!(acks(&wire), [(0, 150)]);
+}
+
+#[test]
+fn coalescing_keeps_acks_carrying_information() {
+    let Endpoint { mut kcp, wire } = receiver(held(100, 1000, true));
+    for (sn, ts) in [(0, 1), (1, 2), (2, 3), (5, 4), (5, 6)] {
+        kcp.input(&push(sn, ts)).unwrap();
+    }
+    kcp.update(100);
+    // una covers 0..3, the highest of them echoes its timestamp, and the
+    // duplicate for 5 keeps the latest one
+    assert_eq!(acks(&wire), [(5, 6), (2, 3)]);
+    assert_eq!(
+        *kcp.ack_stats(),
+        AckStats {
+            acks_requested: 5,
+            acks_sent: 2,
+            acks_saved: 3,
+        }
+    );
+}
+
+#[test]
+fn nothing_is_saved_without_coalescing() {
+    let Endpoint { mut kcp, wire } = receiver(None);
+    for (sn, ts) in [(0, 1), (1, 2), (1, 3)] {
+        kcp.input(&push(sn, ts)).unwrap();
+    }
+    kcp.update(100);
+    assert_eq!(acks(&wire), [(0, 1), (1, 2), (1, 3)]);
+    assert_eq!(
+        *kcp.ack_stats(),
+        AckStats {
+            acks_requested: 3,
+            acks_sent: 3,
+            acks_saved: 0,
+        }
+    );
+}
@@ -0,0 +1,300 @@
+mod common;
+
+use common::{drain, receive_all, Endpoint};
+use ultra_kcp_core::constants::KcpError;
+use ultra_kcp_core::fec::{
+    FecDecoder, FecEncoder, FecOutput, FecStats, FEC_HEADER_SIZE, FEC_OVERHEAD,
+};
+
+const DATA_SHARDS: usize = 4;
+const PARITY_SHARDS: usize = 2;
+
+/// Datagrams of different sizes, so that shards need padding
+fn datagrams(count: usize) -> Vec<Vec<u8>> {
+    (0..count)
+        .map(|i| (0..100 + i * 37).map(|b| (b * 7 + i) as u8).collect())
+        .collect()
+}
+
+/// Every packet the encoder emits for `datagrams`, in order
+fn encode(datagrams: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut encoder = FecEncoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    let mut packets = Vec::new();
+    for datagram in datagrams {
+        encoder
+            .encode(datagram, |packet| packets.push(packet.to_vec()))
+            .unwrap();
+    }
+    packets
+}
+
+/// Decode `packets`, skipping the ones at the `lost` positions
+fn decode(decoder: &mut FecDecoder, packets: &[Vec<u8>], lost: &[usize]) -> Vec<Vec<u8>> {
+    let mut delivered = Vec::new();
+    for (i, packet) in packets.iter().enumerate() {
+        if lost.contains(&i) {
+            continue;
+        }
+        decoder
+            .decode(packet, |datagram| delivered.push(datagram.to_vec()))
+            .unwrap();
+    }
+    delivered.sort();
+    delivered
+}
+
+fn sorted(mut datagrams: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    datagrams.sort();
+    datagrams
+}
+
+#[test]
+fn groups_carry_data_then_parity() {
+    let input = datagrams(DATA_SHARDS);
+    let packets = encode(&input);
+    assert_eq!(packets.len(), DATA_SHARDS + PARITY_SHARDS);
+    for (i, (packet, datagram)) in packets.iter().zip(&input).enumerate() {
+        assert_eq!(&packet[..4], &(i as u32).to_le_bytes());
+        assert_eq!(&packet[4..6], &0xf1u16.to_le_bytes());
+        assert_eq!(packet.len(), datagram.len() + FEC_OVERHEAD);
+        assert_eq!(&packet[FEC_OVERHEAD..], &datagram[..]);
+    }
+    // parity shards are as large as the largest data shard
+    let largest = input.iter().map(Vec::len).max().unwrap() + FEC_OVERHEAD;
+    for packet in &packets[DATA_SHARDS..] {
+        assert_eq!(&packet[4..6], &0xf2u16.to_le_bytes());
+        assert_eq!(packet.len(), largest);
+    }
+}
+
+#[test]
+fn round_trip_without_loss() {
+    let input = datagrams(3 * DATA_SHARDS);
+    let packets = encode(&input);
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    assert_eq!(decode(&mut decoder, &packets, &[]), sorted(input));
+    assert_eq!(
+        *decoder.stats(),
+        FecStats {
+            data_received: 12,
+            parity_received: 6,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn recovers_up_to_parity_lost_shards() {
+    let input = datagrams(DATA_SHARDS);
+    let packets = encode(&input);
+    let total = DATA_SHARDS + PARITY_SHARDS;
+    for first in 0..total {
+        for second in first + 1..total {
+            let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+            let delivered = decode(&mut decoder, &packets, &[first, second]);
+            assert_eq!(delivered, sorted(input.clone()), "lost {first} {second}");
+
+            let lost_data = [first, second].iter().filter(|&&i| i < DATA_SHARDS).count() as u64;
+            let stats = decoder.stats();
+            assert_eq!(stats.recovered_datagrams, lost_data);
+            assert_eq!(stats.recovered_groups, (lost_data > 0) as u64);
+            assert_eq!(stats.unrecoverable_groups, 0);
+        }
+    }
+}
+
+#[test]
+fn duplicates_are_delivered_once() {
+    let input = datagrams(DATA_SHARDS);
+    let packets = encode(&input);
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    let mut delivered = 0;
+    for packet in packets.iter().chain(&packets) {
+        decoder.decode(packet, |_| delivered += 1).unwrap();
+    }
+    assert_eq!(delivered, DATA_SHARDS);
+
+    // a data shard arriving after its group was rebuilt is not delivered again
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    let rebuilt = decode(&mut decoder, &packets, &[0]);
+    assert_eq!(rebuilt.len(), DATA_SHARDS);
+    decoder
+        .decode(&packets[0], |_| panic!("delivered twice"))
+        .unwrap();
+}
+
+#[test]
+fn flush_protects_the_tail_of_a_burst() {
+    let input = datagrams(DATA_SHARDS + 2);
+    let mut encoder = FecEncoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    let mut packets = Vec::new();
+    for datagram in &input {
+        encoder
+            .encode(datagram, |packet| packets.push(packet.to_vec()))
+            .unwrap();
+    }
+    encoder.flush(|packet| packets.push(packet.to_vec()));
+    // nothing is pending anymore
+    encoder.flush(|_| panic!("flushed twice"));
+    assert_eq!(packets.len(), input.len() + 2 * PARITY_SHARDS);
+
+    // the padding shards are skipped, parity ends the second group
+    let total = DATA_SHARDS + PARITY_SHARDS;
+    for (packet, seq) in packets[total + 2..].iter().zip(total + DATA_SHARDS..) {
+        assert_eq!(&packet[..4], &(seq as u32).to_le_bytes());
+        assert_eq!(&packet[4..6], &0xf3u16.to_le_bytes());
+        assert_eq!(&packet[6..8], &2u16.to_le_bytes());
+    }
+    // and the next group starts after it
+    let mut next = Vec::new();
+    encoder
+        .encode(&input[0], |packet| next.push(packet.to_vec()))
+        .unwrap();
+    assert_eq!(&next[0][..4], &(2 * total as u32).to_le_bytes());
+
+    // both datagrams of the tail are lost and rebuilt
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    let delivered = decode(&mut decoder, &packets, &[total, total + 1]);
+    assert_eq!(delivered, sorted(input));
+    let stats = decoder.stats();
+    assert_eq!(stats.recovered_datagrams, 2);
+    assert_eq!(stats.parity_received, 2 * PARITY_SHARDS as u64);
+}
+
+#[test]
+fn flush_does_nothing_between_groups() {
+    let mut encoder = FecEncoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    encoder.flush(|_| panic!("nothing to flush"));
+    for datagram in datagrams(DATA_SHARDS) {
+        encoder.encode(&datagram, |_| {}).unwrap();
+    }
+    encoder.flush(|_| panic!("parity already sent"));
+}
+
+/// Control block protecting its output with FEC
+fn protected(conv: u32) -> Endpoint {
+    let mut endpoint = Endpoint::wrapped(conv, |output| {
+        FecOutput::new(FecEncoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap(), output)
+    });
+    endpoint.kcp.set_nodelay(1, 10, 2, true);
+    endpoint
+}
+
+#[test]
+fn fec_output_protects_every_flush() {
+    let Endpoint { mut kcp, wire } = protected(1);
+    // three fragments, one datagram each
+    let message: Vec<u8> = (0..3000).map(|b| b as u8).collect();
+    kcp.send(&message).unwrap();
+    kcp.update(0);
+    let packets = drain(&wire);
+    assert_eq!(packets.len(), 3 + PARITY_SHARDS);
+
+    // the parity sent at the end of the flush rebuilds two lost fragments
+    let mut receiver = protected(1);
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    for packet in &packets[2..] {
+        decoder.input(&mut receiver.kcp, packet).unwrap();
+    }
+    assert_eq!(decoder.stats().recovered_datagrams, 2);
+    assert_eq!(receive_all(&mut receiver.kcp), [message]);
+
+    // FEC errors come first, then those of the control block
+    assert_eq!(
+        decoder.input(&mut receiver.kcp, &packets[0][..5]),
+        Err(KcpError::InvalidPacket)
+    );
+    let mut other_conv = protected(2);
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    assert!(decoder.input(&mut other_conv.kcp, &packets[0]).is_err());
+}
+
+#[test]
+fn groups_missing_too_many_shards_are_unrecoverable() {
+    let input = datagrams(3 * DATA_SHARDS);
+    let packets = encode(&input);
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    decoder.set_rx_limit(2);
+
+    // the first group loses three shards, more than parity can rebuild
+    let delivered = decode(&mut decoder, &packets, &[0, 1, 4]);
+    assert_eq!(delivered.len(), 3 * DATA_SHARDS - 2);
+    assert!(!delivered.contains(&input[0]) && !delivered.contains(&input[1]));
+    let stats = decoder.stats();
+    assert_eq!(stats.recovered_datagrams, 0);
+    // evicted by the third group while still incomplete
+    assert_eq!(stats.unrecoverable_groups, 1);
+}
+
+#[test]
+fn inconsistent_parity_is_unrecoverable() {
+    let input = datagrams(DATA_SHARDS);
+    let mut packets = encode(&input);
+    // parity shorter than the data shards it should cover
+    packets[DATA_SHARDS].truncate(FEC_HEADER_SIZE + 10);
+    packets[DATA_SHARDS + 1].truncate(FEC_HEADER_SIZE + 10);
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    let delivered = decode(&mut decoder, &packets, &[DATA_SHARDS - 1]);
+    assert_eq!(delivered.len(), DATA_SHARDS - 1);
+    assert_eq!(decoder.stats().unrecoverable_groups, 1);
+}
+
+#[test]
+fn malformed_packets_are_rejected() {
+    let packets = encode(&datagrams(DATA_SHARDS));
+    let mut decoder = FecDecoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    let mut reject = |packet: &[u8]| decoder.decode(packet, |_| {});
+
+    assert_eq!(reject(&packets[0][..5]), Err(KcpError::InvalidPacket));
+    let mut unknown = packets[0].clone();
+    unknown[4] = 0;
+    assert_eq!(reject(&unknown), Err(KcpError::InvalidPacket));
+    // data type at a parity position and the reverse
+    let mut data_as_parity = packets[0].clone();
+    data_as_parity[4] = 0xf2;
+    assert_eq!(reject(&data_as_parity), Err(KcpError::InvalidPacket));
+    let mut parity_as_data = packets[DATA_SHARDS].clone();
+    parity_as_data[4] = 0xf1;
+    assert_eq!(reject(&parity_as_data), Err(KcpError::InvalidPacket));
+    // partial parity at a data position, or covering no or every data shard
+    let mut partial = packets[DATA_SHARDS].clone();
+    partial[4] = 0xf3;
+    partial.splice(FEC_HEADER_SIZE..FEC_HEADER_SIZE, 1u16.to_le_bytes());
+    let mut partial_as_data = partial.clone();
+    partial_as_data[..4].copy_from_slice(&0u32.to_le_bytes());
+    assert_eq!(reject(&partial_as_data), Err(KcpError::InvalidPacket));
+    for sent in [0, DATA_SHARDS as u16] {
+        let mut miscounted = partial.clone();
+        miscounted[FEC_HEADER_SIZE..FEC_OVERHEAD].copy_from_slice(&sent.to_le_bytes());
+        assert_eq!(reject(&miscounted), Err(KcpError::InvalidPacket));
+    }
+    assert_eq!(
+        reject(&partial[..FEC_HEADER_SIZE + 1]),
+        Err(KcpError::InvalidPacket)
+    );
+    // size field beyond the shard
+    let mut oversized = packets[1].clone();
+    oversized[FEC_HEADER_SIZE..FEC_OVERHEAD].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert_eq!(reject(&oversized), Err(KcpError::InvalidPacket));
+}
+
+#[test]
+fn invalid_shapes_are_rejected() {
+    assert!(matches!(
+        FecEncoder::new(0, 2),
+        Err(KcpError::InvalidConfig)
+    ));
+    assert!(matches!(
+        FecDecoder::new(4, 0),
+        Err(KcpError::InvalidConfig)
+    ));
+    assert!(matches!(
+        FecEncoder::new(200, 100),
+        Err(KcpError::InvalidConfig)
+    ));
+    let mut encoder = FecEncoder::new(DATA_SHARDS, PARITY_SHARDS).unwrap();
+    assert_eq!(
+        encoder.encode(&vec![0; u16::MAX as usize], |_| {}),
+        Err(KcpError::BufferTooSmall)
+    );
+}
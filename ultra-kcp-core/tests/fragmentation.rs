@@ -0,0 +1,242 @@
+mod common;
+
+use common::{pump, Endpoint};
+use proptest::prelude::*;
+use ultra_kcp_core::constants::{KcpError, IKCP_WND_RCV};
+
+/// Two endpoints connected back to back by a lossless wire
+struct Loopback {
+    sender: Endpoint,
+    receiver: Endpoint,
+    current: u32,
+}
+
+impl Loopback {
+    fn new(streaming: bool) -> Self {
+        let mut sender = Endpoint::new(7);
+        let mut receiver = Endpoint::new(7);
+        for kcp in [&mut sender.kcp, &mut receiver.kcp] {
+            kcp.streaming_mode = streaming;
+            kcp.set_nodelay(1, 10, 2, true);
+        }
+        Self {
+            sender,
+            receiver,
+            current: 0,
+        }
+    }
+
+    fn pump(&mut self) {
+        self.current += 10;
+        pump(self.current, &mut self.sender, &mut self.receiver);
+    }
+
+    /// Receive the next message, checking `peek_size` and a peeking receive
+    /// against the bytes actually returned
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let size = match self.receiver.kcp.peek_size() {
+            Ok(size) => size,
+            Err(KcpError::QueueEmpty | KcpError::IncompleteMessage) => return None,
+            Err(err) => panic!("unexpected {err:?}"),
+        };
+
+        if size > 0 {
+            let mut short = vec![0; size - 1];
+            assert_eq!(
+                self.receiver.kcp.receive(Some(&mut short), false),
+                Err(KcpError::BufferTooSmall)
+            );
+        }
+
+        let mut peeked = vec![0; size];
+        assert_eq!(self.receiver.kcp.receive(Some(&mut peeked), true), Ok(size));
+
+        let mut buf = vec![0; size + 16];
+        let n = self.receiver.kcp.receive(Some(&mut buf), false).unwrap();
+        assert_eq!(n, size);
+        buf.truncate(n);
+        assert_eq!(buf, peeked);
+        Some(buf)
+    }
+
+    /// Receive and pump until `expected` messages arrived or `rounds` ran out
+    fn run(&mut self, rounds: usize, received: &mut Vec<Vec<u8>>, expected: usize) {
+        for _ in 0..rounds {
+            while let Some(message) = self.receive() {
+                received.push(message);
+            }
+            if received.len() >= expected {
+                break;
+            }
+            self.pump();
+        }
+    }
+}
+
+const MSS: usize = 1376;
+
+/// Largest message accepted by `send` with the default receive window
+const MAX_MESSAGE: usize = (IKCP_WND_RCV as usize - 1) * MSS;
+
+/// Message sizes concentrated around multiples of the mss
+fn message_size() -> impl Strategy<Value = usize> {
+    prop_oneof![
+        0..=64usize,
+        (1..8usize, -2..=2isize).prop_map(|(k, d)| (k * MSS).saturating_add_signed(d)),
+        MAX_MESSAGE - 4..=MAX_MESSAGE,
+        0..=MAX_MESSAGE,
+    ]
+}
+
+fn payload(size: usize, seed: u8) -> Vec<u8> {
+    (0..size)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+fn messages() -> impl Strategy<Value = Vec<Vec<u8>>> {
+    prop::collection::vec(
+        (message_size(), any::<u8>()).prop_map(|(size, seed)| payload(size, seed)),
+        1..12,
+    )
+}
+
+/// Messages spanning up to a few times the receive window, around the
+/// saturation of the one byte fragment counter
+fn large_messages() -> impl Strategy<Value = Vec<Vec<u8>>> {
+    let size = prop_oneof![
+        0..=2 * MSS,
+        (250..260usize, -1..=1isize).prop_map(|(k, d)| (k * MSS).saturating_add_signed(d)),
+        MAX_MESSAGE..=4 * MAX_MESSAGE,
+    ];
+    prop::collection::vec(
+        (size, any::<u8>()).prop_map(|(size, seed)| payload(size, seed)),
+        1..5,
+    )
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn messages_arrive_intact_and_in_order(messages in messages()) {
+        let mut link = Loopback::new(false);
+        prop_assert_eq!(link.sender.kcp.mss as usize, MSS);
+
+        let mut received = Vec::new();
+        for message in &messages {
+            prop_assert_eq!(link.sender.kcp.send(message), Ok(message.len()));
+            link.run(1, &mut received, messages.len());
+        }
+        link.run(1000, &mut received, messages.len());
+        prop_assert_eq!(received, messages);
+    }
+
+    #[test]
+    fn stream_arrives_intact(messages in messages()) {
+        let mut link = Loopback::new(true);
+        for message in &messages {
+            prop_assert_eq!(link.sender.kcp.send(message), Ok(message.len()));
+        }
+
+        let expected = messages.concat();
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            while let Some(chunk) = link.receive() {
+                prop_assert!(chunk.len() <= MSS);
+                received.extend(chunk);
+            }
+            if received.len() >= expected.len() {
+                break;
+            }
+            link.pump();
+        }
+        prop_assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected(extra in 1..4 * MSS) {
+        let mut link = Loopback::new(false);
+        let message = vec![0; MAX_MESSAGE + extra];
+        prop_assert_eq!(link.sender.kcp.send(&message), Err(KcpError::WindowFull));
+        prop_assert_eq!(link.sender.kcp.wait_snd(), 0);
+    }
+
+    #[test]
+    fn slow_reader_fills_the_receive_window(messages in messages()) {
+        let mut link = Loopback::new(false);
+        for message in &messages {
+            prop_assert_eq!(link.sender.kcp.send(message), Ok(message.len()));
+        }
+
+        // let the receive queue fill up to the window before reading
+        for _ in 0..200 {
+            link.pump();
+            prop_assert!(link.receiver.kcp.rcv_queue.len() <= link.receiver.kcp.recv_window as usize);
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..2000 {
+            if let Some(message) = link.receive() {
+                received.push(message);
+            }
+            if received.len() == messages.len() {
+                break;
+            }
+            link.pump();
+        }
+        prop_assert_eq!(received, messages);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn extended_messages_arrive_intact(messages in large_messages()) {
+        let mut link = Loopback::new(false);
+        link.sender.kcp.set_extended_frg(true);
+        link.receiver.kcp.set_extended_frg(true);
+
+        for message in &messages {
+            prop_assert_eq!(link.sender.kcp.send(message), Ok(message.len()));
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..5000 {
+            while let Some(message) = link.receive() {
+                received.push(message);
+            }
+            if received.len() == messages.len() {
+                break;
+            }
+            link.pump();
+            prop_assert!(link.receiver.kcp.rcv_queue.len() <= link.receiver.kcp.recv_window as usize);
+        }
+        prop_assert_eq!(received, messages);
+    }
+
+    #[test]
+    fn extended_oversized_messages_are_dropped(
+        limit in MSS..4 * MSS,
+        excess in 1..2 * MSS,
+        seed in any::<u8>(),
+    ) {
+        let mut link = Loopback::new(false);
+        link.sender.kcp.set_extended_frg(true);
+        link.receiver.kcp.set_extended_frg(true);
+        link.receiver.kcp.set_max_message(limit);
+
+        let oversized = payload(limit + excess, seed);
+        let fitting = payload(limit, seed.wrapping_add(1));
+        prop_assert_eq!(link.sender.kcp.send(&oversized), Ok(oversized.len()));
+        prop_assert_eq!(link.sender.kcp.send(&fitting), Ok(fitting.len()));
+
+        link.sender.kcp.set_max_message(limit);
+        prop_assert_eq!(link.sender.kcp.send(&oversized), Err(KcpError::MessageTooLarge));
+
+        let mut received = Vec::new();
+        link.run(1000, &mut received, 1);
+        prop_assert_eq!(received, vec![fitting]);
+    }
+}
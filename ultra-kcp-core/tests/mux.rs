@@ -0,0 +1,482 @@
+mod common;
+
+use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::{pump, Endpoint};
+use ultra_kcp_core::constants::KcpError;
+use ultra_kcp_core::mux::{Mux, MuxCommand, MuxConfig, MuxRole, SharedMux};
+
+fn endpoint() -> Endpoint {
+    let mut endpoint = Endpoint::new(1);
+    endpoint.kcp.set_nodelay(1, 10, 2, true);
+    endpoint.kcp.set_wndsize(256, 256);
+    endpoint
+}
+
+/// Two multiplexers over a pair of control blocks, on a virtual clock
+struct Session {
+    client: Mux,
+    server: Mux,
+    a: Endpoint,
+    b: Endpoint,
+    now: u32,
+}
+
+impl Session {
+    fn new(config: MuxConfig) -> Self {
+        Self {
+            client: Mux::new(config.clone(), MuxRole::Initiator),
+            server: Mux::new(config, MuxRole::Responder),
+            a: endpoint(),
+            b: endpoint(),
+            now: 0,
+        }
+    }
+
+    /// Exchange frames for `ms` milliseconds
+    fn run(&mut self, ms: u32) {
+        for _ in 0..ms {
+            self.client.send(&mut self.a.kcp).unwrap();
+            self.server.send(&mut self.b.kcp).unwrap();
+            self.now += 1;
+            pump(self.now, &mut self.a, &mut self.b);
+            self.client.receive(&mut self.a.kcp).unwrap();
+            self.server.receive(&mut self.b.kcp).unwrap();
+        }
+    }
+}
+
+fn frame(command: MuxCommand, id: u32, body: &[u8]) -> Vec<u8> {
+    let mut frame = vec![command as u8];
+    frame.extend_from_slice(&id.to_le_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+#[test]
+fn streams_carry_data_both_ways_until_closed() {
+    let mut session = Session::new(MuxConfig::default());
+    let first = session.client.open().unwrap();
+    let second = session.client.open().unwrap();
+    assert_eq!((first, second), (1, 3));
+    session.client.write(first, b"hello").unwrap();
+    session.run(50);
+
+    assert_eq!(session.server.accept(), Some(first));
+    assert_eq!(session.server.accept(), Some(second));
+    assert_eq!(session.server.accept(), None);
+    let mut buf = [0; 16];
+    assert_eq!(session.server.read(first, &mut buf), Ok(5));
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(
+        session.server.read(second, &mut buf),
+        Err(KcpError::QueueEmpty)
+    );
+
+    let reply = session.server.open().unwrap();
+    assert_eq!(reply, 2);
+    session.server.write(first, b"world").unwrap();
+    session.server.close(first).unwrap();
+    session.run(50);
+
+    assert_eq!(session.client.accept(), Some(reply));
+    assert_eq!(session.client.read(first, &mut buf), Ok(5));
+    assert_eq!(&buf[..5], b"world");
+    assert_eq!(session.client.read(first, &mut buf), Ok(0));
+    assert_eq!(
+        session.server.write(first, b"late"),
+        Err(KcpError::StreamClosed)
+    );
+
+    session.client.release(first).unwrap();
+    assert_eq!(session.client.stream_count(), 2);
+    assert_eq!(
+        session.client.read(first, &mut buf),
+        Err(KcpError::UnknownStream)
+    );
+    session.run(50);
+    // the server sees the end of the stream, then forgets it once released
+    assert_eq!(session.server.read(first, &mut buf), Ok(0));
+    session.server.release(first).unwrap();
+    assert_eq!(session.server.stream_count(), 2);
+
+    let stats = session.client.stats();
+    assert_eq!((stats.opened, stats.accepted), (2, 1));
+    assert_eq!((stats.bytes_sent, stats.bytes_received), (5, 5));
+}
+
+#[test]
+fn writes_stop_at_the_peer_window() {
+    let config = MuxConfig {
+        window: 4096,
+        frame_size: 1000,
+        ..Default::default()
+    };
+    let mut session = Session::new(config);
+    let id = session.client.open().unwrap();
+    let data = vec![7; 10_000];
+    assert_eq!(session.client.write(id, &data), Ok(4096));
+    assert_eq!(session.client.writable(id), Ok(0));
+    assert_eq!(session.client.write(id, &data), Err(KcpError::WindowFull));
+    session.run(50);
+    assert_eq!(session.client.write(id, &data), Err(KcpError::WindowFull));
+
+    // reading half the window grants it back to the writer
+    let mut buf = vec![0; 2048];
+    assert_eq!(session.server.read(id, &mut buf), Ok(2048));
+    session.run(50);
+    assert_eq!(session.client.writable(id), Ok(2048));
+    assert_eq!(session.client.write(id, &data), Ok(2048));
+}
+
+#[test]
+fn stalled_stream_does_not_block_others() {
+    let config = MuxConfig {
+        window: 8192,
+        ..Default::default()
+    };
+    let mut session = Session::new(config);
+    let stalled = session.client.open().unwrap();
+    let busy = session.client.open().unwrap();
+    let data = vec![1; 4096];
+
+    // nobody reads `stalled`, which fills its window
+    while session.client.write(stalled, &data).is_ok() {}
+    let total = 100 * data.len();
+    let (mut sent, mut received) = (0, 0);
+    let mut buf = vec![0; 4096];
+    while received < total && session.now < 10_000 {
+        if sent < total {
+            sent += session.client.write(busy, &data).unwrap_or(0);
+        }
+        session.run(1);
+        while let Ok(len) = session.server.read(busy, &mut buf) {
+            received += len;
+        }
+    }
+    assert_eq!(received, total);
+    assert_eq!(session.server.readable(stalled), Ok(8192));
+}
+
+/// Command and stream id of the frames `mux` has queued
+fn queued(mux: &mut Mux) -> Vec<(u8, u32)> {
+    std::iter::from_fn(|| mux.poll_frame())
+        .map(|frame| {
+            (
+                frame[0],
+                u32::from_le_bytes(frame[1..5].try_into().unwrap()),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn streams_take_turns() {
+    let config = MuxConfig {
+        window: 16,
+        frame_size: 4,
+        ..Default::default()
+    };
+    let mut mux = Mux::new(config, MuxRole::Initiator);
+    let (bulk, small) = (mux.open().unwrap(), mux.open().unwrap());
+    mux.write(bulk, &[1; 16]).unwrap();
+    mux.write(small, &[2; 4]).unwrap();
+    mux.close(small).unwrap();
+    // a window update for a stream of the peer overtakes them all
+    mux.input(&frame(MuxCommand::Open, 2, &[])).unwrap();
+    mux.input(&frame(MuxCommand::Data, 2, &[3; 8])).unwrap();
+    mux.read(2, &mut [0; 8]).unwrap();
+
+    let (open, data, close, update) = (
+        MuxCommand::Open as u8,
+        MuxCommand::Data as u8,
+        MuxCommand::Close as u8,
+        MuxCommand::Update as u8,
+    );
+    assert_eq!(
+        queued(&mut mux),
+        [
+            (update, 2),
+            (open, bulk),
+            (open, small),
+            (data, bulk),
+            (data, small),
+            (data, bulk),
+            (close, small),
+            (data, bulk),
+            (data, bulk),
+        ]
+    );
+}
+
+#[test]
+fn send_leaves_frames_queued_once_kcp_holds_enough() {
+    let config = MuxConfig {
+        frame_size: 1000,
+        ..Default::default()
+    };
+    let mut mux = Mux::new(config, MuxRole::Initiator);
+    let mut kcp = endpoint().kcp;
+    kcp.set_wndsize(8, 256);
+    let bulk = mux.open().unwrap();
+    mux.write(bulk, &[1; 64_000]).unwrap();
+    mux.send(&mut kcp).unwrap();
+    // one segment per frame, up to twice the send window
+    assert_eq!(kcp.wait_snd(), 16);
+
+    // a stream opened later goes next, not after the whole backlog
+    let late = mux.open().unwrap();
+    mux.write(late, b"urgent").unwrap();
+    let (open, data) = (MuxCommand::Open as u8, MuxCommand::Data as u8);
+    assert_eq!(
+        queued(&mut mux)[..4],
+        [(open, late), (data, bulk), (data, late), (data, bulk)]
+    );
+}
+
+#[test]
+fn reset_drops_queued_frames() {
+    let mut mux = Mux::new(MuxConfig::default(), MuxRole::Initiator);
+    let (doomed, kept) = (mux.open().unwrap(), mux.open().unwrap());
+    mux.write(doomed, b"never sent").unwrap();
+    mux.reset(doomed).unwrap();
+    assert_eq!(
+        queued(&mut mux),
+        [
+            (MuxCommand::Reset as u8, doomed),
+            (MuxCommand::Open as u8, kept)
+        ]
+    );
+}
+
+#[test]
+fn resets_reach_the_peer() {
+    let mut session = Session::new(MuxConfig::default());
+    let id = session.client.open().unwrap();
+    session.client.write(id, b"doomed").unwrap();
+    session.run(20);
+    session.client.reset(id).unwrap();
+    assert_eq!(session.client.stream_count(), 0);
+    session.run(20);
+
+    let mut buf = [0; 16];
+    assert_eq!(session.server.accept(), Some(id));
+    assert_eq!(
+        session.server.read(id, &mut buf),
+        Err(KcpError::StreamReset)
+    );
+    assert_eq!(session.server.write(id, b"x"), Err(KcpError::StreamReset));
+    session.server.reset(id).unwrap();
+    assert_eq!(session.server.stream_count(), 0);
+    assert_eq!(session.server.stats().resets, 1);
+    session.run(20);
+    assert_eq!(session.client.stats().resets, 1);
+}
+
+#[test]
+fn stream_limit_refuses_peer_streams() {
+    let config = MuxConfig {
+        max_streams: 1,
+        ..Default::default()
+    };
+    let mut session = Session::new(config);
+    let first = session.client.open().unwrap();
+    assert_eq!(session.client.open(), Err(KcpError::TooManyStreams));
+
+    session.run(20);
+    assert_eq!(session.server.accept(), Some(first));
+
+    session
+        .server
+        .input(&frame(MuxCommand::Open, 5, &[]))
+        .unwrap();
+    assert_eq!(session.server.stats().refused, 1);
+    assert_eq!(
+        session.server.poll_frame(),
+        Some(frame(MuxCommand::Reset, 5, &[]))
+    );
+    assert_eq!(session.server.accept(), None);
+}
+
+#[test]
+fn invalid_frames_are_rejected() {
+    let mut mux = Mux::new(MuxConfig::default(), MuxRole::Responder);
+    assert_eq!(mux.input(&[1, 2, 3]), Err(KcpError::InvalidPacket));
+    assert_eq!(
+        mux.input(&frame(MuxCommand::Open, 1, &[])[..4]),
+        Err(KcpError::InvalidPacket)
+    );
+    assert_eq!(mux.input(&[9, 1, 0, 0, 0]), Err(KcpError::InvalidPacket));
+    // even ids belong to the responder
+    assert_eq!(
+        mux.input(&frame(MuxCommand::Open, 2, &[])),
+        Err(KcpError::InvalidPacket)
+    );
+    mux.input(&frame(MuxCommand::Open, 1, &[])).unwrap();
+    assert_eq!(
+        mux.input(&frame(MuxCommand::Open, 1, &[])),
+        Err(KcpError::InvalidPacket)
+    );
+    assert_eq!(
+        mux.input(&frame(MuxCommand::Update, 1, &[1, 2])),
+        Err(KcpError::InvalidPacket)
+    );
+    mux.input(&frame(MuxCommand::Close, 1, &[])).unwrap();
+    assert_eq!(
+        mux.input(&frame(MuxCommand::Data, 1, b"late")),
+        Err(KcpError::InvalidPacket)
+    );
+    // frames for streams that are gone are ignored
+    mux.input(&frame(MuxCommand::Data, 7, b"stray")).unwrap();
+    assert_eq!(mux.stream_count(), 1);
+}
+
+#[test]
+fn window_overrun_resets_the_stream() {
+    let config = MuxConfig {
+        window: 16,
+        ..Default::default()
+    };
+    let mut mux = Mux::new(config, MuxRole::Responder);
+    mux.input(&frame(MuxCommand::Open, 1, &[])).unwrap();
+    mux.input(&frame(MuxCommand::Data, 1, &[0; 10])).unwrap();
+    mux.input(&frame(MuxCommand::Data, 1, &[0; 10])).unwrap();
+    assert_eq!(mux.poll_frame(), Some(frame(MuxCommand::Reset, 1, &[])));
+    let mut buf = [0; 32];
+    assert_eq!(mux.read(1, &mut buf), Err(KcpError::StreamReset));
+}
+
+#[test]
+fn shared_handles_map_stream_errors() {
+    let client = SharedMux::new(MuxConfig::default(), MuxRole::Initiator);
+    let server = SharedMux::new(MuxConfig::default(), MuxRole::Responder);
+    assert!(server.accept(Some(Duration::from_millis(10))).is_none());
+
+    let (mut a, mut b) = (endpoint(), endpoint());
+    let mut exchange = |now: &mut u32| {
+        for _ in 0..20 {
+            *now += 1;
+            client.pump(&mut a.kcp).unwrap();
+            server.pump(&mut b.kcp).unwrap();
+            pump(*now, &mut a, &mut b);
+        }
+        client.pump(&mut a.kcp).unwrap();
+        server.pump(&mut b.kcp).unwrap();
+    };
+    let mut now = 0;
+
+    let mut local = client.open().unwrap();
+    local.set_timeout(Some(Duration::from_millis(10)));
+    let mut buf = [0; 8];
+    assert_eq!(
+        local.read(&mut buf).unwrap_err().kind(),
+        ErrorKind::TimedOut
+    );
+    local.write_all(b"ping").unwrap();
+    local.shutdown().unwrap();
+    assert_eq!(local.write(b"x").unwrap_err().kind(), ErrorKind::BrokenPipe);
+    exchange(&mut now);
+
+    let mut remote = server.accept(None).unwrap();
+    let mut received = Vec::new();
+    remote.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"ping");
+    remote.reset().unwrap();
+    exchange(&mut now);
+    assert_eq!(
+        local.read(&mut buf).unwrap_err().kind(),
+        ErrorKind::ConnectionReset
+    );
+    drop(local);
+    assert_eq!(client.with(|mux| mux.stream_count()), 0);
+    assert_eq!(server.with(|mux| mux.stream_count()), 0);
+}
+
+#[test]
+fn shared_streams_echo_across_threads() {
+    const STREAMS: usize = 4;
+    const SIZE: usize = 200_000;
+
+    let config = MuxConfig {
+        window: 16 * 1024,
+        frame_size: 1024,
+        ..Default::default()
+    };
+    let client = SharedMux::new(config.clone(), MuxRole::Initiator);
+    let server = SharedMux::new(config, MuxRole::Responder);
+    let done = Arc::new(AtomicBool::new(false));
+
+    let driver = {
+        let (client, server, done) = (client.clone(), server.clone(), done.clone());
+        thread::spawn(move || {
+            let mut a = endpoint();
+            let mut b = endpoint();
+            let mut now = 0;
+            while !done.load(Ordering::Relaxed) {
+                now += 1;
+                pump(now, &mut a, &mut b);
+                client.pump(&mut a.kcp).unwrap();
+                server.pump(&mut b.kcp).unwrap();
+                thread::sleep(Duration::from_micros(200));
+            }
+        })
+    };
+
+    let echo = {
+        let server = server.clone();
+        thread::spawn(move || {
+            let mut handles = Vec::new();
+            for _ in 0..STREAMS {
+                let mut stream = server.accept(Some(Duration::from_secs(10))).unwrap();
+                handles.push(thread::spawn(move || {
+                    let mut buf = [0; 4096];
+                    loop {
+                        let len = stream.read(&mut buf).unwrap();
+                        if len == 0 {
+                            break;
+                        }
+                        stream.write_all(&buf[..len]).unwrap();
+                    }
+                    stream.shutdown().unwrap();
+                }));
+            }
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        })
+    };
+
+    let clients: Vec<_> = (0..STREAMS)
+        .map(|n| {
+            let mut stream = client.open().unwrap();
+            stream.set_timeout(Some(Duration::from_secs(30)));
+            let data: Vec<u8> = (0..SIZE).map(|i| (i * (n + 1)) as u8).collect();
+            let mut reader = stream.try_clone().unwrap();
+            let writer = thread::spawn(move || {
+                stream.write_all(&data).unwrap();
+                stream.shutdown().unwrap();
+                (stream, data)
+            });
+            thread::spawn(move || {
+                let mut echoed = Vec::new();
+                reader.read_to_end(&mut echoed).unwrap();
+                let (_stream, data) = writer.join().unwrap();
+                assert!(echoed == data, "stream {n} echoed {} bytes", echoed.len());
+            })
+        })
+        .collect();
+    for handle in clients {
+        handle.join().unwrap();
+    }
+    echo.join().unwrap();
+    done.store(true, Ordering::Relaxed);
+    driver.join().unwrap();
+
+    let stats = client.with(|mux| *mux.stats());
+    assert_eq!(stats.opened, STREAMS as u64);
+    assert_eq!(stats.bytes_received, (STREAMS * SIZE) as u64);
+}
@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Bare metal target without a standard library, override with `NO_STD_TARGET`
+const TARGET: &str = "thumbv7em-none-eabihf";
+
+/// Feature sets that must build without std
+const FEATURES: [&str; 2] = ["alloc", "alloc,log,fec,crypto,cookie,compress"];
+
+fn target() -> String {
+    std::env::var("NO_STD_TARGET").unwrap_or_else(|_| TARGET.to_string())
+}
+
+/// Whether the toolchain ships the core and alloc libraries of `target`
+fn is_installed(target: &str) -> bool {
+    let output = Command::new("rustc")
+        .args(["--print", "target-libdir", "--target", target])
+        .output()
+        .unwrap();
+    let libdir = String::from_utf8_lossy(&output.stdout);
+    output.status.success() && Path::new(libdir.trim()).is_dir()
+}
+
+#[test]
+fn core_builds_without_std() {
+    let target = target();
+    if !is_installed(&target) {
+        eprintln!("skipped, install the target with `rustup target add {target}`");
+        return;
+    }
+
+    for features in FEATURES {
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--quiet", "--lib", "--no-default-features"])
+            .args(["--features", features, "--target", &target])
+            .arg("--manifest-path")
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+            .status()
+            .unwrap();
+        assert!(status.success(), "{target} build failed with {features}");
+    }
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use common::{segments, Endpoint, Wire};
+use ultra_kcp_core::constants::{Command, IKCP_OVERHEAD};
+use ultra_kcp_core::pacing::{Pacer, PacingRate, PACING_BURST_DEF};
+
+/// 100 bytes per millisecond
+const RATE: u64 = 100_000;
+const MTU: u32 = 1000;
+
+/// Pacer at `RATE`, refilled once at `start`, so that its bucket is full
+fn pacer(start: u32) -> Pacer {
+    let mut pacer = Pacer::new(PacingRate::Fixed(RATE));
+    pacer.refill(start, RATE, MTU);
+    pacer
+}
+
+#[test]
+fn bucket_starts_full_and_holds_a_burst() {
+    for start in [0, 0x8000_0000, u32::MAX - 3] {
+        let mut pacer = pacer(start);
+        assert_eq!(pacer.rate(), RATE);
+        assert_eq!(pacer.release(), None);
+        for _ in 0..PACING_BURST_DEF {
+            assert!(pacer.consume(start, MTU));
+        }
+        assert!(!pacer.consume(start, MTU));
+        // 1000 bytes at 100 bytes/ms
+        assert_eq!(pacer.release(), Some(start.wrapping_add(10)));
+    }
+}
+
+#[test]
+fn refill_adds_tokens_for_the_elapsed_time() {
+    for start in [0, u32::MAX - 3] {
+        let mut pacer = pacer(start);
+        assert!(pacer.consume(start, 2 * MTU));
+
+        let now = start.wrapping_add(4);
+        pacer.refill(now, RATE, MTU);
+        assert_eq!(pacer.release(), None);
+        assert!(pacer.consume(now, 300));
+        assert!(!pacer.consume(now, 200));
+        // 100 bytes left, 100 more needed
+        assert_eq!(pacer.release(), Some(now.wrapping_add(1)));
+
+        let now = now.wrapping_add(1);
+        pacer.refill(now, RATE, MTU);
+        assert!(pacer.consume(now, 200));
+        assert!(!pacer.consume(now, 1));
+    }
+}
+
+#[test]
+fn refill_is_capped_by_the_burst() {
+    let mut pacer = pacer(u32::MAX - 3);
+    assert!(pacer.consume(u32::MAX - 3, 2 * MTU));
+    // a long idle period, across the wrap, refills two datagrams only
+    pacer.refill(60_000, RATE, MTU);
+    assert!(pacer.consume(60_000, 2 * MTU));
+    assert!(!pacer.consume(60_000, 1));
+
+    pacer.burst = 5;
+    pacer.refill(120_000, RATE, MTU);
+    assert!(pacer.consume(120_000, 5 * MTU));
+    assert!(!pacer.consume(120_000, 1));
+}
+
+#[test]
+fn clock_going_backwards_adds_nothing() {
+    let mut pacer = pacer(1000);
+    assert!(pacer.consume(1000, 2 * MTU));
+    pacer.refill(990, RATE, MTU);
+    assert!(!pacer.consume(990, 1));
+}
+
+#[test]
+fn zero_rate_does_not_limit() {
+    let mut pacer = Pacer::new(PacingRate::Auto);
+    pacer.refill(0, 0, MTU);
+    for _ in 0..100 {
+        assert!(pacer.consume(0, MTU));
+    }
+    assert_eq!(pacer.release(), None);
+}
+
+/// Sender pacing at `RATE`, with congestion control out of the way
+fn sender() -> Endpoint {
+    let mut endpoint = Endpoint::new(5);
+    let kcp = &mut endpoint.kcp;
+    kcp.set_nodelay(0, 100, 0, true);
+    kcp.set_wndsize(128, 128);
+    kcp.set_mtu(MTU).unwrap();
+    kcp.set_pacing(Some(PacingRate::Fixed(RATE)));
+    endpoint
+}
+
+/// Times at which the push segments of the datagrams on `wire` left
+fn pushes(now: u32, wire: &Wire, times: &mut Vec<u32>) {
+    for (segment, _) in segments(wire) {
+        if segment.cmd == Command::Push as u32 {
+            times.push(now);
+        }
+    }
+}
+
+#[test]
+fn bursts_are_spread_at_the_rate() {
+    for start in [0, u32::MAX - 50] {
+        let Endpoint { mut kcp, wire } = sender();
+        let payload = (MTU - IKCP_OVERHEAD) as usize;
+        for _ in 0..20 {
+            kcp.send(&vec![1; payload]).unwrap();
+        }
+
+        let mut now = start;
+        let mut times = Vec::new();
+        kcp.update(now);
+        pushes(now, &wire, &mut times);
+        // the bucket lets the burst out, the rest waits
+        assert_eq!(times.len(), PACING_BURST_DEF as usize);
+        let release = kcp.pacer().unwrap().release().unwrap();
+        assert_eq!(release, now.wrapping_add(10));
+        assert_eq!(kcp.check(now), release);
+
+        while times.len() < 20 {
+            now = kcp.check(now);
+            kcp.update(now);
+            pushes(now, &wire, &mut times);
+        }
+
+        // one datagram every 10 ms, well within the 100 ms update interval
+        let elapsed: Vec<u32> = times.iter().map(|t| t.wrapping_sub(start)).collect();
+        let expected: Vec<u32> = (0..20u32)
+            .map(|i| i.saturating_sub(PACING_BURST_DEF - 1) * 10)
+            .collect();
+        assert_eq!(elapsed, expected);
+    }
+}
+
+#[test]
+fn check_returns_the_release_time() {
+    let Endpoint { mut kcp, wire } = sender();
+    kcp.update(0);
+    // nothing held back, the next update is the interval
+    assert_eq!(kcp.pacer().unwrap().release(), None);
+    assert_eq!(kcp.check(0), 100);
+
+    for _ in 0..5 {
+        kcp.send(&[1; 900]).unwrap();
+    }
+    kcp.update(100);
+    let release = kcp.pacer().unwrap().release().unwrap();
+    assert!(release > 100 && release < 200);
+    assert_eq!(kcp.check(101), release);
+    // an update before the release time sends nothing
+    wire.lock().unwrap().clear();
+    kcp.update(release - 1);
+    assert!(wire.lock().unwrap().is_empty());
+    kcp.update(release);
+    assert!(!wire.lock().unwrap().is_empty());
+}
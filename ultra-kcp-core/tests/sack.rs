@@ -0,0 +1,163 @@
+mod common;
+
+use common::{drain, segments, Endpoint};
+use ultra_kcp_core::constants::{Command, KcpError, IKCP_OVERHEAD, IKCP_SACK_RANGE_SIZE};
+use ultra_kcp_core::kcp::{KcpControl, Segment};
+
+fn endpoint(sack: bool) -> Endpoint {
+    let mut endpoint = Endpoint::new(9);
+    endpoint.kcp.set_nodelay(1, 10, 1, true);
+    endpoint.kcp.set_wndsize(128, 128);
+    endpoint.kcp.set_sack(sack);
+    endpoint
+}
+
+fn pushed(segments: &[(Segment, Vec<u8>)]) -> Vec<u32> {
+    segments
+        .iter()
+        .filter(|(segment, _)| segment.cmd == Command::Push as u32)
+        .map(|(segment, _)| segment.sn)
+        .collect()
+}
+
+/// Send sn 0..10 and deliver all but `lost` to the receiver
+fn send_with_holes(sender: &mut Endpoint, receiver: &mut KcpControl, lost: &[u32]) {
+    for _ in 0..10 {
+        sender.kcp.send(&[7; 100]).unwrap();
+    }
+    sender.kcp.update(0);
+    receiver.update(0);
+    let sent = segments(&sender.wire);
+    assert_eq!(pushed(&sent), (0..10).collect::<Vec<_>>());
+    for (segment, bytes) in sent {
+        if !lost.contains(&segment.sn) {
+            receiver.input(&bytes).unwrap();
+        }
+    }
+}
+
+#[test]
+fn sack_carries_the_received_ranges() {
+    let mut sender = endpoint(true);
+    let mut receiver = endpoint(true);
+    send_with_holes(&mut sender, &mut receiver.kcp, &[2, 5]);
+
+    receiver.kcp.update(10);
+    let acks = segments(&receiver.wire);
+    assert_eq!(acks.len(), 1);
+    let (sack, bytes) = &acks[0];
+    assert_eq!(sack.cmd, Command::Sack as u32);
+    assert_eq!(sack.una, 2);
+    assert_eq!(sack.len as usize, 2 * IKCP_SACK_RANGE_SIZE);
+    // the last ACKed sn and its timestamp, for RTT estimation
+    assert_eq!((sack.sn, sack.ts), (9, 0));
+    let payload: Vec<u8> = [3u32, 5, 6, 10]
+        .iter()
+        .flat_map(|sn| sn.to_le_bytes())
+        .collect();
+    assert_eq!(&bytes[IKCP_OVERHEAD as usize..], &payload[..]);
+
+    // eight ACKs went out as one segment
+    let stats = receiver.kcp.ack_stats();
+    assert_eq!((stats.acks_sent, stats.acks_saved), (1, 7));
+}
+
+#[test]
+fn only_holes_are_fast_retransmitted() {
+    let mut sender = endpoint(true);
+    let mut receiver = endpoint(true);
+    send_with_holes(&mut sender, &mut receiver.kcp, &[2, 5]);
+
+    receiver.kcp.update(10);
+    for (_, bytes) in segments(&receiver.wire) {
+        sender.kcp.input(&bytes).unwrap();
+    }
+    assert_eq!(sender.kcp.snd_una, 2);
+    let holes: Vec<u32> = sender
+        .kcp
+        .snd_buf
+        .iter()
+        .map(|segment| segment.sn)
+        .collect();
+    assert_eq!(holes, [2, 5]);
+
+    // well before the retransmission timeout
+    sender.kcp.update(10);
+    assert_eq!(pushed(&segments(&sender.wire)), [2, 5]);
+}
+
+#[test]
+fn malformed_sack_is_rejected() {
+    let mut kcp = endpoint(true).kcp;
+    let mut buf = vec![0; IKCP_OVERHEAD as usize + 4];
+    Segment {
+        conv: 9,
+        cmd: Command::Sack as u32,
+        len: 4,
+        ..Default::default()
+    }
+    .encode_header(&mut buf);
+    assert_eq!(kcp.input(&buf), Err(KcpError::InvalidPacket));
+}
+
+/// Lossy transfer of `count` messages from `sender` to `receiver`
+fn transfer(sender_sack: bool, receiver_sack: bool, count: usize) {
+    let Endpoint {
+        kcp: mut sender,
+        wire: forward,
+    } = endpoint(sender_sack);
+    let Endpoint {
+        kcp: mut receiver,
+        wire: backward,
+    } = endpoint(receiver_sack);
+
+    let mut buf = vec![0; 2000];
+    let (mut sent, mut received, mut datagrams) = (0, 0, 0);
+    for now in 0..60_000 {
+        while sent < count && sender.wait_snd() < 64 {
+            sender
+                .send(&(sent as u32).to_le_bytes().repeat(100))
+                .unwrap();
+            sent += 1;
+        }
+        sender.update(now);
+        receiver.update(now);
+        for datagram in drain(&forward) {
+            datagrams += 1;
+            if datagrams % 7 != 0 {
+                receiver.input(&datagram).unwrap();
+            }
+        }
+        for datagram in drain(&backward) {
+            // the receiver picks the kind of acknowledgement
+            let ack = Segment::decode_header(&datagram).unwrap();
+            let expected = if receiver_sack {
+                Command::Sack
+            } else {
+                Command::Ack
+            };
+            assert_eq!(ack.cmd, expected as u32);
+            sender.input(&datagram).unwrap();
+        }
+        while let Ok(size) = receiver.receive(Some(&mut buf), false) {
+            assert_eq!(
+                &buf[..size],
+                &(received as u32).to_le_bytes().repeat(100)[..]
+            );
+            received += 1;
+        }
+        if received == count {
+            return;
+        }
+    }
+    panic!("sack {sender_sack} -> {receiver_sack}: {received} of {count} delivered");
+}
+
+#[test]
+fn interoperates_with_plain_acks() {
+    transfer(true, true, 500);
+    // a plain peer acknowledges with ACK segments, which the SACK side takes
+    transfer(true, false, 500);
+    // and takes the SACK segments of the other side
+    transfer(false, true, 500);
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use common::{drain, Wire};
+use ultra_kcp_core::constants::KcpLogFlags;
+use ultra_kcp_core::kcp::KcpControl;
+use ultra_kcp_core::trace::{SegmentTrace, TraceEvent, TraceOutput};
+
+/// Trace destination readable by the test
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Shared {
+    fn lines(&self) -> Vec<String> {
+        let trace = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        trace.lines().map(str::to_string).collect()
+    }
+}
+
+struct Endpoint {
+    kcp: KcpControl,
+    wire: Wire,
+    trace: Shared,
+}
+
+impl Endpoint {
+    fn new(mask: KcpLogFlags) -> Self {
+        let trace = Shared::default();
+        let common::Endpoint { mut kcp, wire } =
+            common::Endpoint::wrapped(3, |output| TraceOutput::new(output, trace.clone()));
+        kcp.set_nodelay(1, 10, 2, false);
+        kcp.set_tracing(true);
+        kcp.set_log_mask(mask);
+        Self { kcp, wire, trace }
+    }
+}
+
+/// Run both endpoints, dropping the first transmission of every third datagram
+fn run(sender: &mut Endpoint, receiver: &mut Endpoint, until: u32) {
+    let mut sent = 0;
+    for current in (0..until).step_by(10) {
+        sender.kcp.update(current);
+        receiver.kcp.update(current);
+        for packet in drain(&sender.wire) {
+            sent += 1;
+            if sent % 3 != 0 {
+                receiver.kcp.input(&packet).unwrap();
+            }
+        }
+        for packet in drain(&receiver.wire) {
+            sender.kcp.input(&packet).unwrap();
+        }
+    }
+}
+
+#[test]
+fn trace_records_segments_rtt_and_cwnd() {
+    let mut sender = Endpoint::new(KcpLogFlags::all());
+    let mut receiver = Endpoint::new(KcpLogFlags::all());
+    for _ in 0..40 {
+        sender.kcp.send(&[7; 1000]).unwrap();
+    }
+    run(&mut sender, &mut receiver, 3000);
+
+    let lines = sender.trace.lines();
+    assert!(lines
+        .iter()
+        .all(|line| line.starts_with(r#"{"time":"#) && line.ends_with("}}")));
+    let count = |name: &str| lines.iter().filter(|line| line.contains(name)).count();
+    assert!(count(r#""name":"segment_sent","data":{"cmd":"push""#) > 40);
+    assert!(count(r#""cmd":"push","sn":0,"una":0,"frg":0,"wnd":128,"len":1000,"xmit":1"#) == 1);
+    assert!(count(r#""xmit":2"#) > 0, "no retransmission traced");
+    assert!(count(r#""name":"segment_received","data":{"cmd":"ack""#) >= 40);
+    assert!(count(r#""name":"rtt_updated""#) >= 40);
+    assert!(count(r#""name":"cwnd_changed""#) > 0);
+    assert!(count(r#""reason":"ack""#) > 0);
+
+    let received = receiver.trace.lines();
+    assert!(received
+        .iter()
+        .any(|line| line.contains(r#""name":"segment_received","data":{"cmd":"push""#)));
+    assert!(received
+        .iter()
+        .any(|line| line.contains(r#""name":"segment_sent","data":{"cmd":"ack""#)));
+}
+
+#[test]
+fn log_mask_selects_traced_events() {
+    let mut sender = Endpoint::new(KcpLogFlags::OUT_DATA | KcpLogFlags::RTT);
+    let mut receiver = Endpoint::new(KcpLogFlags::empty());
+    sender.kcp.send(b"masked").unwrap();
+    run(&mut sender, &mut receiver, 500);
+
+    let lines = sender.trace.lines();
+    assert!(!lines.is_empty());
+    assert!(lines.iter().all(|line| {
+        line.contains(r#""name":"segment_sent","data":{"cmd":"push""#)
+            || line.contains(r#""name":"rtt_updated""#)
+    }));
+    assert!(receiver.trace.lines().is_empty());
+
+    // tracing is off by default
+    let mut silent = Endpoint::new(KcpLogFlags::all());
+    silent.kcp.set_tracing(false);
+    silent.kcp.send(b"quiet").unwrap();
+    silent.kcp.update(0);
+    assert!(silent.trace.lines().is_empty());
+}
+
+#[test]
+fn dead_link_is_traced_once() {
+    let mut sender = Endpoint::new(KcpLogFlags::STATE);
+    sender.kcp.dead_link = 3;
+    sender.kcp.send(b"nobody listens").unwrap();
+    for current in (0..60_000).step_by(10) {
+        sender.kcp.update(current);
+    }
+
+    let lines = sender.trace.lines();
+    assert_eq!(lines.len(), 1, "{lines:?}");
+    assert!(lines[0].ends_with(r#""name":"state_changed","data":{"from":0,"to":4294967295}}"#));
+    assert_eq!(sender.kcp.state, u32::MAX);
+}
+
+#[test]
+fn events_serialize_as_json_lines() {
+    let event = TraceEvent::SegmentSent(SegmentTrace {
+        cmd: 81,
+        sn: 5,
+        una: 2,
+        frg: 1,
+        wnd: 64,
+        len: 1376,
+        xmit: 3,
+    });
+    let mut line = String::new();
+    event.write_json(1200, &mut line).unwrap();
+    assert_eq!(
+        line,
+        r#"{"time":1200,"name":"segment_sent","data":{"cmd":"push","sn":5,"una":2,"frg":1,"wnd":64,"len":1376,"xmit":3}}"#
+    );
+    assert!(event.flag().bits() == KcpLogFlags::OUT_DATA.bits());
+}
@@ -0,0 +1,277 @@
+/* Generated by cbindgen from ultra-kcp-ffi, run `UPDATE_HEADER=1 cargo test -p ultra-kcp-ffi --test header` after changing the API */
+
+#ifndef __IKCP_H__
+#define __IKCP_H__
+
+#include <stdint.h>
+
+typedef int32_t IINT32;
+typedef uint32_t IUINT32;
+typedef int16_t IINT16;
+typedef uint16_t IUINT16;
+typedef int8_t IINT8;
+typedef uint8_t IUINT8;
+
+#define IKCP_LOG_OUTPUT 1
+
+#define IKCP_LOG_INPUT 2
+
+#define IKCP_LOG_SEND 4
+
+#define IKCP_LOG_RECV 8
+
+#define IKCP_LOG_IN_DATA 16
+
+#define IKCP_LOG_IN_ACK 32
+
+#define IKCP_LOG_IN_PROBE 64
+
+#define IKCP_LOG_IN_WINS 128
+
+#define IKCP_LOG_OUT_DATA 256
+
+#define IKCP_LOG_OUT_ACK 512
+
+#define IKCP_LOG_OUT_PROBE 1024
+
+#define IKCP_LOG_OUT_WINS 2048
+
+/*
+ KCP control block handed to C code
+
+ Unlike ikcp the structure is opaque: fields are only reachable through
+ the functions below, and code writing them directly must switch to
+
+ * `ikcp_setlog` and `ikcp_logmask` for `writelog` and `logmask`
+ * `ikcp_setstream` for `stream`
+ * `ikcp_setminrto` for `rx_minrto`
+ * `ikcp_setfastresend` for `fastresend`
+ */
+typedef struct IKCPCB IKCPCB;
+
+/*
+ Output callback, sends a datagram to the peer
+ */
+typedef int (*ikcp_output_fn)(const char *buf, int len, struct IKCPCB *kcp, void *user);
+
+/*
+ Log callback, receives a NUL terminated log line
+ */
+typedef void (*ikcp_writelog_fn)(const char *log, struct IKCPCB *kcp, void *user);
+
+typedef struct IKCPCB ikcpcb;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Create a new KCP control block
+
+ # Arguments
+ * `conv` - Conversation id, must be equal on both ends
+ * `user` - Passed back to the output and log callbacks
+
+ # Returns
+ Handle to release with `ikcp_release`
+ */
+struct IKCPCB *ikcp_create(uint32_t conv, void *user);
+
+/*
+ Release a KCP control block
+
+ # Safety
+ `kcp` must come from `ikcp_create` and not be used afterwards, NULL is ignored.
+ */
+void ikcp_release(struct IKCPCB *kcp);
+
+/*
+ Set the output callback, invoked from `ikcp_update` and `ikcp_flush`
+
+ # Safety
+ `kcp` must be a live handle. Callbacks must not call back into the same handle.
+ */
+void ikcp_setoutput(struct IKCPCB *kcp, ikcp_output_fn output);
+
+/*
+ Set the log callback, NULL disables logging
+
+ # Safety
+ `kcp` must be a live handle. Callbacks must not call back into the same handle.
+ */
+void ikcp_setlog(struct IKCPCB *kcp, ikcp_writelog_fn writelog);
+
+/*
+ Select the events logged, a combination of `IKCP_LOG_*`
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+void ikcp_logmask(struct IKCPCB *kcp, int mask);
+
+/*
+ Receive a message
+
+ # Arguments
+ * `buffer` - Destination, NULL discards the message
+ * `len` - Size of `buffer`, a negative size peeks without removing the message
+
+ # Returns
+ Size of the message, -1 when the queue is empty, -2 when the message is
+ incomplete, -3 when `buffer` is too small
+
+ # Safety
+ `kcp` must be a live handle and `buffer` valid for `|len|` bytes.
+ */
+int ikcp_recv(struct IKCPCB *kcp, char *buffer, int len);
+
+/*
+ Queue a message for sending
+
+ # Returns
+ Bytes queued, -1 on a negative size, -2 when the message needs more
+ fragments than the receive window
+
+ # Safety
+ `kcp` must be a live handle and `buffer` valid for `len` bytes.
+ */
+int ikcp_send(struct IKCPCB *kcp, const char *buffer, int len);
+
+/*
+ Update the state, call it every `interval` ms or at the time returned by `ikcp_check`
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+void ikcp_update(struct IKCPCB *kcp, uint32_t current);
+
+/*
+ Time of the next required `ikcp_update`
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+uint32_t ikcp_check(const struct IKCPCB *kcp, uint32_t current);
+
+/*
+ Feed a datagram received from the peer
+
+ # Returns
+ 0 on success, -1 when shorter than a segment header or for another
+ conversation, -2 when a segment is longer than the datagram, -3 for an
+ unknown command
+
+ # Safety
+ `kcp` must be a live handle and `data` valid for `size` bytes.
+ */
+int ikcp_input(struct IKCPCB *kcp, const char *data, long size);
+
+/*
+ Flush pending data and acknowledgements
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+void ikcp_flush(struct IKCPCB *kcp);
+
+/*
+ Size of the next message, -1 when none is complete
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_peeksize(const struct IKCPCB *kcp);
+
+/*
+ Change the MTU, 0 on success, -1 when smaller than 50 bytes or the overhead
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_setmtu(struct IKCPCB *kcp, int mtu);
+
+/*
+ Set the window sizes in segments, values below 1 are left unchanged
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_wndsize(struct IKCPCB *kcp, int sndwnd, int rcvwnd);
+
+/*
+ Number of segments waiting to be sent or acknowledged
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_waitsnd(const struct IKCPCB *kcp);
+
+/*
+ Configure the nodelay mode, negative values are left unchanged
+
+ # Arguments
+ * `nodelay` - 0: disable, 1: enable, 2: enable with a less aggressive RTO
+   backoff, also resets the minimum retransmission timeout
+ * `interval` - Internal update interval in milliseconds
+ * `resend` - Fast resend threshold, 0 disables fast resend
+ * `nc` - 1 ignores the congestion window
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_nodelay(struct IKCPCB *kcp, int nodelay, int interval, int resend, int nc);
+
+/*
+ Set the update interval, clamped to 10..=5000 ms
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_interval(struct IKCPCB *kcp, int interval);
+
+/*
+ Enable stream mode when `stream` is not 0, replaces `kcp->stream = stream`
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_setstream(struct IKCPCB *kcp, int stream);
+
+/*
+ Set the minimum retransmission timeout in ms, replaces `kcp->rx_minrto = minrto`
+
+ `ikcp_nodelay` with a non-negative `nodelay` resets it, as in ikcp.
+
+ # Returns
+ 0 on success, -1 on a negative timeout
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_setminrto(struct IKCPCB *kcp, int minrto);
+
+/*
+ Set the fast resend threshold, 0 disables fast resend, replaces
+ `kcp->fastresend = resend`
+
+ # Returns
+ 0 on success, -1 on a negative threshold
+
+ # Safety
+ `kcp` must be a live handle.
+ */
+int ikcp_setfastresend(struct IKCPCB *kcp, int resend);
+
+/*
+ Read the conversation id of a datagram
+
+ # Safety
+ `ptr` must be valid for 4 bytes.
+ */
+uint32_t ikcp_getconv(const void *ptr);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* __IKCP_H__ */
@@ -0,0 +1,441 @@
+use std::any::Any;
+use std::ffi::{c_char, c_int, c_long, c_void, CString};
+
+use ultra_kcp_core::constants::{KcpError, KcpLogFlags, IKCP_OVERHEAD, IKCP_RTO_MIN, IKCP_RTO_NDL};
+use ultra_kcp_core::kcp::{KcpCallBack, KcpControl};
+
+pub const IKCP_LOG_OUTPUT: c_int = 1;
+pub const IKCP_LOG_INPUT: c_int = 2;
+pub const IKCP_LOG_SEND: c_int = 4;
+pub const IKCP_LOG_RECV: c_int = 8;
+pub const IKCP_LOG_IN_DATA: c_int = 16;
+pub const IKCP_LOG_IN_ACK: c_int = 32;
+pub const IKCP_LOG_IN_PROBE: c_int = 64;
+pub const IKCP_LOG_IN_WINS: c_int = 128;
+pub const IKCP_LOG_OUT_DATA: c_int = 256;
+pub const IKCP_LOG_OUT_ACK: c_int = 512;
+pub const IKCP_LOG_OUT_PROBE: c_int = 1024;
+pub const IKCP_LOG_OUT_WINS: c_int = 2048;
+
+/// Output callback, sends a datagram to the peer
+pub type IkcpOutputFn = Option<
+    unsafe extern "C" fn(
+        buf: *const c_char,
+        len: c_int,
+        kcp: *mut IKCPCB,
+        user: *mut c_void,
+    ) -> c_int,
+>;
+
+/// Log callback, receives a NUL terminated log line
+pub type IkcpWritelogFn =
+    Option<unsafe extern "C" fn(log: *const c_char, kcp: *mut IKCPCB, user: *mut c_void)>;
+
+/// KCP control block handed to C code
+///
+/// Unlike ikcp the structure is opaque: fields are only reachable through
+/// the functions below, and code writing them directly must switch to
+///
+/// * `ikcp_setlog` and `ikcp_logmask` for `writelog` and `logmask`
+/// * `ikcp_setstream` for `stream`
+/// * `ikcp_setminrto` for `rx_minrto`
+/// * `ikcp_setfastresend` for `fastresend`
+#[allow(clippy::upper_case_acronyms)]
+pub struct IKCPCB {
+    kcp: KcpControl,
+    user: *mut c_void,
+    output: IkcpOutputFn,
+    writelog: IkcpWritelogFn,
+}
+
+#[allow(non_camel_case_types)]
+pub type ikcpcb = IKCPCB;
+
+/// Forwards `KcpCallBack` events to the C callbacks of a handle
+struct CCallback {
+    handle: *mut IKCPCB,
+    user: *mut c_void,
+    output: IkcpOutputFn,
+    writelog: IkcpWritelogFn,
+}
+
+// SAFETY: like ikcp, a handle is only used by one thread at a time, the
+// pointers are never dereferenced on the Rust side
+unsafe impl Send for CCallback {}
+unsafe impl Sync for CCallback {}
+
+impl KcpCallBack for CCallback {
+    fn output(&self, buf: &[u8], _kcp: &mut KcpControl, _user: Option<&Box<dyn Any>>) {
+        if let Some(output) = self.output {
+            unsafe {
+                output(
+                    buf.as_ptr().cast(),
+                    buf.len() as c_int,
+                    self.handle,
+                    self.user,
+                )
+            };
+        }
+    }
+
+    fn writelog(&self, log: &str, _kcp: &KcpControl, _user: Option<&Box<dyn Any>>) {
+        if let Some(writelog) = self.writelog {
+            let log = CString::new(log.replace('\0', "")).unwrap_or_default();
+            unsafe { writelog(log.as_ptr(), self.handle, self.user) };
+        }
+    }
+}
+
+impl IKCPCB {
+    /// Install the callbacks on the control block, after any of them changed
+    fn install(&mut self) {
+        let callback = CCallback {
+            handle: self,
+            user: self.user,
+            output: self.output,
+            writelog: self.writelog,
+        };
+        self.kcp.set_callback(Box::new(callback));
+    }
+}
+
+/// Status code returned by ikcp for an error
+const fn error_code(error: KcpError) -> c_int {
+    match error {
+        // ikcp_recv
+        KcpError::QueueEmpty => -1,
+        KcpError::IncompleteMessage => -2,
+        KcpError::BufferTooSmall => -3,
+        // ikcp_send
+        KcpError::WindowFull | KcpError::MessageTooLarge => -2,
+        // ikcp_input
+        KcpError::ConversationMismatch => -1,
+        KcpError::InvalidPacket => -2,
+        KcpError::UnknownCommand => -3,
+        // not produced by the control blocks handed out here
+        KcpError::InvalidConfig
+        | KcpError::ChecksumMismatch
+        | KcpError::AuthFailed
+        | KcpError::Replayed
+        | KcpError::UnknownStream
+        | KcpError::StreamClosed
+        | KcpError::StreamReset
+        | KcpError::TooManyStreams => -1,
+    }
+}
+
+/// Create a new KCP control block
+///
+/// # Arguments
+/// * `conv` - Conversation id, must be equal on both ends
+/// * `user` - Passed back to the output and log callbacks
+///
+/// # Returns
+/// Handle to release with `ikcp_release`
+#[no_mangle]
+pub extern "C" fn ikcp_create(conv: u32, user: *mut c_void) -> *mut IKCPCB {
+    let handle = Box::into_raw(Box::new(IKCPCB {
+        kcp: KcpControl::new_on_stack(conv, None),
+        user,
+        output: None,
+        writelog: None,
+    }));
+    unsafe { (*handle).install() };
+    handle
+}
+
+/// Release a KCP control block
+///
+/// # Safety
+/// `kcp` must come from `ikcp_create` and not be used afterwards, NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_release(kcp: *mut IKCPCB) {
+    if !kcp.is_null() {
+        drop(Box::from_raw(kcp));
+    }
+}
+
+/// Set the output callback, invoked from `ikcp_update` and `ikcp_flush`
+///
+/// # Safety
+/// `kcp` must be a live handle. Callbacks must not call back into the same handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_setoutput(kcp: *mut IKCPCB, output: IkcpOutputFn) {
+    let kcp = &mut *kcp;
+    kcp.output = output;
+    kcp.install();
+}
+
+/// Set the log callback, NULL disables logging
+///
+/// # Safety
+/// `kcp` must be a live handle. Callbacks must not call back into the same handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_setlog(kcp: *mut IKCPCB, writelog: IkcpWritelogFn) {
+    let kcp = &mut *kcp;
+    kcp.writelog = writelog;
+    kcp.kcp.set_logging(writelog.is_some());
+    kcp.install();
+}
+
+/// Select the events logged, a combination of `IKCP_LOG_*`
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_logmask(kcp: *mut IKCPCB, mask: c_int) {
+    (*kcp)
+        .kcp
+        .set_log_mask(KcpLogFlags::from_bits_truncate(mask as u32));
+}
+
+/// Receive a message
+///
+/// # Arguments
+/// * `buffer` - Destination, NULL discards the message
+/// * `len` - Size of `buffer`, a negative size peeks without removing the message
+///
+/// # Returns
+/// Size of the message, -1 when the queue is empty, -2 when the message is
+/// incomplete, -3 when `buffer` is too small
+///
+/// # Safety
+/// `kcp` must be a live handle and `buffer` valid for `|len|` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_recv(kcp: *mut IKCPCB, buffer: *mut c_char, len: c_int) -> c_int {
+    let kcp = &mut (*kcp).kcp;
+    let is_peek = len < 0;
+    let len = len.unsigned_abs() as usize;
+
+    let size = match kcp.peek_size() {
+        Ok(size) => size,
+        Err(error) => return error_code(error),
+    };
+    if size > len {
+        return -3;
+    }
+    let buffer = (!buffer.is_null()).then(|| std::slice::from_raw_parts_mut(buffer.cast(), len));
+    match kcp.receive(buffer, is_peek) {
+        Ok(size) => size as c_int,
+        Err(error) => error_code(error),
+    }
+}
+
+/// Queue a message for sending
+///
+/// # Returns
+/// Bytes queued, -1 on a negative size, -2 when the message needs more
+/// fragments than the receive window
+///
+/// # Safety
+/// `kcp` must be a live handle and `buffer` valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_send(kcp: *mut IKCPCB, buffer: *const c_char, len: c_int) -> c_int {
+    let kcp = &mut (*kcp).kcp;
+    if len < 0 {
+        return -1;
+    }
+    let data = if len == 0 || buffer.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(buffer.cast(), len as usize)
+    };
+    match kcp.send(data) {
+        Ok(sent) => sent as c_int,
+        Err(error) => error_code(error),
+    }
+}
+
+/// Update the state, call it every `interval` ms or at the time returned by `ikcp_check`
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_update(kcp: *mut IKCPCB, current: u32) {
+    (*kcp).kcp.update(current);
+}
+
+/// Time of the next required `ikcp_update`
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_check(kcp: *const IKCPCB, current: u32) -> u32 {
+    (*kcp).kcp.check(current)
+}
+
+/// Feed a datagram received from the peer
+///
+/// # Returns
+/// 0 on success, -1 when shorter than a segment header or for another
+/// conversation, -2 when a segment is longer than the datagram, -3 for an
+/// unknown command
+///
+/// # Safety
+/// `kcp` must be a live handle and `data` valid for `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_input(kcp: *mut IKCPCB, data: *const c_char, size: c_long) -> c_int {
+    let kcp = &mut (*kcp).kcp;
+    if data.is_null() || size < IKCP_OVERHEAD as c_long {
+        return -1;
+    }
+    match kcp.input(std::slice::from_raw_parts(data.cast(), size as usize)) {
+        Ok(()) => 0,
+        Err(error) => error_code(error),
+    }
+}
+
+/// Flush pending data and acknowledgements
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_flush(kcp: *mut IKCPCB) {
+    (*kcp).kcp.flush();
+}
+
+/// Size of the next message, -1 when none is complete
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_peeksize(kcp: *const IKCPCB) -> c_int {
+    (*kcp).kcp.peek_size().map_or(-1, |size| size as c_int)
+}
+
+/// Change the MTU, 0 on success, -1 when smaller than 50 bytes or the overhead
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_setmtu(kcp: *mut IKCPCB, mtu: c_int) -> c_int {
+    if mtu < 0 {
+        return -1;
+    }
+    (*kcp).kcp.set_mtu(mtu as u32).map_or(-1, |()| 0)
+}
+
+/// Set the window sizes in segments, values below 1 are left unchanged
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_wndsize(kcp: *mut IKCPCB, sndwnd: c_int, rcvwnd: c_int) -> c_int {
+    (*kcp)
+        .kcp
+        .set_wndsize(sndwnd.max(0) as u32, rcvwnd.max(0) as u32);
+    0
+}
+
+/// Number of segments waiting to be sent or acknowledged
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_waitsnd(kcp: *const IKCPCB) -> c_int {
+    (*kcp).kcp.wait_snd() as c_int
+}
+
+/// Configure the nodelay mode, negative values are left unchanged
+///
+/// # Arguments
+/// * `nodelay` - 0: disable, 1: enable, 2: enable with a less aggressive RTO
+///   backoff, also resets the minimum retransmission timeout
+/// * `interval` - Internal update interval in milliseconds
+/// * `resend` - Fast resend threshold, 0 disables fast resend
+/// * `nc` - 1 ignores the congestion window
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_nodelay(
+    kcp: *mut IKCPCB,
+    nodelay: c_int,
+    interval: c_int,
+    resend: c_int,
+    nc: c_int,
+) -> c_int {
+    let kcp = &mut (*kcp).kcp;
+    if nodelay >= 0 {
+        kcp.nodelay = nodelay as u32;
+        kcp.rx_minrto = if nodelay != 0 {
+            IKCP_RTO_NDL
+        } else {
+            IKCP_RTO_MIN
+        };
+    }
+    if interval >= 0 {
+        kcp.set_interval(interval as u32);
+    }
+    if resend >= 0 {
+        kcp.fastresend = resend;
+    }
+    if nc >= 0 {
+        kcp.set_nocwnd(nc != 0);
+    }
+    0
+}
+
+/// Set the update interval, clamped to 10..=5000 ms
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_interval(kcp: *mut IKCPCB, interval: c_int) -> c_int {
+    (*kcp).kcp.set_interval(interval.max(0) as u32);
+    0
+}
+
+/// Enable stream mode when `stream` is not 0, replaces `kcp->stream = stream`
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_setstream(kcp: *mut IKCPCB, stream: c_int) -> c_int {
+    (*kcp).kcp.streaming_mode = stream != 0;
+    0
+}
+
+/// Set the minimum retransmission timeout in ms, replaces `kcp->rx_minrto = minrto`
+///
+/// `ikcp_nodelay` with a non-negative `nodelay` resets it, as in ikcp.
+///
+/// # Returns
+/// 0 on success, -1 on a negative timeout
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_setminrto(kcp: *mut IKCPCB, minrto: c_int) -> c_int {
+    if minrto < 0 {
+        return -1;
+    }
+    (*kcp).kcp.rx_minrto = minrto as u32;
+    0
+}
+
+/// Set the fast resend threshold, 0 disables fast resend, replaces
+/// `kcp->fastresend = resend`
+///
+/// # Returns
+/// 0 on success, -1 on a negative threshold
+///
+/// # Safety
+/// `kcp` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_setfastresend(kcp: *mut IKCPCB, resend: c_int) -> c_int {
+    if resend < 0 {
+        return -1;
+    }
+    (*kcp).kcp.fastresend = resend;
+    0
+}
+
+/// Read the conversation id of a datagram
+///
+/// # Safety
+/// `ptr` must be valid for 4 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ikcp_getconv(ptr: *const c_void) -> u32 {
+    u32::from_le_bytes(std::ptr::read_unaligned(ptr.cast()))
+}
@@ -0,0 +1,200 @@
+/* Exercises the ikcp.h API through a lossy in-memory link */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "ikcp.h"
+
+#define CHECK(cond)                                                          \
+	do {                                                                     \
+		if (!(cond)) {                                                       \
+			fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+			        #cond);                                                  \
+			exit(1);                                                         \
+		}                                                                    \
+	} while (0)
+
+#define MAX_PACKETS 4096
+#define MESSAGES 200
+
+struct packet {
+	int len;
+	char data[1500];
+};
+
+struct endpoint {
+	ikcpcb *kcp;
+	struct endpoint *peer;
+	struct packet queue[MAX_PACKETS];
+	int queued;
+	int sent;
+	int logs;
+};
+
+static int output(const char *buf, int len, ikcpcb *kcp, void *user)
+{
+	struct endpoint *self = (struct endpoint *)user;
+	struct endpoint *peer = self->peer;
+	(void)kcp;
+	CHECK(len > 0 && len <= (int)sizeof(peer->queue[0].data));
+	/* drop one datagram out of seven */
+	if (++self->sent % 7 == 0 || peer->queued == MAX_PACKETS)
+		return 0;
+	memcpy(peer->queue[peer->queued].data, buf, len);
+	peer->queue[peer->queued++].len = len;
+	return 0;
+}
+
+static void writelog(const char *log, ikcpcb *kcp, void *user)
+{
+	(void)kcp;
+	CHECK(strlen(log) > 0);
+	((struct endpoint *)user)->logs++;
+}
+
+static void deliver(struct endpoint *self)
+{
+	int i;
+	for (i = 0; i < self->queued; i++)
+		CHECK(ikcp_input(self->kcp, self->queue[i].data, self->queue[i].len) == 0);
+	self->queued = 0;
+}
+
+static int message_size(int i)
+{
+	/* mostly small messages, with fragmented ones mixed in */
+	return i % 10 == 0 ? 3000 + i : 1 + (i * 37) % 900;
+}
+
+static void fill(char *buf, int i, int size)
+{
+	int j;
+	for (j = 0; j < size; j++)
+		buf[j] = (char)(i * 31 + j);
+}
+
+int main(void)
+{
+	static struct endpoint a, b, c;
+	static char buf[8192], expected[8192];
+	IUINT32 current = 0;
+	int received = 0, i;
+
+	a.peer = &b;
+	b.peer = &a;
+	a.kcp = ikcp_create(0x11223344, &a);
+	b.kcp = ikcp_create(0x11223344, &b);
+	ikcp_setoutput(a.kcp, output);
+	ikcp_setoutput(b.kcp, output);
+	ikcp_setlog(b.kcp, writelog);
+	ikcp_logmask(b.kcp, IKCP_LOG_INPUT | IKCP_LOG_RECV);
+	CHECK(ikcp_nodelay(a.kcp, 1, 10, 2, 1) == 0);
+	CHECK(ikcp_nodelay(b.kcp, 1, 10, 2, 1) == 0);
+	CHECK(ikcp_wndsize(a.kcp, 256, 256) == 0);
+	CHECK(ikcp_wndsize(b.kcp, 256, 256) == 0);
+
+	CHECK(ikcp_setmtu(a.kcp, 10) == -1);
+	CHECK(ikcp_setmtu(a.kcp, 1200) == 0);
+	CHECK(ikcp_setmtu(b.kcp, 1200) == 0);
+	CHECK(ikcp_send(a.kcp, buf, -1) == -1);
+	CHECK(ikcp_recv(b.kcp, buf, sizeof(buf)) == -1);
+	CHECK(ikcp_peeksize(b.kcp) == -1);
+
+	for (i = 0; i < MESSAGES; i++) {
+		int size = message_size(i);
+		fill(buf, i, size);
+		CHECK(ikcp_send(a.kcp, buf, size) == size);
+	}
+	CHECK(ikcp_waitsnd(a.kcp) >= MESSAGES);
+
+	while (received < MESSAGES && current < 60000) {
+		ikcp_update(a.kcp, current);
+		ikcp_update(b.kcp, current);
+		deliver(&b);
+		deliver(&a);
+
+		while (ikcp_peeksize(b.kcp) > 0) {
+			int size = message_size(received);
+			CHECK(ikcp_peeksize(b.kcp) == size);
+			CHECK(ikcp_recv(b.kcp, buf, size - 1) == -3);
+			CHECK(ikcp_recv(b.kcp, buf, -(int)sizeof(buf)) == size);
+			memset(buf, 0, size);
+			CHECK(ikcp_recv(b.kcp, buf, sizeof(buf)) == size);
+			fill(expected, received, size);
+			CHECK(memcmp(buf, expected, size) == 0);
+			received++;
+		}
+		CHECK(ikcp_check(a.kcp, current) - current <= 10);
+		current += 10;
+	}
+	CHECK(received == MESSAGES);
+	CHECK(b.logs > 0);
+
+	/* drain the last acknowledgements */
+	for (i = 0; i < 100 && ikcp_waitsnd(a.kcp) > 0; i++) {
+		current += 10;
+		ikcp_update(a.kcp, current);
+		ikcp_update(b.kcp, current);
+		deliver(&b);
+		deliver(&a);
+	}
+	CHECK(ikcp_waitsnd(a.kcp) == 0);
+
+	/* datagram checks */
+	a.sent = 0;
+	CHECK(ikcp_send(a.kcp, "x", 1) == 1);
+	ikcp_flush(a.kcp);
+	CHECK(b.queued == 1);
+	CHECK(ikcp_getconv(b.queue[0].data) == 0x11223344);
+	CHECK(ikcp_input(b.kcp, b.queue[0].data, 10) == -1);
+	b.queue[0].data[0] ^= 1;
+	CHECK(ikcp_input(b.kcp, b.queue[0].data, b.queue[0].len) == -1);
+	b.queue[0].data[0] ^= 1;
+	CHECK(ikcp_input(b.kcp, b.queue[0].data, b.queue[0].len - 1) == -2);
+	b.queue[0].data[4] = 99;
+	CHECK(ikcp_input(b.kcp, b.queue[0].data, b.queue[0].len) == -3);
+	b.queue[0].data[4] = 81;
+	CHECK(ikcp_input(b.kcp, b.queue[0].data, b.queue[0].len) == 0);
+	b.queued = 0;
+	CHECK(ikcp_recv(b.kcp, buf, sizeof(buf)) == 1 && buf[0] == 'x');
+
+	/* setters replacing direct field writes */
+	CHECK(ikcp_setminrto(a.kcp, -1) == -1);
+	CHECK(ikcp_setminrto(a.kcp, 50) == 0);
+	CHECK(ikcp_setfastresend(a.kcp, -1) == -1);
+	CHECK(ikcp_setfastresend(a.kcp, 3) == 0);
+	CHECK(ikcp_setstream(a.kcp, 1) == 0);
+	CHECK(ikcp_setstream(b.kcp, 1) == 0);
+	/* stream mode appends to the last queued segment */
+	a.sent = 0;
+	CHECK(ikcp_send(a.kcp, "ab", 2) == 2);
+	CHECK(ikcp_send(a.kcp, "cd", 2) == 2);
+	ikcp_flush(a.kcp);
+	CHECK(b.queued == 1 && b.queue[0].len == 24 + 4);
+	deliver(&b);
+	CHECK(ikcp_recv(b.kcp, buf, sizeof(buf)) == 4 && memcmp(buf, "abcd", 4) == 0);
+
+	/* negative nodelay arguments leave the settings alone */
+	c.peer = &b;
+	c.kcp = ikcp_create(0x11223344, &c);
+	ikcp_setoutput(c.kcp, output);
+	CHECK(ikcp_nodelay(c.kcp, 1, 10, 2, 1) == 0);
+	CHECK(ikcp_nodelay(c.kcp, -1, -1, -1, -1) == 0);
+	for (i = 0; i < 10; i++)
+		CHECK(ikcp_send(c.kcp, expected, 1000) == 1000);
+	ikcp_update(c.kcp, current);
+	/* the congestion window is still ignored, one datagram per segment */
+	CHECK(c.sent == 10);
+	CHECK(ikcp_nodelay(c.kcp, -1, -1, -1, 0) == 0);
+	CHECK(ikcp_send(c.kcp, expected, 1000) == 1000);
+	ikcp_flush(c.kcp);
+	CHECK(c.sent == 10);
+	b.queued = 0;
+
+	ikcp_release(a.kcp);
+	ikcp_release(b.kcp);
+	ikcp_release(c.kcp);
+	ikcp_release(NULL);
+	printf("%d messages received\n", received);
+	return 0;
+}
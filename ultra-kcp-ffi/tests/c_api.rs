@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Build the static library, `cargo test` only keeps the rlib of the crate
+fn static_library() -> PathBuf {
+    let status = Command::new(env!("CARGO"))
+        .args([
+            "build",
+            "--quiet",
+            "--lib",
+            "--package",
+            env!("CARGO_PKG_NAME"),
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success(), "cargo build failed");
+
+    // target/<profile>/deps/<test binary>
+    let exe = std::env::current_exe().unwrap();
+    let profile_dir = exe.parent().unwrap().parent().unwrap();
+    profile_dir.join("libultra_kcp_ffi.a")
+}
+
+/// Build the C loopback program against `libultra_kcp_ffi.a` and run it
+#[test]
+fn c_program_runs_against_static_library() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let library = static_library();
+    assert!(library.exists(), "missing {}", library.display());
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ikcp_loopback");
+
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".into()))
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-o"])
+        .arg(&program)
+        .arg(root.join("tests/c/loopback.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm"])
+        .status()
+        .expect("a C compiler is required");
+    assert!(status.success(), "compilation failed");
+
+    let output = Command::new(&program).output().unwrap();
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success());
+}
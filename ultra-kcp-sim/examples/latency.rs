@@ -0,0 +1,35 @@
+//! Latency comparison of the classic ikcp modes, after ikcp's test.cpp:
+//! 10% loss and 60-125ms RTT, 1000 small messages.
+
+use ultra_kcp_core::kcp::KcpControl;
+use ultra_kcp_sim::link::LinkConfig;
+use ultra_kcp_sim::sim::{payloads, SimConfig, Simulation};
+
+type Setup = fn(&mut KcpControl);
+
+fn main() {
+    let modes: [(&str, Setup); 3] = [
+        ("default", |kcp| kcp.set_nodelay(0, 10, 0, false)),
+        ("normal", |kcp| kcp.set_nodelay(0, 10, 0, true)),
+        ("fast", |kcp| {
+            kcp.set_nodelay(2, 10, 2, true);
+            kcp.rx_minrto = 10;
+            kcp.fastresend = 1;
+        }),
+    ];
+
+    for (name, setup) in modes {
+        let mut sim = Simulation::new(SimConfig::symmetric(LinkConfig {
+            loss: 0.1,
+            delay: 30,
+            jitter: 32,
+            ..Default::default()
+        }));
+        sim.configure(|kcp| {
+            kcp.set_wndsize(128, 128);
+            setup(kcp);
+        });
+        let report = sim.transfer(&payloads(1000, 64, 1), 600_000);
+        println!("{name:>8}: {report}");
+    }
+}
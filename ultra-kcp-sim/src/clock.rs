@@ -0,0 +1,33 @@
+/// Simulated millisecond clock shared by both endpoints and the links
+///
+/// Starts at an arbitrary timestamp so that tests can place a run right
+/// before the u32 wraparound.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VirtualClock {
+    now: u32,
+    elapsed: u64,
+}
+
+impl VirtualClock {
+    pub const fn new(start: u32) -> Self {
+        Self {
+            now: start,
+            elapsed: 0,
+        }
+    }
+
+    /// Current timestamp (ms), wrapping like the KCP clock
+    pub const fn now(&self) -> u32 {
+        self.now
+    }
+
+    /// Time elapsed since the clock was created (ms)
+    pub const fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    pub fn advance(&mut self, ms: u32) {
+        self.now = self.now.wrapping_add(ms);
+        self.elapsed += ms as u64;
+    }
+}
@@ -0,0 +1,4 @@
+pub mod clock;
+pub mod link;
+pub mod rng;
+pub mod sim;
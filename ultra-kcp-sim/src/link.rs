@@ -0,0 +1,210 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::rng::SimRng;
+
+/// Two state (Gilbert-Elliott) burst loss model
+///
+/// The link alternates between a good state, using `LinkConfig::loss`, and
+/// a bad state dropping packets with probability `loss`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurstLoss {
+    /// Probability of entering the bad state, per packet
+    pub enter: f64,
+    /// Probability of leaving the bad state, per packet
+    pub leave: f64,
+    /// Loss probability while in the bad state
+    pub loss: f64,
+}
+
+/// Impairments applied by one direction of a simulated link
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LinkConfig {
+    /// Independent loss probability per packet
+    pub loss: f64,
+    /// Correlated loss, on top of `loss`
+    pub burst_loss: Option<BurstLoss>,
+    /// Fixed one-way propagation delay (ms)
+    pub delay: u32,
+    /// Uniform random delay added to `delay`, in `[0, jitter]` (ms)
+    pub jitter: u32,
+    /// Probability that a packet is held back by `reorder_delay` and
+    /// overtaken by the packets sent after it
+    pub reorder: f64,
+    /// Extra delay of reordered packets (ms)
+    pub reorder_delay: u32,
+    /// Probability that a packet is delivered twice
+    pub duplicate: f64,
+    /// Probability that one random bit of a packet is flipped
+    pub corrupt: f64,
+    /// Bottleneck bandwidth in bytes per second, unlimited when `None`
+    pub bandwidth: Option<u64>,
+    /// Bytes that may wait for the bottleneck before tail drop, unlimited when `None`
+    pub queue_limit: Option<usize>,
+    /// Packets larger than this are dropped, as on a path MTU black hole
+    pub mtu: Option<usize>,
+}
+
+impl LinkConfig {
+    /// Perfect link with a fixed one-way delay
+    pub fn with_delay(delay: u32) -> Self {
+        Self {
+            delay,
+            ..Default::default()
+        }
+    }
+}
+
+/// Per direction counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Packets handed to the link
+    pub sent: u64,
+    /// Packets handed to the receiver, duplicates included
+    pub delivered: u64,
+    /// Packets dropped by random or burst loss
+    pub lost: u64,
+    /// Packets dropped because the bottleneck queue was full
+    pub queue_drops: u64,
+    /// Packets dropped for exceeding the MTU
+    pub oversize_drops: u64,
+    /// Extra copies injected
+    pub duplicated: u64,
+    /// Packets delivered with a flipped bit
+    pub corrupted: u64,
+    /// Packets held back for reordering
+    pub reordered: u64,
+}
+
+/// One direction of a simulated link
+pub struct Link {
+    config: LinkConfig,
+    rng: SimRng,
+    bad_state: bool,
+    /// Time the bottleneck finishes serializing queued packets (us)
+    busy_until: u64,
+    /// Delivery time of the last in-order packet, keeps jitter from reordering
+    last_delivery: u64,
+    /// Packets in flight, ordered by (delivery time, send order)
+    in_flight: BinaryHeap<Reverse<(u64, u64, Vec<u8>)>>,
+    order: u64,
+    stats: LinkStats,
+}
+
+impl Link {
+    pub fn new(config: LinkConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: SimRng::new(seed),
+            bad_state: false,
+            busy_until: 0,
+            last_delivery: 0,
+            in_flight: BinaryHeap::new(),
+            order: 0,
+            stats: LinkStats::default(),
+        }
+    }
+
+    pub const fn config(&self) -> &LinkConfig {
+        &self.config
+    }
+
+    pub const fn stats(&self) -> &LinkStats {
+        &self.stats
+    }
+
+    /// Packets currently travelling on the link
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Hand a packet to the link
+    ///
+    /// # Arguments
+    /// * `now` - Elapsed simulation time (ms)
+    /// * `packet` - The datagram
+    pub fn send(&mut self, now: u64, mut packet: Vec<u8>) {
+        self.stats.sent += 1;
+
+        if self.config.mtu.is_some_and(|mtu| packet.len() > mtu) {
+            self.stats.oversize_drops += 1;
+            return;
+        }
+        if self.lose() {
+            self.stats.lost += 1;
+            return;
+        }
+
+        let now_us = now * 1000;
+        let mut depart = now_us;
+        if let Some(bandwidth) = self.config.bandwidth {
+            let backlog = self.busy_until.saturating_sub(now_us) * bandwidth / 1_000_000;
+            if self
+                .config
+                .queue_limit
+                .is_some_and(|limit| backlog as usize + packet.len() > limit)
+            {
+                self.stats.queue_drops += 1;
+                return;
+            }
+            let serialize = (packet.len() as u64 * 1_000_000).div_ceil(bandwidth.max(1));
+            self.busy_until = self.busy_until.max(now_us) + serialize;
+            depart = self.busy_until;
+        }
+
+        let jitter = self.rng.below_or_eq(self.config.jitter) as u64;
+        let mut delivery = depart + (self.config.delay as u64 + jitter) * 1000;
+        if self.rng.chance(self.config.reorder) {
+            self.stats.reordered += 1;
+            delivery += self.config.reorder_delay as u64 * 1000;
+        } else {
+            delivery = delivery.max(self.last_delivery);
+            self.last_delivery = delivery;
+        }
+
+        if !packet.is_empty() && self.rng.chance(self.config.corrupt) {
+            self.stats.corrupted += 1;
+            let bit = self.rng.next_u64() % (packet.len() as u64 * 8);
+            packet[(bit / 8) as usize] ^= 1 << (bit % 8);
+        }
+
+        if self.rng.chance(self.config.duplicate) {
+            self.stats.duplicated += 1;
+            self.push(delivery, packet.clone());
+        }
+        self.push(delivery, packet);
+    }
+
+    /// Take the next packet due at `now` (ms)
+    pub fn poll(&mut self, now: u64) -> Option<Vec<u8>> {
+        let Reverse((delivery, _, _)) = self.in_flight.peek()?;
+        if *delivery > now * 1000 {
+            return None;
+        }
+        let Reverse((_, _, packet)) = self.in_flight.pop()?;
+        self.stats.delivered += 1;
+        Some(packet)
+    }
+
+    fn push(&mut self, delivery: u64, packet: Vec<u8>) {
+        self.in_flight.push(Reverse((delivery, self.order, packet)));
+        self.order += 1;
+    }
+
+    fn lose(&mut self) -> bool {
+        if let Some(burst) = self.config.burst_loss {
+            let flip = if self.bad_state {
+                burst.leave
+            } else {
+                burst.enter
+            };
+            if self.rng.chance(flip) {
+                self.bad_state = !self.bad_state;
+            }
+            if self.bad_state && self.rng.chance(burst.loss) {
+                return true;
+            }
+        }
+        self.rng.chance(self.config.loss)
+    }
+}
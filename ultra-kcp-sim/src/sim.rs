@@ -0,0 +1,297 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use ultra_kcp_core::kcp::{KcpCallBack, KcpControl};
+
+use crate::clock::VirtualClock;
+use crate::link::{Link, LinkConfig, LinkStats};
+use crate::rng::SimRng;
+
+/// Parameters of a simulated connection
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// Sender to receiver direction
+    pub forward: LinkConfig,
+    /// Receiver to sender direction
+    pub backward: LinkConfig,
+    /// Seed of every random decision taken by the links
+    pub seed: u64,
+    /// Initial timestamp handed to `update`
+    pub start_time: u32,
+    /// First sequence number used in both directions
+    pub initial_sn: u32,
+    /// Clock advance per step (ms)
+    pub tick: u32,
+    /// Conversation id of both endpoints
+    pub conv: u32,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            forward: LinkConfig::default(),
+            backward: LinkConfig::default(),
+            seed: 1,
+            start_time: 0,
+            initial_sn: 0,
+            tick: 1,
+            conv: 0x11223344,
+        }
+    }
+}
+
+impl SimConfig {
+    /// Same impairments in both directions
+    pub fn symmetric(link: LinkConfig) -> Self {
+        Self {
+            forward: link.clone(),
+            backward: link,
+            ..Default::default()
+        }
+    }
+}
+
+/// Datagrams output by a control block, oldest first
+pub type Outbox = Arc<Mutex<Vec<Vec<u8>>>>;
+
+/// Output callback appending every datagram to an `Outbox`
+pub struct OutboxCallback(pub Outbox);
+
+impl KcpCallBack for OutboxCallback {
+    fn output(&self, buf: &[u8], _kcp: &mut KcpControl, _user: Option<&Box<dyn Any>>) {
+        self.0.lock().unwrap().push(buf.to_vec());
+    }
+}
+
+/// Outcome of `Simulation::transfer`
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Messages handed to the sender
+    pub messages: usize,
+    /// Messages received
+    pub delivered: usize,
+    /// Messages received with unexpected content
+    pub mismatched: usize,
+    /// Payload bytes received
+    pub bytes: u64,
+    /// Time from the first send to the last receive, or the timeout (ms)
+    pub duration: u64,
+    /// Average time from `send` to `receive` (ms)
+    pub avg_latency: f64,
+    /// Largest time from `send` to `receive` (ms)
+    pub max_latency: u64,
+    /// Segments retransmitted by the sender after a timeout
+    pub retransmissions: u32,
+    /// Datagrams rejected by `input`
+    pub input_errors: u64,
+    pub forward: LinkStats,
+    pub backward: LinkStats,
+}
+
+impl Report {
+    /// Every message was received intact
+    pub fn is_complete(&self) -> bool {
+        self.delivered == self.messages && self.mismatched == 0
+    }
+
+    /// Goodput in bytes per second
+    pub fn throughput(&self) -> f64 {
+        if self.duration == 0 {
+            return 0.0;
+        }
+        self.bytes as f64 * 1000.0 / self.duration as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "delivered {}/{} in {}ms, avg latency {:.1}ms, max latency {}ms, \
+             throughput {:.0} B/s, retransmissions {}, lost {}/{}",
+            self.delivered,
+            self.messages,
+            self.duration,
+            self.avg_latency,
+            self.max_latency,
+            self.throughput(),
+            self.retransmissions,
+            self.forward.lost + self.backward.lost,
+            self.forward.sent + self.backward.sent,
+        )
+    }
+}
+
+/// Two KCP endpoints connected by a pair of simulated links
+///
+/// Everything runs on a virtual clock advanced by `step`, so that a run
+/// depends only on its configuration and seed.
+pub struct Simulation {
+    clock: VirtualClock,
+    tick: u32,
+    pub sender: KcpControl,
+    pub receiver: KcpControl,
+    forward: Link,
+    backward: Link,
+    sender_out: Outbox,
+    receiver_out: Outbox,
+    input_errors: u64,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Self {
+        let mut seeds = SimRng::new(config.seed);
+        let sender_out = Outbox::default();
+        let receiver_out = Outbox::default();
+
+        let mut sender = KcpControl::new_on_stack(config.conv, None);
+        sender.set_callback(Box::new(OutboxCallback(sender_out.clone())));
+        let mut receiver = KcpControl::new_on_stack(config.conv, None);
+        receiver.set_callback(Box::new(OutboxCallback(receiver_out.clone())));
+        for kcp in [&mut sender, &mut receiver] {
+            kcp.snd_una = config.initial_sn;
+            kcp.snd_nxt = config.initial_sn;
+            kcp.rcv_nxt = config.initial_sn;
+        }
+
+        Self {
+            clock: VirtualClock::new(config.start_time),
+            tick: config.tick.max(1),
+            sender,
+            receiver,
+            forward: Link::new(config.forward, seeds.next_u64()),
+            backward: Link::new(config.backward, seeds.next_u64()),
+            sender_out,
+            receiver_out,
+            input_errors: 0,
+        }
+    }
+
+    pub const fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    pub const fn forward(&self) -> &Link {
+        &self.forward
+    }
+
+    pub const fn backward(&self) -> &Link {
+        &self.backward
+    }
+
+    /// Apply the same configuration to both endpoints
+    pub fn configure<F: FnMut(&mut KcpControl)>(&mut self, mut setup: F) {
+        setup(&mut self.sender);
+        setup(&mut self.receiver);
+    }
+
+    /// Advance the clock by one tick
+    ///
+    /// Updates both endpoints, hands their output to the links and feeds
+    /// the datagrams that have arrived to the opposite endpoint.
+    pub fn step(&mut self) {
+        self.clock.advance(self.tick);
+        let now = self.clock.now();
+        let elapsed = self.clock.elapsed();
+
+        self.sender.update(now);
+        self.receiver.update(now);
+
+        for packet in self.sender_out.lock().unwrap().drain(..) {
+            self.forward.send(elapsed, packet);
+        }
+        for packet in self.receiver_out.lock().unwrap().drain(..) {
+            self.backward.send(elapsed, packet);
+        }
+
+        while let Some(packet) = self.forward.poll(elapsed) {
+            if self.receiver.input(&packet).is_err() {
+                self.input_errors += 1;
+            }
+        }
+        while let Some(packet) = self.backward.poll(elapsed) {
+            if self.sender.input(&packet).is_err() {
+                self.input_errors += 1;
+            }
+        }
+    }
+
+    /// Send `messages` from the sender to the receiver and check they
+    /// arrive intact and in order
+    ///
+    /// # Arguments
+    /// * `messages` - Payloads, sent as fast as the send queue accepts them
+    /// * `timeout` - Simulated time after which the transfer is abandoned (ms)
+    pub fn transfer(&mut self, messages: &[Vec<u8>], timeout: u64) -> Report {
+        let start = self.clock.elapsed();
+        let max_pending = (self.sender.send_window as usize * 2).max(64);
+        let mut report = Report {
+            messages: messages.len(),
+            ..Default::default()
+        };
+        let mut sent_at = VecDeque::new();
+        let mut latency_sum = 0;
+        let mut buf = Vec::new();
+
+        while report.delivered < messages.len() && self.clock.elapsed() - start < timeout {
+            while sent_at.len() + report.delivered < messages.len()
+                && self.sender.wait_snd() < max_pending
+            {
+                let message = &messages[sent_at.len() + report.delivered];
+                if self.sender.send(message).is_err() {
+                    break;
+                }
+                sent_at.push_back(self.clock.elapsed());
+            }
+
+            self.step();
+
+            while report.delivered < messages.len() {
+                let Ok(size) = self.receiver.peek_size() else {
+                    break;
+                };
+                buf.resize(size, 0);
+                let Ok(n) = self.receiver.receive(Some(&mut buf), false) else {
+                    break;
+                };
+                let latency = self.clock.elapsed() - sent_at.pop_front().unwrap_or(start);
+                if buf[..n] != messages[report.delivered][..] {
+                    report.mismatched += 1;
+                }
+                latency_sum += latency;
+                report.max_latency = report.max_latency.max(latency);
+                report.bytes += n as u64;
+                report.delivered += 1;
+            }
+        }
+
+        // output beyond the messages, e.g. a stream mode message received in pieces
+        while let Ok(size) = self.receiver.peek_size() {
+            buf.resize(size, 0);
+            if self.receiver.receive(Some(&mut buf), false).is_err() {
+                break;
+            }
+            report.mismatched += 1;
+        }
+
+        report.duration = self.clock.elapsed() - start;
+        if report.delivered > 0 {
+            report.avg_latency = latency_sum as f64 / report.delivered as f64;
+        }
+        report.retransmissions = self.sender.xmit;
+        report.input_errors = self.input_errors;
+        report.forward = *self.forward.stats();
+        report.backward = *self.backward.stats();
+        report
+    }
+}
+
+/// Generate `count` pseudo random payloads of `size` bytes
+pub fn payloads(count: usize, size: usize, seed: u64) -> Vec<Vec<u8>> {
+    let mut rng = SimRng::new(seed);
+    (0..count)
+        .map(|_| (0..size).map(|_| rng.next_u64() as u8).collect())
+        .collect()
+}
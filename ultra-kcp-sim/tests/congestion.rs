@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex};
+
+use ultra_kcp_core::bbr::{BbrController, BbrMode};
+use ultra_kcp_core::congestion::{
+    AckEvent, CongestionController, KcpClassic, LossEvent, TimeoutEvent,
+};
+use ultra_kcp_sim::link::LinkConfig;
+use ultra_kcp_sim::sim::{payloads, Report, SimConfig, Simulation};
+
+/// Shares a controller with the test so its state can be inspected
+struct Shared<C>(Arc<Mutex<C>>);
+
+impl<C: CongestionController> CongestionController for Shared<C> {
+    fn on_ack(&mut self, event: &AckEvent) {
+        self.0.lock().unwrap().on_ack(event);
+    }
+
+    fn on_loss(&mut self, event: &LossEvent) {
+        self.0.lock().unwrap().on_loss(event);
+    }
+
+    fn on_timeout(&mut self, event: &TimeoutEvent) {
+        self.0.lock().unwrap().on_timeout(event);
+    }
+
+    fn window(&self) -> u32 {
+        self.0.lock().unwrap().window()
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        self.0.lock().unwrap().pacing_rate()
+    }
+}
+
+/// 3% loss, 30ms one-way delay, 1MB/s bottleneck in both directions
+fn lossy_link() -> SimConfig {
+    SimConfig::symmetric(LinkConfig {
+        loss: 0.03,
+        delay: 30,
+        bandwidth: Some(1_000_000),
+        ..Default::default()
+    })
+}
+
+fn transfer(congestion: Box<dyn CongestionController>, count: usize) -> Report {
+    let mut sim = Simulation::new(lossy_link());
+    sim.configure(|kcp| {
+        kcp.set_nodelay(1, 10, 2, false);
+        kcp.set_wndsize(512, 512);
+    });
+    sim.sender.set_congestion(congestion);
+    sim.transfer(&payloads(count, 1000, 3), 600_000)
+}
+
+#[test]
+fn bbr_outperforms_classic_on_lossy_link() {
+    let bbr = Arc::new(Mutex::new(BbrController::new()));
+
+    let classic = transfer(Box::new(KcpClassic::default()), 4096);
+    let paced = transfer(Box::new(Shared(bbr.clone())), 4096);
+
+    assert!(classic.is_complete(), "{classic}");
+    assert!(paced.is_complete(), "{paced}");
+    let bbr = bbr.lock().unwrap();
+    assert_eq!(bbr.mode(), BbrMode::ProbeBw);
+    assert!(
+        bbr.min_rtt() >= 60 && bbr.min_rtt() < 100,
+        "min_rtt {}",
+        bbr.min_rtt()
+    );
+    assert!(bbr.pacing_rate().is_some());
+    assert!(
+        paced.duration * 2 < classic.duration,
+        "classic: {classic}, bbr: {paced}"
+    );
+}
+
+#[test]
+fn bbr_is_deterministic() {
+    let first = transfer(Box::new(BbrController::new()), 512);
+    let second = transfer(Box::new(BbrController::new()), 512);
+    assert_eq!(first.duration, second.duration);
+    assert_eq!(first.retransmissions, second.retransmissions);
+}
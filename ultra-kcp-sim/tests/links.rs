@@ -0,0 +1,170 @@
+use ultra_kcp_sim::link::{BurstLoss, LinkConfig};
+use ultra_kcp_sim::sim::{payloads, Report, SimConfig, Simulation};
+
+fn run(config: SimConfig, count: usize, size: usize) -> Report {
+    let mut sim = Simulation::new(config);
+    sim.configure(|kcp| {
+        kcp.set_nodelay(1, 10, 2, true);
+        kcp.set_wndsize(128, 128);
+    });
+    sim.transfer(&payloads(count, size, 7), 120_000)
+}
+
+#[test]
+fn perfect_link() {
+    let report = run(SimConfig::symmetric(LinkConfig::with_delay(20)), 500, 1000);
+    assert!(report.is_complete(), "{report}");
+    assert_eq!(report.retransmissions, 0);
+    assert!(report.avg_latency >= 20.0);
+}
+
+#[test]
+fn random_loss() {
+    let report = run(
+        SimConfig::symmetric(LinkConfig {
+            loss: 0.1,
+            delay: 20,
+            ..Default::default()
+        }),
+        500,
+        3000,
+    );
+    assert!(report.is_complete(), "{report}");
+    assert!(report.forward.lost > 0);
+    assert!(report.retransmissions > 0);
+}
+
+#[test]
+fn burst_loss() {
+    let report = run(
+        SimConfig::symmetric(LinkConfig {
+            delay: 20,
+            burst_loss: Some(BurstLoss {
+                enter: 0.02,
+                leave: 0.3,
+                loss: 0.8,
+            }),
+            ..Default::default()
+        }),
+        500,
+        1000,
+    );
+    assert!(report.is_complete(), "{report}");
+    assert!(report.forward.lost > 0);
+}
+
+#[test]
+fn jitter_reordering_and_duplication() {
+    let report = run(
+        SimConfig::symmetric(LinkConfig {
+            delay: 20,
+            jitter: 15,
+            reorder: 0.1,
+            reorder_delay: 30,
+            duplicate: 0.05,
+            ..Default::default()
+        }),
+        500,
+        2000,
+    );
+    assert!(report.is_complete(), "{report}");
+    assert!(report.forward.reordered > 0);
+    assert!(report.forward.duplicated > 0);
+}
+
+#[test]
+fn bandwidth_cap_limits_throughput() {
+    let report = run(
+        SimConfig::symmetric(LinkConfig {
+            delay: 10,
+            bandwidth: Some(200_000),
+            queue_limit: Some(64 * 1024),
+            ..Default::default()
+        }),
+        400,
+        1000,
+    );
+    assert!(report.is_complete(), "{report}");
+    assert!(report.throughput() <= 200_000.0);
+    assert!(report.throughput() > 100_000.0);
+}
+
+#[test]
+fn mtu_clamp_requires_smaller_kcp_mtu() {
+    let config = SimConfig::symmetric(LinkConfig {
+        delay: 10,
+        mtu: Some(576),
+        ..Default::default()
+    });
+
+    let mut sim = Simulation::new(config.clone());
+    let blocked = sim.transfer(&payloads(10, 4000, 1), 5000);
+    assert_eq!(blocked.delivered, 0);
+    assert!(blocked.forward.oversize_drops > 0);
+
+    let mut sim = Simulation::new(config);
+    sim.configure(|kcp| kcp.set_mtu(576).unwrap());
+    assert!(sim.transfer(&payloads(10, 4000, 1), 5000).is_complete());
+}
+
+#[test]
+fn same_seed_same_run() {
+    let config = SimConfig {
+        seed: 42,
+        ..SimConfig::symmetric(LinkConfig {
+            loss: 0.05,
+            delay: 15,
+            jitter: 10,
+            reorder: 0.05,
+            reorder_delay: 20,
+            ..Default::default()
+        })
+    };
+    let first = run(config.clone(), 300, 1500);
+    let second = run(config, 300, 1500);
+    assert_eq!(first.duration, second.duration);
+    assert_eq!(first.max_latency, second.max_latency);
+    assert_eq!(first.forward, second.forward);
+    assert_eq!(first.backward, second.backward);
+}
+
+#[test]
+fn checksum_drops_corrupted_datagrams() {
+    let config = SimConfig::symmetric(LinkConfig {
+        corrupt: 0.05,
+        delay: 20,
+        ..Default::default()
+    });
+
+    let plain = run(config.clone(), 500, 1000);
+    assert!(!plain.is_complete(), "{plain}");
+
+    let mut sim = Simulation::new(config);
+    sim.configure(|kcp| {
+        kcp.set_nodelay(1, 10, 2, true);
+        kcp.set_wndsize(128, 128);
+        kcp.set_checksum(true);
+    });
+    let report = sim.transfer(&payloads(500, 1000, 7), 120_000);
+    assert!(report.is_complete(), "{report}");
+    assert!(report.forward.corrupted > 0);
+
+    let mismatches =
+        sim.sender.checksum_stats().mismatches + sim.receiver.checksum_stats().mismatches;
+    assert!(mismatches > 0);
+    assert_eq!(report.input_errors, mismatches);
+}
+
+#[test]
+fn stream_mode_pieces_count_as_mismatched() {
+    let mut sim = Simulation::new(SimConfig::symmetric(LinkConfig::with_delay(10)));
+    sim.configure(|kcp| {
+        kcp.set_nodelay(1, 10, 2, true);
+        kcp.streaming_mode = true;
+    });
+    // each message arrives in several receive calls
+    let report = sim.transfer(&payloads(5, 4000, 3), 5000);
+    assert_eq!(report.delivered, 5);
+    assert!(report.mismatched >= 5, "{report}");
+    assert!(!report.is_complete());
+}
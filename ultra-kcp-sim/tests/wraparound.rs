@@ -0,0 +1,89 @@
+use ultra_kcp_core::bbr::BbrController;
+use ultra_kcp_core::delayed_ack::DelayedAck;
+use ultra_kcp_core::kcp::KcpControl;
+use ultra_kcp_core::pacing::PacingRate;
+use ultra_kcp_sim::link::LinkConfig;
+use ultra_kcp_sim::sim::{payloads, Report, SimConfig, Simulation};
+
+/// Lossy, jittery and reordering link, so that retransmissions, SACK ranges
+/// and out of order buffering all straddle the wraparound
+fn impaired(start_time: u32, initial_sn: u32) -> SimConfig {
+    SimConfig {
+        seed: 9,
+        start_time,
+        initial_sn,
+        ..SimConfig::symmetric(LinkConfig {
+            loss: 0.05,
+            delay: 20,
+            jitter: 10,
+            reorder: 0.02,
+            reorder_delay: 15,
+            bandwidth: Some(2_000_000),
+            ..Default::default()
+        })
+    }
+}
+
+fn classic(kcp: &mut KcpControl) {
+    kcp.set_nodelay(1, 10, 2, false);
+    kcp.set_wndsize(256, 256);
+}
+
+fn extended(kcp: &mut KcpControl) {
+    classic(kcp);
+    kcp.set_sack(true);
+    kcp.set_extended_frg(true);
+    kcp.set_delayed_ack(Some(DelayedAck::default()));
+    kcp.set_pacing(Some(PacingRate::Auto));
+}
+
+fn run(config: SimConfig, setup: fn(&mut KcpControl), bbr: bool) -> (Simulation, Report) {
+    let mut sim = Simulation::new(config);
+    sim.configure(setup);
+    if bbr {
+        sim.sender.set_congestion(Box::new(BbrController::new()));
+    }
+    let report = sim.transfer(&payloads(3000, 1200, 5), 600_000);
+    (sim, report)
+}
+
+/// A run shifted in time or sequence space must behave exactly like the
+/// same run starting from zero
+fn assert_same(shifted: &Report, baseline: &Report) {
+    assert!(shifted.is_complete(), "{shifted}");
+    assert_eq!(shifted.duration, baseline.duration);
+    assert_eq!(shifted.max_latency, baseline.max_latency);
+    assert_eq!(shifted.retransmissions, baseline.retransmissions);
+    assert_eq!(shifted.forward, baseline.forward);
+    assert_eq!(shifted.backward, baseline.backward);
+}
+
+#[test]
+fn timestamps_wrap() {
+    let start = u32::MAX - 3000;
+    let (_, baseline) = run(impaired(0, 0), classic, false);
+    let (sim, shifted) = run(impaired(start, 0), classic, false);
+
+    assert!(sim.clock().now() < start);
+    assert_same(&shifted, &baseline);
+}
+
+#[test]
+fn sequence_numbers_wrap() {
+    let initial_sn = u32::MAX - 500;
+    let (_, baseline) = run(impaired(0, 0), classic, false);
+    let (sim, shifted) = run(impaired(0, initial_sn), classic, false);
+
+    // one segment per message
+    assert_eq!(sim.receiver.rcv_nxt, initial_sn.wrapping_add(3000));
+    assert_same(&shifted, &baseline);
+}
+
+#[test]
+fn extensions_survive_both_wraps() {
+    let (_, baseline) = run(impaired(0, 0), extended, true);
+    let (sim, shifted) = run(impaired(u32::MAX - 2000, u32::MAX - 700), extended, true);
+
+    assert!(sim.sender.snd_una < u32::MAX - 700);
+    assert_same(&shifted, &baseline);
+}
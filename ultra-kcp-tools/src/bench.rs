@@ -0,0 +1,580 @@
+use std::any::Any;
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cpu_time::ThreadTime;
+use serde::Serialize;
+use socket2::SockRef;
+use ultra_kcp_core::constants::{Command, KcpLogFlags};
+use ultra_kcp_core::kcp::{KcpCallBack, KcpControl};
+use ultra_kcp_core::serial::itimediff;
+use ultra_kcp_core::trace::TraceEvent;
+use ultra_kcp_sim::link::{Link, LinkConfig, LinkStats};
+
+use crate::udp::KcpOptions;
+
+/// Bytes at the start of every message: sequence number and send time
+pub const HEADER_SIZE: usize = 16;
+
+/// Default time after which a run is abandoned (ms)
+pub const TIMEOUT_DEF: u64 = 60_000;
+
+/// Default socket send and receive buffer size (bytes), as kcptun
+pub const SOCKBUF_DEF: usize = 4 * 1024 * 1024;
+
+/// Largest UDP payload
+const DATAGRAM_MAX: usize = 65535;
+
+/// Impairments applied to the datagrams leaving each endpoint, like netem
+/// on the egress of an interface
+#[derive(clap::Args, Debug, Clone, PartialEq)]
+pub struct Impairment {
+    /// Random loss of each datagram (%)
+    #[arg(long, default_value_t = 0.0)]
+    pub loss: f64,
+    /// One-way delay (ms)
+    #[arg(long, default_value_t = 0)]
+    pub delay: u32,
+    /// Uniform random delay added to `--delay`, up to this value (ms)
+    #[arg(long, default_value_t = 0)]
+    pub jitter: u32,
+    /// Datagrams held back by `--reorder-delay` and overtaken by the next ones (%)
+    #[arg(long, default_value_t = 0.0)]
+    pub reorder: f64,
+    /// Extra delay of reordered datagrams (ms)
+    #[arg(long, default_value_t = 10)]
+    pub reorder_delay: u32,
+    /// Datagrams delivered twice (%)
+    #[arg(long, default_value_t = 0.0)]
+    pub duplicate: f64,
+    /// Bottleneck bandwidth of each direction (bytes/s), unlimited when absent
+    #[arg(long)]
+    pub bandwidth: Option<u64>,
+    /// Bytes waiting for the bottleneck before tail drop, unlimited when absent
+    #[arg(long)]
+    pub queue_limit: Option<usize>,
+    /// Seed of the random impairment decisions
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+}
+
+impl Default for Impairment {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            delay: 0,
+            jitter: 0,
+            reorder: 0.0,
+            reorder_delay: 10,
+            duplicate: 0.0,
+            bandwidth: None,
+            queue_limit: None,
+            seed: 1,
+        }
+    }
+}
+
+impl Impairment {
+    /// Simulated link configuration of one direction
+    pub fn link(&self) -> LinkConfig {
+        LinkConfig {
+            loss: self.loss / 100.0,
+            delay: self.delay,
+            jitter: self.jitter,
+            reorder: self.reorder / 100.0,
+            reorder_delay: self.reorder_delay,
+            duplicate: self.duplicate / 100.0,
+            bandwidth: self.bandwidth,
+            queue_limit: self.queue_limit,
+            ..Default::default()
+        }
+    }
+}
+
+/// One point of a sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchConfig {
+    /// Label of the KCP configuration in the results
+    pub profile: String,
+    pub kcp: KcpOptions,
+    /// Message size (bytes), at least `HEADER_SIZE`
+    pub size: usize,
+    /// Messages sent by the run
+    pub messages: usize,
+    pub impairment: Impairment,
+    /// Time after which the run is abandoned (ms)
+    pub timeout: u64,
+    /// Socket send and receive buffer size (bytes)
+    ///
+    /// A full window sent at once overflows the default receive buffer of
+    /// Linux, the kernel limit `net.core.rmem_max` applies.
+    pub sockbuf: usize,
+}
+
+/// Outcome of a run, latencies in ms from `send` to `receive`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BenchResult {
+    pub profile: String,
+    pub size: usize,
+    pub sndwnd: u32,
+    pub rcvwnd: u32,
+    pub messages: usize,
+    pub delivered: usize,
+    /// Messages received out of sequence, always 0 unless KCP is broken
+    pub mismatched: usize,
+    /// Time from the start to the last message received (ms)
+    pub duration_ms: f64,
+    /// Payload bytes received per second
+    pub goodput: f64,
+    pub latency_p50_ms: f64,
+    pub latency_p99_ms: f64,
+    pub latency_max_ms: f64,
+    /// Data segments sent by the sender, retransmissions included
+    pub data_sent: u64,
+    pub retransmits: u64,
+    pub retransmit_ratio: f64,
+    pub sender_cpu_ms: f64,
+    pub receiver_cpu_ms: f64,
+    /// Datagrams dropped by the impairment layer in both directions
+    pub dropped: u64,
+}
+
+/// Output format of the results
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned columns
+    Text,
+    /// Comma separated values with a header line
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+
+impl BenchResult {
+    /// Every message was received in sequence
+    pub fn is_complete(&self) -> bool {
+        self.delivered == self.messages && self.mismatched == 0
+    }
+
+    /// Write the line preceding the results, if the format has one
+    pub fn write_header(format: Format, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            Format::Text => writeln!(
+                out,
+                "{:<8} {:>7} {:>6} {:>11} {:>10} {:>8} {:>8} {:>8} {:>6} {:>8} {:>8}",
+                "profile",
+                "wnd",
+                "size",
+                "delivered",
+                "KiB/s",
+                "p50 ms",
+                "p99 ms",
+                "max ms",
+                "retx%",
+                "snd cpu",
+                "rcv cpu"
+            ),
+            Format::Csv => writeln!(
+                out,
+                "profile,size,sndwnd,rcvwnd,messages,delivered,mismatched,duration_ms,goodput,\
+                 latency_p50_ms,latency_p99_ms,latency_max_ms,data_sent,retransmits,\
+                 retransmit_ratio,sender_cpu_ms,receiver_cpu_ms,dropped"
+            ),
+            Format::Json => Ok(()),
+        }
+    }
+
+    /// Write the result as one line
+    pub fn write(&self, format: Format, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            Format::Text => writeln!(
+                out,
+                "{:<8} {:>7} {:>6} {:>11} {:>10.1} {:>8.1} {:>8.1} {:>8.1} {:>6.2} {:>8.1} {:>8.1}",
+                self.profile,
+                format!("{}/{}", self.sndwnd, self.rcvwnd),
+                self.size,
+                format!("{}/{}", self.delivered, self.messages),
+                self.goodput / 1024.0,
+                self.latency_p50_ms,
+                self.latency_p99_ms,
+                self.latency_max_ms,
+                self.retransmit_ratio * 100.0,
+                self.sender_cpu_ms,
+                self.receiver_cpu_ms
+            ),
+            Format::Csv => writeln!(
+                out,
+                "{},{},{},{},{},{},{},{:.3},{:.1},{:.3},{:.3},{:.3},{},{},{:.5},{:.3},{:.3},{}",
+                self.profile,
+                self.size,
+                self.sndwnd,
+                self.rcvwnd,
+                self.messages,
+                self.delivered,
+                self.mismatched,
+                self.duration_ms,
+                self.goodput,
+                self.latency_p50_ms,
+                self.latency_p99_ms,
+                self.latency_max_ms,
+                self.data_sent,
+                self.retransmits,
+                self.retransmit_ratio,
+                self.sender_cpu_ms,
+                self.receiver_cpu_ms,
+                self.dropped
+            ),
+            Format::Json => {
+                serde_json::to_writer(&mut *out, self)?;
+                writeln!(out)
+            }
+        }
+    }
+}
+
+/// Datagrams leaving an endpoint, through its impairment link
+struct Egress {
+    socket: UdpSocket,
+    link: Link,
+    start: Instant,
+    data_sent: u64,
+    retransmits: u64,
+}
+
+impl Egress {
+    /// Send the datagrams the link delivers by now
+    ///
+    /// # Note
+    /// Send errors are ignored, KCP retransmits what the network lost.
+    fn pump(&mut self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        while let Some(packet) = self.link.poll(now) {
+            let _ = self.socket.send(&packet);
+        }
+    }
+}
+
+/// Output callback handing datagrams to the egress link and counting
+/// data segments from the trace events
+struct BenchOutput(Arc<Mutex<Egress>>);
+
+impl KcpCallBack for BenchOutput {
+    fn output(&self, buf: &[u8], _kcp: &mut KcpControl, _user: Option<&Box<dyn Any>>) {
+        let mut egress = self.0.lock().unwrap();
+        let now = egress.start.elapsed().as_millis() as u64;
+        egress.link.send(now, buf.to_vec());
+        egress.pump();
+    }
+
+    fn trace(&self, event: &TraceEvent, _kcp: &KcpControl, _user: Option<&Box<dyn Any>>) {
+        if let TraceEvent::SegmentSent(segment) = event {
+            if segment.cmd == Command::Push as u32 {
+                let mut egress = self.0.lock().unwrap();
+                egress.data_sent += 1;
+                if segment.xmit > 1 {
+                    egress.retransmits += 1;
+                }
+            }
+        }
+    }
+}
+
+/// One side of a run, owning its control block
+struct Endpoint {
+    kcp: KcpControl,
+    socket: UdpSocket,
+    egress: Arc<Mutex<Egress>>,
+    start: Instant,
+    wait: Duration,
+    buf: Vec<u8>,
+}
+
+impl Endpoint {
+    fn new(
+        options: &KcpOptions,
+        socket: UdpSocket,
+        link: Link,
+        start: Instant,
+    ) -> io::Result<Self> {
+        let egress = Arc::new(Mutex::new(Egress {
+            socket: socket.try_clone()?,
+            link,
+            start,
+            data_sent: 0,
+            retransmits: 0,
+        }));
+        let mut kcp = options.create().map_err(invalid)?;
+        kcp.set_callback(Box::new(BenchOutput(egress.clone())));
+        kcp.set_tracing(true);
+        kcp.set_log_mask(KcpLogFlags::OUT_DATA);
+        Ok(Self {
+            kcp,
+            socket,
+            egress,
+            start,
+            wait: Duration::ZERO,
+            buf: vec![0; DATAGRAM_MAX],
+        })
+    }
+
+    fn now(&self) -> u32 {
+        self.start.elapsed().as_millis() as u32
+    }
+
+    /// Update KCP, then wait for one datagram until its next deadline
+    ///
+    /// Waits at most 1 ms while the egress link holds delayed datagrams.
+    fn poll(&mut self) -> io::Result<()> {
+        let now = self.now();
+        self.kcp.update(now);
+        let mut egress = self.egress.lock().unwrap();
+        egress.pump();
+        let mut wait = itimediff(self.kcp.check(now), now).max(1) as u64;
+        if egress.link.in_flight() > 0 {
+            wait = 1;
+        }
+        drop(egress);
+
+        let wait = Duration::from_millis(wait);
+        if wait != self.wait {
+            self.socket.set_read_timeout(Some(wait))?;
+            self.wait = wait;
+        }
+        match self.socket.recv(&mut self.buf) {
+            Ok(len) => {
+                // datagrams KCP rejects are counted by nobody, as on a real network
+                let _ = self.kcp.input(&self.buf[..len]);
+                Ok(())
+            }
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn stats(&self) -> LinkStats {
+        *self.egress.lock().unwrap().link.stats()
+    }
+}
+
+/// Loopback socket with `sockbuf` bytes of buffers
+fn bind(sockbuf: usize) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    let sock = SockRef::from(&socket);
+    sock.set_recv_buffer_size(sockbuf)?;
+    sock.set_send_buffer_size(sockbuf)?;
+    Ok(socket)
+}
+
+fn invalid(error: impl std::fmt::Debug) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{error:?}"))
+}
+
+struct SenderReport {
+    data_sent: u64,
+    retransmits: u64,
+    cpu: Duration,
+    stats: LinkStats,
+}
+
+struct ReceiverReport {
+    /// Send to receive times (us)
+    latencies: Vec<u64>,
+    mismatched: usize,
+    bytes: u64,
+    /// Time of the last message received (us)
+    finish: u64,
+    cpu: Duration,
+    stats: LinkStats,
+}
+
+fn send(
+    config: &BenchConfig,
+    socket: UdpSocket,
+    start: Instant,
+    done: &AtomicBool,
+) -> io::Result<SenderReport> {
+    let link = Link::new(config.impairment.link(), config.impairment.seed);
+    let mut endpoint = Endpoint::new(&config.kcp, socket, link, start)?;
+    let cpu = ThreadTime::try_now()?;
+    // keep the send queue short so that latency measures the link, not the queue
+    let queue_max = 2 * endpoint.kcp.send_window.max(1) as usize;
+    let mut message: Vec<u8> = (0..config.size).map(|i| i as u8).collect();
+    let mut next = 0;
+
+    while !done.load(Ordering::Relaxed) && start.elapsed().as_millis() < config.timeout as u128 {
+        while next < config.messages && endpoint.kcp.wait_snd() < queue_max {
+            message[..8].copy_from_slice(&(next as u64).to_le_bytes());
+            message[8..16].copy_from_slice(&(start.elapsed().as_micros() as u64).to_le_bytes());
+            endpoint.kcp.send(&message).map_err(invalid)?;
+            next += 1;
+        }
+        endpoint.poll()?;
+        if endpoint.kcp.state == u32::MAX {
+            break;
+        }
+    }
+
+    let egress = endpoint.egress.lock().unwrap();
+    Ok(SenderReport {
+        data_sent: egress.data_sent,
+        retransmits: egress.retransmits,
+        cpu: cpu.try_elapsed()?,
+        stats: *egress.link.stats(),
+    })
+}
+
+fn receive(
+    config: &BenchConfig,
+    socket: UdpSocket,
+    start: Instant,
+    done: &AtomicBool,
+) -> io::Result<ReceiverReport> {
+    let seed = config.impairment.seed.wrapping_add(1);
+    let link = Link::new(config.impairment.link(), seed);
+    let mut endpoint = Endpoint::new(&config.kcp, socket, link, start)?;
+    let cpu = ThreadTime::try_now()?;
+    let mut report = ReceiverReport {
+        latencies: Vec::with_capacity(config.messages),
+        mismatched: 0,
+        bytes: 0,
+        finish: 0,
+        cpu: Duration::ZERO,
+        stats: LinkStats::default(),
+    };
+    // stream mode does not keep message boundaries, messages are cut by size
+    let mut pending = Vec::new();
+    let mut data = Vec::new();
+
+    while report.latencies.len() < config.messages
+        && !done.load(Ordering::Relaxed)
+        && start.elapsed().as_millis() < config.timeout as u128
+    {
+        endpoint.poll()?;
+        while let Ok(size) = endpoint.kcp.peek_size() {
+            data.resize(size, 0);
+            let Ok(len) = endpoint.kcp.receive(Some(&mut data), false) else {
+                break;
+            };
+            pending.extend_from_slice(&data[..len]);
+        }
+
+        let now = start.elapsed().as_micros() as u64;
+        let mut offset = 0;
+        while pending.len() - offset >= config.size {
+            let message = &pending[offset..offset + config.size];
+            let sequence = u64::from_le_bytes(message[..8].try_into().unwrap());
+            let sent = u64::from_le_bytes(message[8..16].try_into().unwrap());
+            if sequence != report.latencies.len() as u64 {
+                report.mismatched += 1;
+            }
+            report.latencies.push(now.saturating_sub(sent));
+            report.bytes += config.size as u64;
+            report.finish = now;
+            offset += config.size;
+        }
+        pending.drain(..offset);
+    }
+
+    done.store(true, Ordering::Relaxed);
+    // acknowledge the last messages, the sender stops on `done` anyway
+    endpoint.kcp.flush();
+    report.cpu = cpu.try_elapsed()?;
+    report.stats = endpoint.stats();
+    Ok(report)
+}
+
+/// Value below which a share `q` of the sorted `values` falls, nearest rank
+fn percentile(sorted: &[u64], q: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Send `config.messages` messages between two endpoints over loopback UDP
+///
+/// The sender and the receiver run on their own thread, each with its
+/// impairment link on its outgoing datagrams, and stop when every message
+/// is received or at the timeout.
+///
+/// # Errors
+/// - `InvalidInput`: message smaller than `HEADER_SIZE`, invalid KCP options
+///   or message too large for the receive window
+/// - Socket errors
+pub fn run(config: &BenchConfig) -> io::Result<BenchResult> {
+    if config.size < HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("message size must be at least {HEADER_SIZE} bytes"),
+        ));
+    }
+    config.kcp.create().map_err(invalid)?;
+
+    let sender_socket = bind(config.sockbuf)?;
+    let receiver_socket = bind(config.sockbuf)?;
+    sender_socket.connect(receiver_socket.local_addr()?)?;
+    receiver_socket.connect(sender_socket.local_addr()?)?;
+    let done = AtomicBool::new(false);
+    let start = Instant::now();
+
+    let (sent, received) = thread::scope(|scope| {
+        let sender = scope.spawn(|| {
+            let report = send(config, sender_socket, start, &done);
+            done.store(true, Ordering::Relaxed);
+            report
+        });
+        let received = receive(config, receiver_socket, start, &done);
+        done.store(true, Ordering::Relaxed);
+        (sender.join().unwrap(), received)
+    });
+    let (sent, mut received) = (sent?, received?);
+
+    received.latencies.sort_unstable();
+    let ms = |us: u64| us as f64 / 1000.0;
+    let duration = ms(received.finish);
+    Ok(BenchResult {
+        profile: config.profile.clone(),
+        size: config.size,
+        sndwnd: config.kcp.sndwnd,
+        rcvwnd: config.kcp.rcvwnd,
+        messages: config.messages,
+        delivered: received.latencies.len(),
+        mismatched: received.mismatched,
+        duration_ms: duration,
+        goodput: if duration > 0.0 {
+            received.bytes as f64 * 1000.0 / duration
+        } else {
+            0.0
+        },
+        latency_p50_ms: ms(percentile(&received.latencies, 0.5)),
+        latency_p99_ms: ms(percentile(&received.latencies, 0.99)),
+        latency_max_ms: ms(received.latencies.last().copied().unwrap_or(0)),
+        data_sent: sent.data_sent,
+        retransmits: sent.retransmits,
+        retransmit_ratio: if sent.data_sent > 0 {
+            sent.retransmits as f64 / sent.data_sent as f64
+        } else {
+            0.0
+        },
+        sender_cpu_ms: sent.cpu.as_secs_f64() * 1000.0,
+        receiver_cpu_ms: received.cpu.as_secs_f64() * 1000.0,
+        dropped: [sent.stats, received.stats]
+            .iter()
+            .map(|stats| stats.lost + stats.queue_drops)
+            .sum(),
+    })
+}
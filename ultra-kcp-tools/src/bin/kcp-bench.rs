@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+use clap::{Parser, ValueEnum};
+use ultra_kcp_tools::bench::{
+    self, BenchConfig, BenchResult, Format, Impairment, SOCKBUF_DEF, TIMEOUT_DEF,
+};
+use ultra_kcp_tools::udp::{KcpOptions, Preset};
+
+/// Measure KCP goodput, latency, retransmissions and CPU time over loopback UDP
+///
+/// Every combination of profile, window and message size is run in turn,
+/// through the impairments given, and reported as soon as it completes.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Message sizes to sweep (bytes, at least 16)
+    #[arg(long, value_delimiter = ',', default_value = "64,1024,8192")]
+    sizes: Vec<usize>,
+    /// Presets to sweep, the tuning options are used as they are when absent
+    #[arg(long, value_delimiter = ',', value_enum)]
+    profiles: Vec<Preset>,
+    /// Windows to sweep, as both send and receive window (segments),
+    /// `--sndwnd` and `--rcvwnd` are used when absent
+    #[arg(long, value_delimiter = ',')]
+    windows: Vec<u32>,
+    /// Messages sent by every run
+    #[arg(long, default_value_t = 1000)]
+    messages: usize,
+    /// Abandon a run after this long (ms)
+    #[arg(long, default_value_t = TIMEOUT_DEF)]
+    timeout: u64,
+    /// Socket send and receive buffer size (bytes)
+    #[arg(long, default_value_t = SOCKBUF_DEF)]
+    sockbuf: usize,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    #[command(flatten)]
+    kcp: KcpOptions,
+    #[command(flatten)]
+    impairment: Impairment,
+}
+
+/// Every point of the sweep, profiles outermost
+fn sweep(args: &Args) -> Vec<BenchConfig> {
+    let profiles: Vec<Option<Preset>> = match args.profiles.is_empty() {
+        true => vec![args.kcp.preset],
+        false => args.profiles.iter().copied().map(Some).collect(),
+    };
+    let windows: Vec<Option<u32>> = match args.windows.is_empty() {
+        true => vec![None],
+        false => args.windows.iter().copied().map(Some).collect(),
+    };
+    let mut configs = Vec::new();
+    for preset in &profiles {
+        for window in &windows {
+            for &size in &args.sizes {
+                let mut kcp = args.kcp.clone();
+                kcp.preset = *preset;
+                if let Some(window) = *window {
+                    kcp.sndwnd = window;
+                    kcp.rcvwnd = window;
+                }
+                let profile = preset.and_then(|preset| preset.to_possible_value());
+                configs.push(BenchConfig {
+                    profile: profile.map_or("custom".into(), |value| value.get_name().into()),
+                    kcp,
+                    size,
+                    messages: args.messages,
+                    impairment: args.impairment.clone(),
+                    timeout: args.timeout,
+                    sockbuf: args.sockbuf,
+                });
+            }
+        }
+    }
+    configs
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    BenchResult::write_header(args.format, &mut stdout)?;
+    for config in sweep(args) {
+        let result = bench::run(&config).map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("{} size {}: {error}", config.profile, config.size),
+            )
+        })?;
+        result.write(args.format, &mut stdout)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(&Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("kcp-bench: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
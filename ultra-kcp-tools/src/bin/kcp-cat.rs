@@ -0,0 +1,147 @@
+use std::io::{self, BufRead, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+use ultra_kcp_core::kcp::KcpControl;
+use ultra_kcp_tools::udp::{KcpOptions, UdpSession};
+
+/// Chunks read from standard input in streaming mode
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Pipe standard input and output through a KCP session over UDP
+///
+/// Without `--stream`, every input line is sent as one KCP message, or as
+/// several when it does not fit in the receive window.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Address to connect to, or to listen on with `--listen`
+    address: String,
+    /// Wait for a peer instead of connecting
+    #[arg(long, short)]
+    listen: bool,
+    /// Exit once standard input is closed and everything sent is acknowledged
+    #[arg(long, short)]
+    quit: bool,
+    /// Exit after this many ms without a datagram from the peer, 0 waits forever
+    #[arg(long, default_value_t = 0)]
+    idle_timeout: u64,
+    #[command(flatten)]
+    kcp: KcpOptions,
+}
+
+/// Read standard input on a thread, as lines or as chunks
+///
+/// The channel is closed at the end of the input.
+fn read_stdin(stream: bool) -> Receiver<io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::sync_channel(16);
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        loop {
+            let mut data = vec![0; CHUNK_SIZE];
+            let read = match stream {
+                true => stdin.read(&mut data),
+                false => {
+                    data.clear();
+                    stdin.read_until(b'\n', &mut data)
+                }
+            };
+            let message = match read {
+                Ok(0) => break,
+                Ok(len) => {
+                    data.truncate(len);
+                    Ok(data)
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => Err(error),
+            };
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Largest message `KcpControl::send` accepts at once
+fn max_message(kcp: &KcpControl) -> usize {
+    if kcp.extended_frg() {
+        return kcp.max_message();
+    }
+    // fewer fragments than the receive window, and at most 256 of them
+    let fragments = (kcp.recv_window as usize).clamp(2, u8::MAX as usize + 2) - 1;
+    fragments * kcp.mss as usize
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let address: SocketAddr = args
+        .address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+    let mut session = match args.listen {
+        true => UdpSession::listen(address, &args.kcp)?,
+        false => UdpSession::connect(address, &args.kcp)?,
+    };
+    let input = read_stdin(args.kcp.stream);
+    let mut stdin_open = true;
+    let mut stdout = io::stdout().lock();
+    // keep the send queue short so that standard input is read at the pace of the link
+    let queue_max = 2 * args.kcp.sndwnd.max(1) as usize;
+
+    loop {
+        session.poll(Duration::from_millis(10))?;
+
+        // a listener does not know where to send before its peer spoke
+        while stdin_open && session.peer().is_some() && session.kcp().wait_snd() < queue_max {
+            match input.try_recv() {
+                Ok(data) => {
+                    // the pieces of a long line are written back to back on the other end
+                    let max = max_message(session.kcp());
+                    for piece in data?.chunks(max) {
+                        session.send(piece).map_err(|error| {
+                            io::Error::new(io::ErrorKind::InvalidInput, format!("send: {error:?}"))
+                        })?;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => stdin_open = false,
+            }
+        }
+
+        let mut wrote = false;
+        while let Some(data) = session.receive() {
+            stdout.write_all(data)?;
+            wrote = true;
+        }
+        if wrote {
+            stdout.flush()?;
+        }
+
+        if session.is_dead() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "link is dead"));
+        }
+        if args.quit && !stdin_open && session.kcp().wait_snd() == 0 {
+            return Ok(());
+        }
+        if args.idle_timeout > 0 && session.idle() > Duration::from_millis(args.idle_timeout) {
+            return Ok(());
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("kcp-cat: {}: {error}", args.address);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use ultra_kcp_tools::dump::{read_packets, Dump};
+
+/// Decode KCP datagrams from a pcap file or hex dumps
+///
+/// Hex dumps hold one datagram per line. Every segment is printed, followed
+/// by per conversation summaries.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// pcap file or hex dumps, `-` reads standard input
+    input: PathBuf,
+    /// Datagrams end with a CRC32C trailer
+    #[arg(long)]
+    checksum: bool,
+    /// Only decode this conversation
+    #[arg(long)]
+    conv: Option<u32>,
+    /// Only decode UDP datagrams from or to this port
+    #[arg(long)]
+    port: Option<u16>,
+    /// Print the summaries only
+    #[arg(long, short)]
+    quiet: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let data = if args.input.as_os_str() == "-" {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data).map(|_| data)
+    } else {
+        fs::read(&args.input)
+    };
+    let packets = match data.and_then(|data| read_packets(&data)) {
+        Ok(packets) => packets,
+        Err(error) => {
+            eprintln!("kcp-dump: {}: {error}", args.input.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut dump = Dump::new(args.checksum, args.conv);
+    for packet in &packets {
+        if let (Some(port), Some((src, dst))) = (args.port, packet.route) {
+            if src.port() != port && dst.port() != port {
+                continue;
+            }
+        }
+        let Some(segments) = dump.add(packet) else {
+            continue;
+        };
+        if !args.quiet {
+            let label = dump.label(packet);
+            for seg in segments {
+                println!("{label} {seg}");
+            }
+        }
+    }
+    print!("{dump}");
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,38 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use ultra_kcp_tools::trace::{TraceSummary, BUCKET_DEF};
+
+/// Summarize a JSON-lines KCP trace into RTT, cwnd and retransmission timelines
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Trace written by `TraceOutput`, `-` reads standard input
+    trace: PathBuf,
+    /// Width of a timeline row (ms)
+    #[arg(long, default_value_t = BUCKET_DEF)]
+    bucket: u32,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let summary = if args.trace.as_os_str() == "-" {
+        TraceSummary::analyze(io::stdin().lock(), args.bucket)
+    } else {
+        File::open(&args.trace)
+            .and_then(|file| TraceSummary::analyze(BufReader::new(file), args.bucket))
+    };
+    match summary {
+        Ok(summary) => {
+            print!("{summary}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("kcp-trace: {}: {error}", args.trace.display());
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use ultra_kcp_tools::tunnel::{Client, Server, TunnelOptions};
+
+/// Forward TCP connections over KCP on UDP
+///
+/// Every TCP connection accepted by the client is carried by its own KCP
+/// session to the server, which opens a connection to the target.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    #[command(subcommand)]
+    side: Side,
+}
+
+#[derive(Subcommand)]
+enum Side {
+    /// Accept TCP connections and tunnel them to a server
+    Client {
+        /// TCP address to accept connections on
+        #[arg(long)]
+        listen: String,
+        /// UDP address of the server
+        #[arg(long)]
+        remote: String,
+        #[command(flatten)]
+        options: TunnelOptions,
+    },
+    /// Accept tunnels and connect them to a TCP target
+    Server {
+        /// UDP address to accept tunnels on
+        #[arg(long)]
+        listen: String,
+        /// TCP address every tunnel is connected to
+        #[arg(long)]
+        target: String,
+        #[command(flatten)]
+        options: TunnelOptions,
+    },
+}
+
+fn resolve(address: &str) -> io::Result<SocketAddr> {
+    address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{address}: no address"),
+        )
+    })
+}
+
+fn run(side: Side) -> io::Result<()> {
+    match side {
+        Side::Client {
+            listen,
+            remote,
+            options,
+        } => Client::bind(resolve(&listen)?, resolve(&remote)?, options)?.run(),
+        Side::Server {
+            listen,
+            target,
+            options,
+        } => Server::bind(resolve(&listen)?, resolve(&target)?, options)?.run(),
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse().side) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("kcp-tunnel: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
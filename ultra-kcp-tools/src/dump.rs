@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use ultra_kcp_core::checksum::crc32c;
+use ultra_kcp_core::constants::{
+    Command, KcpError, IKCP_CHECKSUM_SIZE, IKCP_OVERHEAD, IKCP_SACK_RANGE_SIZE,
+};
+use ultra_kcp_core::kcp::Segment;
+use ultra_kcp_core::serial::itimediff;
+
+use crate::pcap;
+
+/// Source and destination of a captured datagram, unknown for hex dumps
+pub type Route = Option<(SocketAddr, SocketAddr)>;
+
+/// Datagram to decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// Position in the input, from 1
+    pub number: usize,
+    /// Capture time (us), unknown for hex dumps
+    pub time: Option<u64>,
+    pub route: Route,
+    pub payload: Vec<u8>,
+}
+
+/// Read a pcap file, or hex dumps when `data` is not a capture
+///
+/// # Errors
+/// Errors of `pcap::read_udp` and `parse_hex`
+pub fn read_packets(data: &[u8]) -> io::Result<Vec<Packet>> {
+    if !pcap::is_capture(data) {
+        let text = std::str::from_utf8(data)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "neither pcap nor hex"))?;
+        return parse_hex(text);
+    }
+    Ok(pcap::read_udp(data)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, datagram)| Packet {
+            number: index + 1,
+            time: Some(datagram.time),
+            route: Some((datagram.src, datagram.dst)),
+            payload: datagram.payload,
+        })
+        .collect())
+}
+
+/// Parse hex dumps, one datagram per line
+///
+/// Whitespace and `:` between digits are ignored, blank lines and lines
+/// starting with `#` are skipped.
+///
+/// # Errors
+/// `InvalidData` naming the first line that is not an even number of hex digits
+pub fn parse_hex(text: &str) -> io::Result<Vec<Packet>> {
+    let mut packets = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let digits: Vec<u8> = line
+            .bytes()
+            .filter(|byte| !byte.is_ascii_whitespace() && *byte != b':')
+            .collect();
+        let payload = digits
+            .chunks(2)
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .filter(|pair| pair.len() == 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: invalid hex dump", index + 1),
+                )
+            })?;
+        packets.push(Packet {
+            number: packets.len() + 1,
+            time: None,
+            route: None,
+            payload,
+        });
+    }
+    Ok(packets)
+}
+
+/// Header of a decoded segment, with the ranges of a `Sack`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodedSegment {
+    pub conv: u32,
+    pub cmd: u32,
+    pub frg: u32,
+    pub wnd: u32,
+    pub ts: u32,
+    pub sn: u32,
+    pub una: u32,
+    pub len: u32,
+    /// Acknowledged `[start, end)` sn ranges
+    pub ranges: Vec<(u32, u32)>,
+}
+
+impl DecodedSegment {
+    fn new(seg: &Segment) -> Self {
+        Self {
+            conv: seg.conv,
+            cmd: seg.cmd,
+            frg: seg.frg,
+            wnd: seg.wnd,
+            ts: seg.ts,
+            sn: seg.sn,
+            una: seg.una,
+            len: seg.len,
+            ranges: Vec::new(),
+        }
+    }
+
+    pub fn command(&self) -> Option<Command> {
+        Command::try_from(self.cmd).ok()
+    }
+}
+
+impl fmt::Display for DecodedSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cmd = self.command().map_or("unknown", |cmd| cmd.name());
+        write!(
+            f,
+            "conv={} cmd={cmd} frg={} wnd={} ts={} sn={} una={} len={}",
+            self.conv, self.frg, self.wnd, self.ts, self.sn, self.una, self.len
+        )?;
+        for (index, (start, end)) in self.ranges.iter().enumerate() {
+            let separator = if index == 0 { " ranges=" } else { "," };
+            write!(f, "{separator}[{start},{end})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode every segment of a datagram with `Segment::decode_header`
+///
+/// # Arguments
+/// * `datagram` - UDP payload
+/// * `checksum` - Datagram ends with a CRC32C trailer, see `KcpControl::set_checksum`
+///
+/// # Errors
+/// - `ChecksumMismatch`: Trailer is missing or wrong
+/// - `InvalidPacket`: Datagram is empty, truncated, carries an unknown
+///   command or trailing bytes
+pub fn decode_datagram(datagram: &[u8], checksum: bool) -> Result<Vec<DecodedSegment>, KcpError> {
+    let mut data = datagram;
+    if checksum {
+        let len = data
+            .len()
+            .checked_sub(IKCP_CHECKSUM_SIZE as usize)
+            .ok_or(KcpError::ChecksumMismatch)?;
+        let (body, trailer) = data.split_at(len);
+        if crc32c(body).to_le_bytes() != *trailer {
+            return Err(KcpError::ChecksumMismatch);
+        }
+        data = body;
+    }
+    if data.is_empty() {
+        return Err(KcpError::InvalidPacket);
+    }
+
+    let mut segments = Vec::new();
+    while !data.is_empty() {
+        let mut seg = DecodedSegment::new(&Segment::decode_header(data)?);
+        data = &data[IKCP_OVERHEAD as usize..];
+        let body = data
+            .get(..seg.len as usize)
+            .ok_or(KcpError::InvalidPacket)?;
+        if seg.command().ok_or(KcpError::InvalidPacket)? == Command::Sack {
+            if !body.len().is_multiple_of(IKCP_SACK_RANGE_SIZE) {
+                return Err(KcpError::InvalidPacket);
+            }
+            seg.ranges = body
+                .chunks_exact(IKCP_SACK_RANGE_SIZE)
+                .map(|range| {
+                    let start = u32::from_le_bytes(range[0..4].try_into().unwrap());
+                    let end = u32::from_le_bytes(range[4..8].try_into().unwrap());
+                    (start, end)
+                })
+                .collect();
+        }
+        data = &data[body.len()..];
+        segments.push(seg);
+    }
+    Ok(segments)
+}
+
+/// Round trip samples (us)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RttStats {
+    pub samples: u64,
+    pub min: u64,
+    pub max: u64,
+    sum: u64,
+}
+
+impl RttStats {
+    pub fn add(&mut self, sample: u64) {
+        if self.samples == 0 {
+            self.min = sample;
+            self.max = sample;
+        }
+        self.samples += 1;
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.sum += sample;
+    }
+
+    pub fn avg(&self) -> Option<u64> {
+        (self.samples > 0).then(|| self.sum / self.samples)
+    }
+}
+
+/// Segments sent in one direction of a conversation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Flow {
+    pub segments: u64,
+    /// Segments per command, in `Command` order from `Push`
+    pub commands: [u64; 5],
+    /// Payload bytes of `Push` segments
+    pub data_bytes: u64,
+    /// `Push` segments whose sn was seen before
+    pub retransmits: u64,
+    /// Jumps over unseen sn, and the number of sn skipped
+    pub gaps: u64,
+    pub missing: u64,
+    /// Time between a `Push` and the `Ack` or `Sack` echoing its sn and ts
+    pub rtt: RttStats,
+    highest: Option<u32>,
+    seen: HashSet<u32>,
+    /// Capture time of the `Push` segments not acknowledged yet, by sn and ts
+    pending: HashMap<(u32, u32), u64>,
+    una: Option<u32>,
+}
+
+impl Flow {
+    fn push(&mut self, seg: &DecodedSegment, time: Option<u64>) {
+        self.data_bytes += seg.len as u64;
+        if !self.seen.insert(seg.sn) {
+            self.retransmits += 1;
+        }
+        match self.highest {
+            Some(highest) if itimediff(seg.sn, highest) <= 0 => {}
+            Some(highest) => {
+                let skipped = itimediff(seg.sn, highest) as u64 - 1;
+                if skipped > 0 {
+                    self.gaps += 1;
+                    self.missing += skipped;
+                }
+                self.highest = Some(seg.sn);
+            }
+            None => self.highest = Some(seg.sn),
+        }
+        if let Some(time) = time {
+            self.pending.entry((seg.sn, seg.ts)).or_insert(time);
+        }
+    }
+
+    /// Account for an acknowledgement sent by the peer
+    fn acked(&mut self, seg: &DecodedSegment, time: Option<u64>) {
+        if matches!(seg.command(), Some(Command::Ack | Command::Sack)) {
+            if let (Some(time), Some(sent)) = (time, self.pending.remove(&(seg.sn, seg.ts))) {
+                self.rtt.add(time.saturating_sub(sent));
+            }
+        }
+        if self.una != Some(seg.una) {
+            self.una = Some(seg.una);
+            self.pending
+                .retain(|(sn, _), _| itimediff(*sn, seg.una) >= 0);
+        }
+    }
+}
+
+/// Flows of a conversation, by route
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Conversation {
+    pub flows: BTreeMap<Route, Flow>,
+}
+
+/// Decoder of a datagram sequence, collecting per conversation summaries
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dump {
+    /// Datagrams carry a checksum trailer
+    pub checksum: bool,
+    /// Only keep this conversation
+    pub conv: Option<u32>,
+    pub conversations: BTreeMap<u32, Conversation>,
+    pub datagrams: u64,
+    pub segments: u64,
+    /// Datagrams that are not KCP, or fail their checksum
+    pub skipped: u64,
+    /// Time of the first datagram, printed times are relative to it
+    first: Option<u64>,
+}
+
+impl Dump {
+    pub fn new(checksum: bool, conv: Option<u32>) -> Self {
+        Self {
+            checksum,
+            conv,
+            ..Default::default()
+        }
+    }
+
+    /// Decode a datagram and account for its segments
+    ///
+    /// # Returns
+    /// The segments, `None` when the datagram was skipped
+    pub fn add(&mut self, packet: &Packet) -> Option<Vec<DecodedSegment>> {
+        let segments = match decode_datagram(&packet.payload, self.checksum) {
+            Ok(segments) => segments,
+            Err(_) => {
+                self.skipped += 1;
+                return None;
+            }
+        };
+        if self.conv.is_some_and(|conv| segments[0].conv != conv) {
+            return None;
+        }
+        if let Some(time) = packet.time {
+            self.first.get_or_insert(time);
+        }
+        self.datagrams += 1;
+        let reverse = packet.route.map(|(src, dst)| (dst, src));
+        for seg in &segments {
+            self.segments += 1;
+            let conversation = self.conversations.entry(seg.conv).or_default();
+            let flow = conversation.flows.entry(packet.route).or_default();
+            flow.segments += 1;
+            if let Some(cmd) = seg.command() {
+                flow.commands[(u32::from(cmd) - u32::from(Command::Push)) as usize] += 1;
+                if cmd == Command::Push {
+                    flow.push(seg, packet.time);
+                }
+            }
+            if let Some(peer) = conversation.flows.get_mut(&reverse) {
+                peer.acked(seg, packet.time);
+            }
+        }
+        Some(segments)
+    }
+
+    /// Line prefix of a datagram: relative time and route, or its number
+    pub fn label(&self, packet: &Packet) -> String {
+        match (packet.time, packet.route, self.first) {
+            (Some(time), Some((src, dst)), Some(first)) => {
+                let offset = time.saturating_sub(first);
+                format!(
+                    "{}.{:06} {src} > {dst}",
+                    offset / 1_000_000,
+                    offset % 1_000_000
+                )
+            }
+            _ => format!("#{}", packet.number),
+        }
+    }
+}
+
+/// Milliseconds with one decimal, `-` when unknown
+struct Millis(Option<u64>);
+
+impl fmt::Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(us) => write!(f, "{}.{}", us / 1000, us % 1000 / 100),
+            None => "-".fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} datagrams, {} segments, {} skipped",
+            self.datagrams, self.segments, self.skipped
+        )?;
+        for (conv, conversation) in &self.conversations {
+            writeln!(f, "conv {conv}")?;
+            for (route, flow) in &conversation.flows {
+                match route {
+                    Some((src, dst)) => write!(f, "  {src} > {dst}:")?,
+                    None => write!(f, "  all:")?,
+                }
+                let [push, ack, wask, wins, sack] = flow.commands;
+                writeln!(
+                    f,
+                    " {} segments (push {push}, ack {ack}, wask {wask}, wins {wins}, sack {sack}), {} data bytes",
+                    flow.segments, flow.data_bytes
+                )?;
+                if push > 0 {
+                    writeln!(
+                        f,
+                        "    retransmits {}, gaps {} ({} sn missing), rtt min {} avg {} max {} ms over {} samples",
+                        flow.retransmits,
+                        flow.gaps,
+                        flow.missing,
+                        Millis((flow.rtt.samples > 0).then_some(flow.rtt.min)),
+                        Millis(flow.rtt.avg()),
+                        Millis((flow.rtt.samples > 0).then_some(flow.rtt.max)),
+                        flow.rtt.samples
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,6 @@
+pub mod bench;
+pub mod dump;
+pub mod pcap;
+pub mod trace;
+pub mod tunnel;
+pub mod udp;
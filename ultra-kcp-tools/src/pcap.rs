@@ -0,0 +1,176 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// pcap magic numbers with microsecond and nanosecond timestamps
+const MAGIC_USEC: u32 = 0xa1b2_c3d4;
+const MAGIC_NSEC: u32 = 0xa1b2_3c4d;
+/// Block type of a pcapng section header
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_UDP: u8 = 17;
+
+/// UDP datagram extracted from a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    /// Capture time (us since the epoch)
+    pub time: u64,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+/// Whether `data` starts like a pcap or pcapng file
+pub fn is_capture(data: &[u8]) -> bool {
+    data.get(..4)
+        .map(|magic| {
+            let magic = u32::from_le_bytes(magic.try_into().unwrap());
+            [MAGIC_USEC, MAGIC_NSEC, PCAPNG_MAGIC]
+                .iter()
+                .any(|&known| magic == known || magic == known.swap_bytes())
+        })
+        .unwrap_or(false)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Extract the UDP datagrams of a pcap file
+///
+/// Ethernet (with VLAN tags), Linux cooked, BSD loopback and raw IP link
+/// types are understood. Other packets, IP fragments after the first one and
+/// truncated packets are skipped.
+///
+/// # Errors
+/// `InvalidData` when `data` is not a pcap file, pcapng included, or is
+/// truncated within a record header.
+pub fn read_udp(data: &[u8]) -> io::Result<Vec<Datagram>> {
+    let magic = match data.get(..4) {
+        Some(magic) if data.len() >= 24 => u32::from_le_bytes(magic.try_into().unwrap()),
+        Some(magic) if magic == PCAPNG_MAGIC.to_le_bytes() => PCAPNG_MAGIC,
+        _ => return Err(invalid("not a pcap file")),
+    };
+    let (big_endian, nanos) = match magic {
+        MAGIC_USEC => (false, false),
+        MAGIC_NSEC => (false, true),
+        _ if magic == MAGIC_USEC.swap_bytes() => (true, false),
+        _ if magic == MAGIC_NSEC.swap_bytes() => (true, true),
+        PCAPNG_MAGIC => {
+            return Err(invalid(
+                "pcapng is not supported, convert with `editcap -F pcap`",
+            ))
+        }
+        _ => return Err(invalid("not a pcap file")),
+    };
+    let read_u32 = |at: usize| {
+        let bytes = data[at..at + 4].try_into().unwrap();
+        match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    };
+    let linktype = read_u32(20) & 0x0fff_ffff;
+
+    let mut datagrams = Vec::new();
+    let mut at = 24;
+    while at < data.len() {
+        if data.len() - at < 16 {
+            return Err(invalid("truncated pcap record header"));
+        }
+        let seconds = read_u32(at) as u64;
+        let fraction = read_u32(at + 4) as u64;
+        let caplen = read_u32(at + 8) as usize;
+        at += 16;
+        let packet = &data[at..data.len().min(at + caplen)];
+        at += caplen;
+
+        let time = seconds * 1_000_000 + if nanos { fraction / 1000 } else { fraction };
+        if let Some(datagram) = link_payload(linktype, packet).and_then(|ip| parse_ip(time, ip)) {
+            datagrams.push(datagram);
+        }
+    }
+    Ok(datagrams)
+}
+
+/// IP packet carried by a link layer frame
+fn link_payload(linktype: u32, frame: &[u8]) -> Option<&[u8]> {
+    match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(frame),
+        // address family in host byte order, the IP version tells the rest
+        LINKTYPE_NULL | LINKTYPE_LOOP => frame.get(4..),
+        LINKTYPE_ETHERNET => {
+            let mut at = 12;
+            let mut ethertype = u16::from_be_bytes(frame.get(at..at + 2)?.try_into().ok()?);
+            while ethertype == ETHERTYPE_VLAN {
+                at += 4;
+                ethertype = u16::from_be_bytes(frame.get(at..at + 2)?.try_into().ok()?);
+            }
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => frame.get(at + 2..),
+                _ => None,
+            }
+        }
+        LINKTYPE_LINUX_SLL => match u16::from_be_bytes(frame.get(14..16)?.try_into().ok()?) {
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => frame.get(16..),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// UDP datagram of an IPv4 or IPv6 packet
+fn parse_ip(time: u64, packet: &[u8]) -> Option<Datagram> {
+    let (src, dst, udp) = match packet.first()? >> 4 {
+        4 => {
+            let header = (packet[0] & 0x0f) as usize * 4;
+            let total = u16::from_be_bytes(packet.get(2..4)?.try_into().ok()?) as usize;
+            let fragment = u16::from_be_bytes(packet.get(6..8)?.try_into().ok()?);
+            // later fragments carry no UDP header
+            if packet.get(9)? != &IPPROTO_UDP || fragment & 0x1fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            let udp = packet.get(header..total.min(packet.len()))?;
+            (
+                IpAddr::from(Ipv4Addr::from(src)),
+                IpAddr::from(Ipv4Addr::from(dst)),
+                udp,
+            )
+        }
+        6 => {
+            if packet.get(6)? != &IPPROTO_UDP {
+                return None;
+            }
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv6Addr::from(src)),
+                IpAddr::from(Ipv6Addr::from(dst)),
+                packet.get(40..)?,
+            )
+        }
+        _ => return None,
+    };
+    let src_port = u16::from_be_bytes(udp.get(0..2)?.try_into().ok()?);
+    let dst_port = u16::from_be_bytes(udp.get(2..4)?.try_into().ok()?);
+    let len = u16::from_be_bytes(udp.get(4..6)?.try_into().ok()?) as usize;
+    let payload = udp.get(8..len)?;
+    Some(Datagram {
+        time,
+        src: SocketAddr::new(src, src_port),
+        dst: SocketAddr::new(dst, dst_port),
+        payload: payload.to_vec(),
+    })
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::io::{self, BufRead};
+
+use serde::Deserialize;
+use ultra_kcp_core::serial::itimediff;
+
+/// Default width of a timeline bucket (ms)
+pub const BUCKET_DEF: u32 = 1000;
+
+/// Header fields of a traced segment
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Segment {
+    pub cmd: String,
+    pub sn: u32,
+    pub una: u32,
+    pub frg: u32,
+    pub wnd: u32,
+    pub len: u32,
+    pub xmit: u32,
+}
+
+/// Event of a trace written by `ultra_kcp_core::trace::TraceOutput`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "name", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    SegmentSent(Segment),
+    SegmentReceived(Segment),
+    RttUpdated {
+        sample: i32,
+        srtt: i32,
+        rttval: i32,
+        rto: u32,
+    },
+    CwndChanged {
+        from: u32,
+        to: u32,
+        reason: String,
+    },
+    StateChanged {
+        from: u32,
+        to: u32,
+    },
+}
+
+/// One line of a trace
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Record {
+    pub time: u32,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Record {
+    /// Parse a trace line
+    ///
+    /// # Errors
+    /// `InvalidData` when the line is not a trace event
+    pub fn parse(line: &str) -> io::Result<Self> {
+        serde_json::from_str(line)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Timeline entry covering `[start, start + bucket)` ms from the first event
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bucket {
+    pub start: u32,
+    /// RTT samples taken in the bucket
+    pub rtt_samples: u32,
+    pub rtt_min: i32,
+    pub rtt_max: i32,
+    rtt_sum: i64,
+    /// Smoothed RTT and RTO at the end of the bucket
+    pub srtt: Option<i32>,
+    pub rto: Option<u32>,
+    /// Congestion window at the end of the bucket (segments)
+    pub cwnd: Option<u32>,
+    /// Data segments sent, retransmissions included
+    pub sent: u32,
+    pub retransmits: u32,
+    /// Data segments received
+    pub received: u32,
+}
+
+impl Bucket {
+    pub fn rtt_avg(&self) -> Option<i32> {
+        (self.rtt_samples > 0).then(|| (self.rtt_sum / self.rtt_samples as i64) as i32)
+    }
+}
+
+/// RTT, congestion window and retransmission timelines of a trace
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceSummary {
+    /// Width of a bucket (ms)
+    pub bucket: u32,
+    pub buckets: Vec<Bucket>,
+    /// Time of the first event
+    pub first: Option<u32>,
+    pub events: u64,
+    pub segments_sent: u64,
+    pub segments_received: u64,
+    pub data_sent: u64,
+    pub retransmits: u64,
+    pub rtt_samples: u64,
+    pub rtt_min: Option<i32>,
+    pub rtt_max: Option<i32>,
+    rtt_sum: i64,
+    pub cwnd_min: Option<u32>,
+    pub cwnd_max: Option<u32>,
+    /// State transitions, time offset (ms) and new state
+    pub states: Vec<(u32, u32)>,
+}
+
+impl TraceSummary {
+    pub fn new(bucket: u32) -> Self {
+        Self {
+            bucket: bucket.max(1),
+            ..Default::default()
+        }
+    }
+
+    /// Summarize a whole trace, blank lines are skipped
+    ///
+    /// # Errors
+    /// Read errors, and `InvalidData` naming the first malformed line
+    pub fn analyze(reader: impl BufRead, bucket: u32) -> io::Result<Self> {
+        let mut summary = Self::new(bucket);
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = Record::parse(&line).map_err(|error| {
+                io::Error::new(error.kind(), format!("line {}: {error}", index + 1))
+            })?;
+            summary.add(&record);
+        }
+        Ok(summary)
+    }
+
+    /// Account for one event
+    pub fn add(&mut self, record: &Record) {
+        let first = *self.first.get_or_insert(record.time);
+        // timestamps wrap with `current`, events before the first one go to the first bucket
+        let offset = itimediff(record.time, first).max(0) as u32;
+        let index = (offset / self.bucket) as usize;
+        while self.buckets.len() <= index {
+            let previous = self.buckets.last();
+            let bucket = Bucket {
+                start: self.buckets.len() as u32 * self.bucket,
+                srtt: previous.and_then(|bucket| bucket.srtt),
+                rto: previous.and_then(|bucket| bucket.rto),
+                cwnd: previous.and_then(|bucket| bucket.cwnd),
+                ..Default::default()
+            };
+            self.buckets.push(bucket);
+        }
+        let bucket = &mut self.buckets[index];
+        self.events += 1;
+
+        match &record.event {
+            Event::SegmentSent(seg) => {
+                self.segments_sent += 1;
+                if seg.cmd == "push" {
+                    self.data_sent += 1;
+                    bucket.sent += 1;
+                    if seg.xmit > 1 {
+                        self.retransmits += 1;
+                        bucket.retransmits += 1;
+                    }
+                }
+            }
+            Event::SegmentReceived(seg) => {
+                self.segments_received += 1;
+                if seg.cmd == "push" {
+                    bucket.received += 1;
+                }
+            }
+            Event::RttUpdated {
+                sample, srtt, rto, ..
+            } => {
+                if bucket.rtt_samples == 0 {
+                    bucket.rtt_min = *sample;
+                    bucket.rtt_max = *sample;
+                }
+                bucket.rtt_samples += 1;
+                bucket.rtt_min = bucket.rtt_min.min(*sample);
+                bucket.rtt_max = bucket.rtt_max.max(*sample);
+                bucket.rtt_sum += *sample as i64;
+                bucket.srtt = Some(*srtt);
+                bucket.rto = Some(*rto);
+
+                self.rtt_samples += 1;
+                self.rtt_sum += *sample as i64;
+                self.rtt_min = Some(self.rtt_min.map_or(*sample, |min| min.min(*sample)));
+                self.rtt_max = Some(self.rtt_max.map_or(*sample, |max| max.max(*sample)));
+            }
+            Event::CwndChanged { to, .. } => {
+                bucket.cwnd = Some(*to);
+                self.cwnd_min = Some(self.cwnd_min.map_or(*to, |min| min.min(*to)));
+                self.cwnd_max = Some(self.cwnd_max.map_or(*to, |max| max.max(*to)));
+            }
+            Event::StateChanged { to, .. } => self.states.push((offset, *to)),
+        }
+    }
+
+    pub fn rtt_avg(&self) -> Option<i32> {
+        (self.rtt_samples > 0).then(|| (self.rtt_sum / self.rtt_samples as i64) as i32)
+    }
+
+    /// Share of data segments that were retransmissions
+    pub fn retransmit_ratio(&self) -> f64 {
+        if self.data_sent == 0 {
+            return 0.0;
+        }
+        self.retransmits as f64 / self.data_sent as f64
+    }
+}
+
+/// Column value, `-` when unknown
+struct Cell<T>(Option<T>);
+
+impl<T: fmt::Display> fmt::Display for Cell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(value) => value.fmt(f),
+            None => "-".fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for TraceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} events, segments sent {} received {}, data sent {} retransmitted {} ({:.1}%)",
+            self.events,
+            self.segments_sent,
+            self.segments_received,
+            self.data_sent,
+            self.retransmits,
+            self.retransmit_ratio() * 100.0
+        )?;
+        writeln!(
+            f,
+            "rtt min {} avg {} max {} ms over {} samples, cwnd min {} max {}",
+            Cell(self.rtt_min),
+            Cell(self.rtt_avg()),
+            Cell(self.rtt_max),
+            self.rtt_samples,
+            Cell(self.cwnd_min),
+            Cell(self.cwnd_max)
+        )?;
+        for (offset, state) in &self.states {
+            writeln!(f, "state {state} at {offset} ms")?;
+        }
+        writeln!(
+            f,
+            "{:>8} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6}",
+            "time", "min", "avg", "max", "srtt", "rto", "cwnd", "sent", "retx", "recv"
+        )?;
+        for bucket in &self.buckets {
+            let (min, max) = match bucket.rtt_samples {
+                0 => (None, None),
+                _ => (Some(bucket.rtt_min), Some(bucket.rtt_max)),
+            };
+            writeln!(
+                f,
+                "{:>8} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6}",
+                bucket.start,
+                Cell(min),
+                Cell(bucket.rtt_avg()),
+                Cell(max),
+                Cell(bucket.srtt),
+                Cell(bucket.rto),
+                Cell(bucket.cwnd),
+                bucket.sent,
+                bucket.retransmits,
+                bucket.received
+            )?;
+        }
+        Ok(())
+    }
+}
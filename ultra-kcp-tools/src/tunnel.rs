@@ -0,0 +1,611 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ultra_kcp_core::constants::{Command, KcpError};
+#[cfg(feature = "crypto")]
+use ultra_kcp_core::crypto::{CryptoRole, CryptoSession, CRYPTO_KEY_SIZE, CRYPTO_OVERHEAD};
+#[cfg(feature = "fec")]
+use ultra_kcp_core::fec::{FecDecoder, FecEncoder, FecOutput, FEC_HEADER_SIZE, FEC_SIZE_FIELD};
+use ultra_kcp_core::kcp::{KcpCallBack, KcpControl, Segment};
+use ultra_kcp_core::serial::itimediff;
+
+use crate::udp::KcpOptions;
+
+/// First byte of every KCP message of a tunnel
+const FRAME_OPEN: u8 = 0;
+const FRAME_DATA: u8 = 1;
+/// The sender will not write to its TCP connection anymore
+const FRAME_CLOSE: u8 = 2;
+
+/// Bytes read from a TCP connection at once
+const READ_SIZE: usize = 16 * 1024;
+/// Packets queued for a session before the server drops them
+const SESSION_QUEUE: usize = 1024;
+/// Interval at which the server forgets sessions that ended
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+const DATAGRAM_MAX: usize = 65535;
+
+/// Default time without datagrams from the peer before a session is closed (ms)
+pub const IDLE_TIMEOUT_DEF: u64 = 30_000;
+
+/// Settings shared by both ends of a tunnel, they must agree on all but the timeout
+#[derive(clap::Args, Debug, Clone, PartialEq, Eq)]
+pub struct TunnelOptions {
+    #[command(flatten)]
+    pub kcp: KcpOptions,
+    /// Close a session after this many ms without a datagram from the peer
+    #[arg(long, default_value_t = IDLE_TIMEOUT_DEF)]
+    pub idle_timeout: u64,
+    /// Shared ChaCha20-Poly1305 key, 64 hex digits
+    #[cfg(feature = "crypto")]
+    #[arg(long, value_parser = parse_key)]
+    pub key: Option<[u8; CRYPTO_KEY_SIZE]>,
+    /// Reed-Solomon shards per group, as `DATA,PARITY`
+    #[cfg(feature = "fec")]
+    #[arg(long, value_parser = parse_fec)]
+    pub fec: Option<(usize, usize)>,
+}
+
+impl Default for TunnelOptions {
+    fn default() -> Self {
+        Self {
+            kcp: KcpOptions::default(),
+            idle_timeout: IDLE_TIMEOUT_DEF,
+            #[cfg(feature = "crypto")]
+            key: None,
+            #[cfg(feature = "fec")]
+            fec: None,
+        }
+    }
+}
+
+#[cfg(feature = "crypto")]
+fn parse_key(key: &str) -> Result<[u8; CRYPTO_KEY_SIZE], String> {
+    let digits = key.as_bytes();
+    if digits.len() != CRYPTO_KEY_SIZE * 2 {
+        return Err(format!("expected {} hex digits", CRYPTO_KEY_SIZE * 2));
+    }
+    let mut bytes = [0; CRYPTO_KEY_SIZE];
+    for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+        *byte = std::str::from_utf8(pair)
+            .ok()
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or("invalid hex digit")?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(feature = "fec")]
+fn parse_fec(shards: &str) -> Result<(usize, usize), String> {
+    let (data, parity) = shards.split_once(',').ok_or("expected DATA,PARITY")?;
+    let parse = |count: &str| {
+        count
+            .trim()
+            .parse::<usize>()
+            .map_err(|error| error.to_string())
+    };
+    Ok((parse(data)?, parse(parity)?))
+}
+
+fn config_error(error: KcpError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{error:?}"))
+}
+
+/// Datagram path between KCP and the UDP socket, through FEC then encryption
+///
+/// Outgoing datagrams are FEC encoded by the `FecOutput` around `LinkOutput`.
+struct Link {
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    /// A packet could not be sealed, the session must end
+    broken: bool,
+    #[cfg(feature = "crypto")]
+    crypto: Option<CryptoSession>,
+    #[cfg(feature = "fec")]
+    fec: Option<FecDecoder>,
+}
+
+impl Link {
+    #[allow(unused_variables)]
+    fn new(
+        socket: Arc<UdpSocket>,
+        peer: SocketAddr,
+        options: &TunnelOptions,
+        initiator: bool,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            socket,
+            peer,
+            broken: false,
+            #[cfg(feature = "crypto")]
+            crypto: options.key.map(|key| {
+                let role = match initiator {
+                    true => CryptoRole::Initiator,
+                    false => CryptoRole::Responder,
+                };
+                CryptoSession::new(&key, role)
+            }),
+            #[cfg(feature = "fec")]
+            fec: options
+                .fec
+                .map(|(data, parity)| FecDecoder::new(data, parity))
+                .transpose()
+                .map_err(config_error)?,
+        })
+    }
+
+    /// Bytes the layers add to every KCP datagram
+    #[allow(unused_mut)]
+    fn overhead(&self) -> u32 {
+        let mut overhead = 0;
+        #[cfg(feature = "crypto")]
+        if self.crypto.is_some() {
+            overhead += CRYPTO_OVERHEAD;
+        }
+        #[cfg(feature = "fec")]
+        if self.fec.is_some() {
+            overhead += FEC_HEADER_SIZE + FEC_SIZE_FIELD;
+        }
+        overhead as u32
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        #[cfg(feature = "crypto")]
+        let sealed;
+        #[cfg(feature = "crypto")]
+        let packet = match &mut self.crypto {
+            Some(crypto) => match crypto.seal(packet) {
+                Ok(packet) => {
+                    sealed = packet;
+                    &sealed[..]
+                }
+                Err(_) => {
+                    // the key cannot be rotated, no later packet would go out either
+                    self.broken = true;
+                    return;
+                }
+            },
+            None => packet,
+        };
+        let _ = self.socket.send_to(packet, self.peer);
+    }
+
+    /// Hand the KCP datagrams carried by a packet to `emit`
+    fn receive(&mut self, packet: &[u8], mut emit: impl FnMut(&[u8])) {
+        #[cfg(feature = "crypto")]
+        let opened;
+        #[cfg(feature = "crypto")]
+        let packet = match &mut self.crypto {
+            Some(crypto) => match crypto.open(packet) {
+                Ok(datagram) => {
+                    opened = datagram;
+                    &opened[..]
+                }
+                Err(_) => return,
+            },
+            None => packet,
+        };
+        #[cfg(feature = "fec")]
+        if let Some(decoder) = &mut self.fec {
+            let _ = decoder.decode(packet, &mut emit);
+            return;
+        }
+        emit(packet);
+    }
+}
+
+struct LinkOutput(Arc<Mutex<Link>>);
+
+impl KcpCallBack for LinkOutput {
+    fn output(&self, buf: &[u8], _kcp: &mut KcpControl, _user: Option<&Box<dyn Any>>) {
+        self.0.lock().unwrap().send(buf);
+    }
+}
+
+/// KCP control block of a session, sending through `link`
+fn session_kcp(
+    conv: u32,
+    link: &Arc<Mutex<Link>>,
+    options: &TunnelOptions,
+) -> io::Result<KcpControl> {
+    let mut kcp = KcpControl::new_on_stack(conv, None);
+    options.kcp.configure(&mut kcp).map_err(config_error)?;
+    // packets on the wire stay within the configured MTU
+    let overhead = link.lock().unwrap().overhead();
+    kcp.set_mtu(options.kcp.mtu.saturating_sub(overhead))
+        .map_err(config_error)?;
+    // the tunnel frames messages itself
+    kcp.streaming_mode = false;
+    let output = LinkOutput(link.clone());
+    #[cfg(feature = "fec")]
+    if let Some((data, parity)) = options.fec {
+        let encoder = FecEncoder::new(data, parity).map_err(config_error)?;
+        kcp.set_callback(Box::new(FecOutput::new(encoder, output)));
+        return Ok(kcp);
+    }
+    kcp.set_callback(Box::new(output));
+    Ok(kcp)
+}
+
+/// Queue a tunnel frame
+fn send_frame(kcp: &mut KcpControl, kind: u8, data: &[u8]) {
+    let mut message = Vec::with_capacity(1 + data.len());
+    message.push(kind);
+    message.extend_from_slice(data);
+    let _ = kcp.send(&message);
+}
+
+/// Feed a packet from the wire to KCP, `true` when a datagram was accepted
+fn input(kcp: &mut KcpControl, link: &Mutex<Link>, packet: &[u8]) -> bool {
+    let mut accepted = false;
+    link.lock().unwrap().receive(packet, |datagram| {
+        accepted |= kcp.input(datagram).is_ok();
+    });
+    accepted
+}
+
+/// Pump one TCP connection through a KCP session until both sides closed
+///
+/// `tcp` is `None` when the server could not reach its target, the session
+/// then only tells the client to close.
+fn run_session(
+    mut kcp: KcpControl,
+    link: Arc<Mutex<Link>>,
+    tcp: Option<TcpStream>,
+    packets: Receiver<Vec<u8>>,
+    open: bool,
+    idle_timeout: Duration,
+) {
+    let start = Instant::now();
+    let now = || start.elapsed().as_millis() as u32;
+    let mut last_input = Instant::now();
+    let chunks = tcp
+        .as_ref()
+        .and_then(|tcp| tcp.try_clone().ok())
+        .map(read_tcp);
+    let mut tcp_open = chunks.is_some();
+    let mut peer_open = true;
+    // keep the send queue short so that TCP is read at the pace of the link
+    let queue_max = 2 * kcp.send_window.max(1) as usize;
+
+    if open {
+        send_frame(&mut kcp, FRAME_OPEN, &[]);
+    }
+    if !tcp_open {
+        send_frame(&mut kcp, FRAME_CLOSE, &[]);
+    }
+
+    let mut received = Vec::new();
+    loop {
+        let current = now();
+        kcp.update(current);
+        let wait = itimediff(kcp.check(current), current).clamp(1, 10) as u64;
+        match packets.recv_timeout(Duration::from_millis(wait)) {
+            Ok(packet) => {
+                let mut accepted = input(&mut kcp, &link, &packet);
+                while let Ok(packet) = packets.try_recv() {
+                    accepted |= input(&mut kcp, &link, &packet);
+                }
+                if accepted {
+                    last_input = Instant::now();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        kcp.update(now());
+
+        while tcp_open && kcp.wait_snd() < queue_max {
+            match chunks.as_ref().unwrap().try_recv() {
+                Ok(Some(data)) => send_frame(&mut kcp, FRAME_DATA, &data),
+                Ok(None) | Err(TryRecvError::Disconnected) => {
+                    tcp_open = false;
+                    send_frame(&mut kcp, FRAME_CLOSE, &[]);
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        while let Ok(size) = kcp.peek_size() {
+            received.resize(size, 0);
+            let Ok(len) = kcp.receive(Some(&mut received), false) else {
+                break;
+            };
+            match (received[..len].split_first(), &tcp) {
+                (Some((&FRAME_DATA, data)), Some(tcp)) if peer_open => {
+                    let written = (&*tcp).write_all(data);
+                    if written.is_err() {
+                        // the target is gone, stop reading from it as well
+                        peer_open = false;
+                        let _ = tcp.shutdown(Shutdown::Both);
+                    }
+                }
+                (Some((&FRAME_CLOSE, _)), tcp) => {
+                    peer_open = false;
+                    if let Some(tcp) = tcp {
+                        let _ = tcp.shutdown(Shutdown::Write);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !tcp_open && !peer_open && kcp.wait_snd() == 0 {
+            break;
+        }
+        if kcp.state == u32::MAX || last_input.elapsed() > idle_timeout {
+            break;
+        }
+        if link.lock().unwrap().broken {
+            break;
+        }
+    }
+    // acknowledge what the peer sent last before leaving
+    if !link.lock().unwrap().broken {
+        kcp.flush();
+    }
+    if let Some(tcp) = tcp {
+        let _ = tcp.shutdown(Shutdown::Both);
+    }
+}
+
+/// Read a TCP connection on a thread, `None` marks the end of the stream
+fn read_tcp(mut tcp: TcpStream) -> Receiver<Option<Vec<u8>>> {
+    let (sender, receiver) = mpsc::sync_channel(4);
+    thread::spawn(move || {
+        let mut buf = vec![0; READ_SIZE];
+        loop {
+            let data = match tcp.read(&mut buf) {
+                Ok(0) | Err(_) => None,
+                Ok(len) => Some(buf[..len].to_vec()),
+            };
+            let done = data.is_none();
+            if sender.send(data).is_err() || done {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Session of the server, fed with the packets of one source address
+struct ServerSession {
+    packets: SyncSender<Vec<u8>>,
+    /// Owned by the session thread, gone once it ended
+    link: Weak<Mutex<Link>>,
+}
+
+impl ServerSession {
+    fn is_finished(&self) -> bool {
+        self.link.strong_count() == 0
+    }
+}
+
+/// UDP end of a tunnel, connecting every session to the TCP target
+///
+/// Sessions are told apart by the source address of their packets, the
+/// client uses a UDP socket per TCP connection.
+pub struct Server {
+    socket: Arc<UdpSocket>,
+    target: SocketAddr,
+    options: TunnelOptions,
+}
+
+impl Server {
+    /// # Errors
+    /// Errors of `UdpSocket::bind`
+    pub fn bind(
+        listen: SocketAddr,
+        target: SocketAddr,
+        options: TunnelOptions,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            socket: Arc::new(UdpSocket::bind(listen)?),
+            target,
+            options,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Serve sessions forever
+    ///
+    /// # Errors
+    /// Socket errors other than ICMP reports of earlier sends
+    pub fn run(self) -> io::Result<()> {
+        let mut sessions: HashMap<SocketAddr, ServerSession> = HashMap::new();
+        let mut buf = vec![0; DATAGRAM_MAX];
+        // wake up now and then to forget finished sessions, even without traffic
+        self.socket.set_read_timeout(Some(REAP_INTERVAL))?;
+        let mut reaped = Instant::now();
+        loop {
+            if reaped.elapsed() >= REAP_INTERVAL {
+                sessions.retain(|_, session| !session.is_finished());
+                reaped = Instant::now();
+            }
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::ConnectionRefused
+                            | io::ErrorKind::ConnectionReset
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(error) => return Err(error),
+            };
+            let packet = buf[..len].to_vec();
+            if let Some(session) = sessions.get(&from) {
+                match session.packets.try_send(packet) {
+                    Ok(()) | Err(TrySendError::Full(_)) => continue,
+                    Err(TrySendError::Disconnected(packet)) => {
+                        sessions.remove(&from);
+                        if let Some(session) = self.accept(from, &packet) {
+                            sessions.insert(from, session);
+                        }
+                    }
+                }
+            } else if let Some(session) = self.accept(from, &packet) {
+                sessions.insert(from, session);
+            }
+        }
+    }
+
+    /// Start a session for the first packet of `from`
+    ///
+    /// Only the first segment of a session starts one, so that late
+    /// retransmissions of a finished session are dropped.
+    fn accept(&self, from: SocketAddr, packet: &[u8]) -> Option<ServerSession> {
+        let mut link = Link::new(self.socket.clone(), from, &self.options, false).ok()?;
+        let mut datagrams = Vec::new();
+        link.receive(packet, |datagram| datagrams.push(datagram.to_vec()));
+        let first = Segment::decode_header(datagrams.first()?).ok()?;
+        if first.cmd != u32::from(Command::Push) || first.sn != 0 {
+            return None;
+        }
+
+        let (sender, packets) = mpsc::sync_channel(SESSION_QUEUE);
+        let target = self.target;
+        let options = self.options.clone();
+        let link = Arc::new(Mutex::new(link));
+        let session = ServerSession {
+            packets: sender,
+            link: Arc::downgrade(&link),
+        };
+        thread::spawn(move || {
+            let Ok(mut kcp) = session_kcp(first.conv, &link, &options) else {
+                return;
+            };
+            for datagram in &datagrams {
+                let _ = kcp.input(datagram);
+            }
+            let tcp = TcpStream::connect(target).ok();
+            let idle_timeout = Duration::from_millis(options.idle_timeout);
+            run_session(kcp, link, tcp, packets, false, idle_timeout);
+        });
+        Some(session)
+    }
+}
+
+/// TCP end of a tunnel, opening a session to the server for every connection
+pub struct Client {
+    listener: TcpListener,
+    remote: SocketAddr,
+    options: TunnelOptions,
+}
+
+impl Client {
+    /// # Errors
+    /// Errors of `TcpListener::bind`
+    pub fn bind(
+        listen: SocketAddr,
+        remote: SocketAddr,
+        options: TunnelOptions,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(listen)?,
+            remote,
+            options,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept connections forever
+    ///
+    /// # Errors
+    /// - `InvalidInput`: Options are invalid
+    /// - Errors of `TcpListener::accept`
+    pub fn run(self) -> io::Result<()> {
+        // fail before the first connection rather than in every session
+        Link::new(
+            Arc::new(UdpSocket::bind(("127.0.0.1", 0))?),
+            self.remote,
+            &self.options,
+            true,
+        )?;
+        loop {
+            let (tcp, _) = self.listener.accept()?;
+            let remote = self.remote;
+            let options = self.options.clone();
+            thread::spawn(move || {
+                if let Ok((kcp, link, packets)) = Self::open(remote, &options) {
+                    let idle_timeout = Duration::from_millis(options.idle_timeout);
+                    run_session(kcp, link, Some(tcp), packets, true, idle_timeout);
+                }
+            });
+        }
+    }
+
+    /// Session to the server on a fresh UDP socket, with its receive thread
+    #[allow(clippy::type_complexity)]
+    fn open(
+        remote: SocketAddr,
+        options: &TunnelOptions,
+    ) -> io::Result<(KcpControl, Arc<Mutex<Link>>, Receiver<Vec<u8>>)> {
+        static SESSIONS: AtomicU32 = AtomicU32::new(0);
+        let local = match remote {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        let socket = Arc::new(UdpSocket::bind(local)?);
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let link = Arc::new(Mutex::new(Link::new(
+            socket.clone(),
+            remote,
+            options,
+            true,
+        )?));
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.subsec_nanos());
+        let conv = nanos ^ SESSIONS.fetch_add(1, Ordering::Relaxed).rotate_left(16);
+        let kcp = session_kcp(conv, &link, options)?;
+
+        let (sender, packets) = mpsc::sync_channel(SESSION_QUEUE);
+        // the session owns the link, the thread stops once it is gone
+        let session = Arc::downgrade(&link);
+        thread::spawn(move || {
+            let mut buf = vec![0; DATAGRAM_MAX];
+            while session.strong_count() > 0 {
+                match socket.recv_from(&mut buf) {
+                    Ok((len, from)) if from == remote => {
+                        if let Err(TrySendError::Disconnected(_)) =
+                            sender.try_send(buf[..len].to_vec())
+                        {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(error)
+                        if matches!(
+                            error.kind(),
+                            io::ErrorKind::WouldBlock
+                                | io::ErrorKind::TimedOut
+                                | io::ErrorKind::ConnectionRefused
+                                | io::ErrorKind::ConnectionReset
+                                | io::ErrorKind::Interrupted
+                        ) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+        Ok((kcp, link, packets))
+    }
+}
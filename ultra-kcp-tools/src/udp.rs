@@ -0,0 +1,288 @@
+use std::any::Any;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ultra_kcp_core::constants::{
+    KcpError, IKCP_INTERVAL, IKCP_MTU_DEF, IKCP_WND_RCV, IKCP_WND_SND,
+};
+use ultra_kcp_core::kcp::{KcpCallBack, KcpControl};
+use ultra_kcp_core::serial::itimediff;
+
+/// Largest UDP payload
+const DATAGRAM_MAX: usize = 65535;
+
+/// Tuning presets, the modes of kcptun
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Normal,
+    Fast,
+    Fast2,
+    Fast3,
+}
+
+impl Preset {
+    /// `set_nodelay` arguments of the preset
+    pub const fn nodelay(self) -> (u32, u32, i32, bool) {
+        match self {
+            Preset::Normal => (0, 40, 2, true),
+            Preset::Fast => (0, 30, 2, true),
+            Preset::Fast2 => (1, 20, 2, true),
+            Preset::Fast3 => (1, 10, 2, true),
+        }
+    }
+}
+
+/// KCP tuning knobs shared by the command line tools
+#[derive(clap::Args, Debug, Clone, PartialEq, Eq)]
+pub struct KcpOptions {
+    /// Conversation id, both sides must agree
+    #[arg(long, default_value_t = 1)]
+    pub conv: u32,
+    /// Maximum transmission unit (bytes)
+    #[arg(long, default_value_t = IKCP_MTU_DEF)]
+    pub mtu: u32,
+    /// Send window (segments)
+    #[arg(long, default_value_t = IKCP_WND_SND)]
+    pub sndwnd: u32,
+    /// Receive window (segments)
+    #[arg(long, default_value_t = IKCP_WND_RCV)]
+    pub rcvwnd: u32,
+    /// 0: disable, 1: enable, 2: enable with a less aggressive RTO backoff
+    #[arg(long, default_value_t = 0)]
+    pub nodelay: u32,
+    /// Internal update interval (ms)
+    #[arg(long, default_value_t = IKCP_INTERVAL)]
+    pub interval: u32,
+    /// Fast resend threshold, 0 disables fast resend
+    #[arg(long, default_value_t = 0)]
+    pub resend: i32,
+    /// Disable congestion control
+    #[arg(long)]
+    pub nocwnd: bool,
+    /// Streaming mode, message boundaries are not preserved
+    #[arg(long)]
+    pub stream: bool,
+    /// Preset overriding nodelay, interval, resend and nocwnd
+    #[arg(long, value_enum)]
+    pub preset: Option<Preset>,
+}
+
+impl Default for KcpOptions {
+    fn default() -> Self {
+        Self {
+            conv: 1,
+            mtu: IKCP_MTU_DEF,
+            sndwnd: IKCP_WND_SND,
+            rcvwnd: IKCP_WND_RCV,
+            nodelay: 0,
+            interval: IKCP_INTERVAL,
+            resend: 0,
+            nocwnd: false,
+            stream: false,
+            preset: None,
+        }
+    }
+}
+
+impl KcpOptions {
+    /// Create a control block configured with the options
+    ///
+    /// # Errors
+    /// - `InvalidConfig`: MTU is too small
+    pub fn create(&self) -> Result<KcpControl, KcpError> {
+        let mut kcp = KcpControl::new_on_stack(self.conv, None);
+        self.configure(&mut kcp)?;
+        Ok(kcp)
+    }
+
+    /// Apply every option but `conv` to an existing control block
+    ///
+    /// # Errors
+    /// - `InvalidConfig`: MTU is too small
+    pub fn configure(&self, kcp: &mut KcpControl) -> Result<(), KcpError> {
+        kcp.set_mtu(self.mtu)?;
+        kcp.set_wndsize(self.sndwnd, self.rcvwnd);
+        let (nodelay, interval, resend, nocwnd) = self.preset.map_or(
+            (self.nodelay, self.interval, self.resend, self.nocwnd),
+            Preset::nodelay,
+        );
+        kcp.set_nodelay(nodelay, interval, resend, nocwnd);
+        kcp.streaming_mode = self.stream;
+        Ok(())
+    }
+}
+
+/// Output callback sending datagrams to the peer, once it is known
+///
+/// # Note
+/// Send errors are ignored, KCP retransmits what the network lost.
+struct UdpOutput {
+    socket: Arc<UdpSocket>,
+    peer: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl KcpCallBack for UdpOutput {
+    fn output(&self, buf: &[u8], _kcp: &mut KcpControl, _user: Option<&Box<dyn Any>>) {
+        if let Some(peer) = *self.peer.lock().unwrap() {
+            let _ = self.socket.send_to(buf, peer);
+        }
+    }
+}
+
+/// KCP session over a UDP socket, driven by `poll`
+///
+/// A listening session adopts the source of the first datagram accepted by
+/// `KcpControl::input` as its peer, and ignores every other source afterwards.
+pub struct UdpSession {
+    socket: Arc<UdpSocket>,
+    peer: Arc<Mutex<Option<SocketAddr>>>,
+    kcp: KcpControl,
+    start: Instant,
+    last_input: Instant,
+    datagram: Vec<u8>,
+    message: Vec<u8>,
+}
+
+impl UdpSession {
+    fn new(socket: UdpSocket, peer: Option<SocketAddr>, options: &KcpOptions) -> io::Result<Self> {
+        let socket = Arc::new(socket);
+        let peer = Arc::new(Mutex::new(peer));
+        let mut kcp = options
+            .create()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, format!("{error:?}")))?;
+        kcp.set_callback(Box::new(UdpOutput {
+            socket: socket.clone(),
+            peer: peer.clone(),
+        }));
+        let now = Instant::now();
+        Ok(Self {
+            socket,
+            peer,
+            kcp,
+            start: now,
+            last_input: now,
+            datagram: vec![0; DATAGRAM_MAX],
+            message: Vec::new(),
+        })
+    }
+
+    /// Connect to `remote` from an ephemeral local port
+    ///
+    /// # Errors
+    /// - `InvalidInput`: Options are invalid
+    /// - Errors of `UdpSocket::bind`
+    pub fn connect(remote: SocketAddr, options: &KcpOptions) -> io::Result<Self> {
+        let local = match remote {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        Self::new(UdpSocket::bind(local)?, Some(remote), options)
+    }
+
+    /// Wait on `local` for a peer
+    ///
+    /// # Errors
+    /// - `InvalidInput`: Options are invalid
+    /// - Errors of `UdpSocket::bind`
+    pub fn listen(local: SocketAddr, options: &KcpOptions) -> io::Result<Self> {
+        Self::new(UdpSocket::bind(local)?, None, options)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn peer(&self) -> Option<SocketAddr> {
+        *self.peer.lock().unwrap()
+    }
+
+    pub fn kcp(&self) -> &KcpControl {
+        &self.kcp
+    }
+
+    pub fn kcp_mut(&mut self) -> &mut KcpControl {
+        &mut self.kcp
+    }
+
+    /// Milliseconds since the session was created, the KCP clock
+    pub fn now(&self) -> u32 {
+        self.start.elapsed().as_millis() as u32
+    }
+
+    /// Time since the last datagram from the peer, or since creation
+    pub fn idle(&self) -> Duration {
+        self.last_input.elapsed()
+    }
+
+    /// Whether the link was declared dead after too many retransmissions
+    pub fn is_dead(&self) -> bool {
+        self.kcp.state == u32::MAX
+    }
+
+    /// Run timers, then wait up to `timeout` for datagrams and feed them to KCP
+    ///
+    /// The wait ends earlier when KCP has to flush.
+    ///
+    /// # Errors
+    /// Socket errors other than timeouts
+    pub fn poll(&mut self, timeout: Duration) -> io::Result<()> {
+        let now = self.now();
+        self.kcp.update(now);
+        let flush = Duration::from_millis(itimediff(self.kcp.check(now), now).max(1) as u64);
+        self.socket
+            .set_read_timeout(Some(timeout.min(flush).max(Duration::from_millis(1))))?;
+        if self.read()? {
+            // drain what else arrived without blocking again
+            self.socket.set_nonblocking(true)?;
+            let drained = loop {
+                match self.read() {
+                    Ok(true) => {}
+                    done => break done,
+                }
+            };
+            self.socket.set_nonblocking(false)?;
+            drained?;
+        }
+        self.kcp.update(self.now());
+        Ok(())
+    }
+
+    /// Read one datagram, `false` when none arrived in time
+    fn read(&mut self) -> io::Result<bool> {
+        let (len, from) = match self.socket.recv_from(&mut self.datagram) {
+            Ok(received) => received,
+            Err(error) => {
+                return match error.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Ok(false),
+                    // ICMP error of an earlier send, the peer may not be up yet
+                    io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => Ok(true),
+                    _ => Err(error),
+                };
+            }
+        };
+        let peer = self.peer();
+        if peer.is_none_or(|peer| peer == from) && self.kcp.input(&self.datagram[..len]).is_ok() {
+            *self.peer.lock().unwrap() = Some(from);
+            self.last_input = Instant::now();
+        }
+        Ok(true)
+    }
+
+    /// Queue a message, see `KcpControl::send`
+    ///
+    /// # Errors
+    /// Errors of `KcpControl::send`
+    pub fn send(&mut self, data: &[u8]) -> Result<usize, KcpError> {
+        self.kcp.send(data)
+    }
+
+    /// Next received message, or the next received bytes in streaming mode
+    pub fn receive(&mut self) -> Option<&[u8]> {
+        let size = self.kcp.peek_size().ok()?;
+        self.message.resize(size, 0);
+        let len = self.kcp.receive(Some(&mut self.message), false).ok()?;
+        Some(&self.message[..len])
+    }
+}
@@ -0,0 +1,110 @@
+use std::io;
+use std::process::Command;
+
+use ultra_kcp_tools::bench::{self, BenchConfig, Impairment, SOCKBUF_DEF};
+use ultra_kcp_tools::udp::{KcpOptions, Preset};
+
+fn config(size: usize, messages: usize) -> BenchConfig {
+    BenchConfig {
+        profile: "fast3".into(),
+        kcp: KcpOptions {
+            preset: Some(Preset::Fast3),
+            sndwnd: 128,
+            rcvwnd: 128,
+            ..Default::default()
+        },
+        size,
+        messages,
+        impairment: Impairment::default(),
+        timeout: 30_000,
+        sockbuf: SOCKBUF_DEF,
+    }
+}
+
+#[test]
+fn clean_link_delivers_everything() {
+    let result = bench::run(&config(1024, 2000)).unwrap();
+    assert!(result.is_complete(), "{result:?}");
+    assert_eq!(result.profile, "fast3");
+    assert_eq!(result.dropped, 0);
+    assert_eq!(result.retransmits, 0);
+    assert!(result.data_sent >= 2000);
+    assert!(result.goodput > 0.0);
+    assert!(result.latency_p50_ms <= result.latency_p99_ms);
+    assert!(result.latency_p99_ms <= result.latency_max_ms);
+    assert!(result.latency_max_ms <= result.duration_ms);
+    assert!(result.sender_cpu_ms > 0.0 && result.receiver_cpu_ms > 0.0);
+}
+
+#[test]
+fn impaired_link_is_measured() {
+    let mut config = config(512, 500);
+    config.impairment.loss = 5.0;
+    config.impairment.delay = 10;
+    let result = bench::run(&config).unwrap();
+    assert!(result.is_complete(), "{result:?}");
+    assert!(result.dropped > 0);
+    assert!(result.retransmits > 0);
+    assert!(result.retransmit_ratio > 0.0 && result.retransmit_ratio < 1.0);
+    // one-way delay plus at least one update interval
+    assert!(result.latency_p50_ms >= 10.0);
+}
+
+#[test]
+fn stream_mode_cuts_messages_by_size() {
+    let mut config = config(100, 1000);
+    config.kcp.stream = true;
+    let result = bench::run(&config).unwrap();
+    assert!(result.is_complete(), "{result:?}");
+    // small messages share segments
+    assert!(result.data_sent < 1000);
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    let error = bench::run(&config(8, 10)).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    let mut small_mtu = config(64, 10);
+    small_mtu.kcp.mtu = 10;
+    let error = bench::run(&small_mtu).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn cli_sweeps_profiles_and_sizes() {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcp-bench"))
+        .args(["--profiles", "fast2,fast3", "--sizes", "64,2048"])
+        .args(["--messages", "200", "--format", "csv"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[0].starts_with("profile,size,"));
+    let columns = lines[0].split(',').count();
+    for (line, prefix) in
+        lines[1..]
+            .iter()
+            .zip(["fast2,64,", "fast2,2048,", "fast3,64,", "fast3,2048,"])
+    {
+        assert!(line.starts_with(prefix), "{line}");
+        assert_eq!(line.split(',').count(), columns);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kcp-bench"))
+        .args(["--windows", "64", "--sizes", "256", "--messages", "100"])
+        .args(["--preset", "fast3", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["profile"], "fast3");
+    assert_eq!(result["sndwnd"], 64);
+    assert_eq!(result["delivered"], 100);
+}
@@ -0,0 +1,101 @@
+use std::io::Write;
+use std::net::UdpSocket;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+/// Loopback address of a port that was free a moment ago
+fn free_address() -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.local_addr().unwrap().to_string()
+}
+
+/// Send `input` from a connecting kcp-cat to a listening one, return both outputs
+fn transfer(input: &[u8], flags: &[&str]) -> (Output, Output) {
+    let address = free_address();
+    let tuning = ["--nodelay", "1", "--interval", "10", "--resend", "2"];
+    let listener = Command::new(env!("CARGO_BIN_EXE_kcp-cat"))
+        .args(["--listen", &address, "--idle-timeout", "1000"])
+        .args(tuning)
+        .args(flags)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut sender = Command::new(env!("CARGO_BIN_EXE_kcp-cat"))
+        .args([&address, "--quit"])
+        .args(tuning)
+        .args(flags)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // both pipes are served while the processes run, a full pipe would stall the transfer
+    let received = thread::spawn(move || listener.wait_with_output().unwrap());
+    let mut stdin = sender.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input).unwrap());
+
+    let sent = sender.wait_with_output().unwrap();
+    writer.join().unwrap();
+    (sent, received.join().unwrap())
+}
+
+#[test]
+fn stream_mode_carries_bytes() {
+    let input: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let (sent, received) = transfer(&input, &["--stream", "--sndwnd", "128"]);
+    assert!(
+        sent.status.success(),
+        "{}",
+        String::from_utf8_lossy(&sent.stderr)
+    );
+    assert!(
+        received.status.success(),
+        "{}",
+        String::from_utf8_lossy(&received.stderr)
+    );
+    assert_eq!(received.stdout.len(), input.len());
+    assert!(received.stdout == input);
+}
+
+#[test]
+fn message_mode_carries_lines() {
+    let input: String = (0..500).map(|i| format!("line {i}\n")).collect();
+    let (sent, received) = transfer(input.as_bytes(), &[]);
+    assert!(
+        sent.status.success(),
+        "{}",
+        String::from_utf8_lossy(&sent.stderr)
+    );
+    assert!(received.status.success());
+    assert_eq!(String::from_utf8(received.stdout).unwrap(), input);
+}
+
+#[test]
+fn message_mode_splits_lines_longer_than_the_window() {
+    let long: String = (0..20_000)
+        .map(|i| char::from(b'a' + (i % 26) as u8))
+        .collect();
+    let input = format!("short\n{long}\nlast\n");
+    let (sent, received) = transfer(input.as_bytes(), &["--rcvwnd", "8"]);
+    assert!(
+        sent.status.success(),
+        "{}",
+        String::from_utf8_lossy(&sent.stderr)
+    );
+    assert!(received.status.success());
+    assert_eq!(String::from_utf8(received.stdout).unwrap(), input);
+}
+
+#[test]
+fn invalid_options_are_reported() {
+    let output = Command::new(env!("CARGO_BIN_EXE_kcp-cat"))
+        .args([&free_address(), "--mtu", "10"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("InvalidConfig"));
+}
@@ -0,0 +1,261 @@
+use std::io::ErrorKind;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use ultra_kcp_core::capture::{CaptureConfig, CaptureOutput, PcapCapture};
+use ultra_kcp_core::checksum::crc32c;
+use ultra_kcp_core::constants::{self, KcpError};
+use ultra_kcp_core::kcp::{KcpControl, Segment};
+use ultra_kcp_sim::sim::{Outbox, OutboxCallback};
+use ultra_kcp_tools::dump::{decode_datagram, parse_hex, read_packets, Dump};
+
+/// pcap of a transfer losing one datagram out of four on the way to the receiver
+///
+/// The capture runs on the sender when `at_sender`, on the receiver otherwise.
+fn lossy_capture(at_sender: bool) -> Vec<u8> {
+    let capture = Arc::new(Mutex::new(
+        PcapCapture::new(Vec::new(), CaptureConfig::default()).unwrap(),
+    ));
+    let sender_out = Outbox::default();
+    let receiver_out = Outbox::default();
+    let mut sender = KcpControl::new_on_stack(9, None);
+    let mut receiver = KcpControl::new_on_stack(9, None);
+    let (captured, plain) = match at_sender {
+        true => ((&mut sender, &sender_out), (&mut receiver, &receiver_out)),
+        false => ((&mut receiver, &receiver_out), (&mut sender, &sender_out)),
+    };
+    captured.0.set_callback(Box::new(CaptureOutput::new(
+        capture.clone(),
+        OutboxCallback(captured.1.clone()),
+    )));
+    plain
+        .0
+        .set_callback(Box::new(OutboxCallback(plain.1.clone())));
+    for kcp in [&mut sender, &mut receiver] {
+        kcp.set_nodelay(1, 10, 2, false);
+    }
+
+    for _ in 0..100 {
+        sender.send(&[1; 800]).unwrap();
+    }
+    let mut sent = 0;
+    for current in (0..3000).step_by(10) {
+        sender.update(current);
+        receiver.update(current);
+        for packet in sender_out.lock().unwrap().drain(..).collect::<Vec<_>>() {
+            sent += 1;
+            if sent % 4 == 0 {
+                continue;
+            }
+            match at_sender {
+                true => receiver.input(&packet).unwrap(),
+                false => capture
+                    .lock()
+                    .unwrap()
+                    .input(&mut receiver, &packet)
+                    .unwrap(),
+            }
+        }
+        for packet in receiver_out.lock().unwrap().drain(..).collect::<Vec<_>>() {
+            match at_sender {
+                true => capture.lock().unwrap().input(&mut sender, &packet).unwrap(),
+                false => sender.input(&packet).unwrap(),
+            }
+        }
+    }
+    drop((sender, receiver));
+    Arc::try_unwrap(capture)
+        .ok()
+        .unwrap()
+        .into_inner()
+        .unwrap()
+        .into_inner()
+}
+
+fn dump(data: &[u8]) -> Dump {
+    let mut dump = Dump::new(false, None);
+    for packet in read_packets(data).unwrap() {
+        assert!(dump.add(&packet).is_some());
+    }
+    dump
+}
+
+#[test]
+fn sender_capture_counts_retransmits_and_rtt() {
+    let config = CaptureConfig::default();
+    let dump = dump(&lossy_capture(true));
+    println!("{dump}");
+    assert_eq!(dump.skipped, 0);
+    assert_eq!(dump.conversations.len(), 1);
+    let flows = &dump.conversations[&9].flows;
+    let data = &flows[&Some((config.local, config.remote))];
+    let acks = &flows[&Some((config.remote, config.local))];
+
+    assert!(data.commands[0] > 100);
+    assert_eq!(data.data_bytes, data.commands[0] * 800);
+    assert!(data.retransmits > 0);
+    assert_eq!(data.commands[0] - data.retransmits, 100);
+    // every transmission leaves the sender, no sn is skipped
+    assert_eq!(data.gaps, 0);
+    assert!(acks.commands[1] > 0);
+    assert_eq!(acks.commands[0], 0);
+
+    assert!(data.rtt.samples > 0);
+    assert!(data.rtt.min <= data.rtt.avg().unwrap() && data.rtt.avg().unwrap() <= data.rtt.max);
+    assert!(data.rtt.max <= 1_000_000);
+}
+
+#[test]
+fn receiver_capture_sees_gaps() {
+    let config = CaptureConfig::default();
+    let dump = dump(&lossy_capture(false));
+    let data = &dump.conversations[&9].flows[&Some((config.remote, config.local))];
+    assert!(data.gaps > 0);
+    assert!(data.missing >= data.gaps);
+    // lost transmissions never reach the receiver, every sn arrives once
+    assert_eq!(data.commands[0] - data.retransmits, 100);
+}
+
+fn datagram(segments: &[(constants::Command, u32, &[u8])]) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    for (cmd, sn, payload) in segments {
+        let seg = Segment {
+            conv: 0x1234,
+            cmd: u32::from(*cmd),
+            wnd: 128,
+            ts: 500,
+            sn: *sn,
+            una: 2,
+            len: payload.len() as u32,
+            ..Default::default()
+        };
+        let mut header = [0; constants::IKCP_OVERHEAD as usize];
+        seg.encode_header(&mut header);
+        datagram.extend_from_slice(&header);
+        datagram.extend_from_slice(payload);
+    }
+    datagram
+}
+
+#[test]
+fn hex_dumps_decode_segment_headers() {
+    let sack_ranges = [4u32, 6, 8, 9]
+        .iter()
+        .flat_map(|n| n.to_le_bytes())
+        .collect::<Vec<_>>();
+    let first = datagram(&[
+        (constants::Command::Push, 2, b"hello"),
+        (constants::Command::Sack, 3, &sack_ranges),
+    ]);
+    let second = datagram(&[(constants::Command::Push, 5, b"")]);
+    let spaced = first
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let plain: String = second.iter().map(|byte| format!("{byte:02X}")).collect();
+    let text = format!("# captured by hand\n{spaced}\n\n{plain}\n");
+
+    let packets = parse_hex(&text).unwrap();
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0].payload, first);
+    assert_eq!(packets[1].number, 2);
+
+    let mut dump = Dump::new(false, None);
+    let segments = dump.add(&packets[0]).unwrap();
+    assert_eq!(
+        segments[0].to_string(),
+        "conv=4660 cmd=push frg=0 wnd=128 ts=500 sn=2 una=2 len=5"
+    );
+    assert_eq!(segments[1].ranges, [(4, 6), (8, 9)]);
+    assert!(segments[1]
+        .to_string()
+        .ends_with("len=16 ranges=[4,6),[8,9)"));
+    assert_eq!(dump.label(&packets[0]), "#1");
+    dump.add(&packets[1]).unwrap();
+    let flow = &dump.conversations[&0x1234].flows[&None];
+    assert_eq!((flow.gaps, flow.missing), (1, 2));
+    assert_eq!(flow.commands, [2, 0, 0, 0, 1]);
+
+    let error = parse_hex("51 52\nzz\n").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("line 2:"), "{error}");
+}
+
+#[test]
+fn malformed_datagrams_are_rejected() {
+    let mut sealed = datagram(&[(constants::Command::Ack, 1, b"")]);
+    assert!(decode_datagram(&sealed, true).is_err());
+    sealed.extend_from_slice(&crc32c(&sealed).to_le_bytes());
+    assert_eq!(decode_datagram(&sealed, true).unwrap().len(), 1);
+    sealed[0] ^= 1;
+    assert_eq!(
+        decode_datagram(&sealed, true),
+        Err(KcpError::ChecksumMismatch)
+    );
+
+    let mut truncated = datagram(&[(constants::Command::Push, 1, b"abc")]);
+    truncated.pop();
+    assert_eq!(
+        decode_datagram(&truncated, false),
+        Err(KcpError::InvalidPacket)
+    );
+    let mut unknown = datagram(&[(constants::Command::Push, 1, b"")]);
+    unknown[4] = 99;
+    assert_eq!(
+        decode_datagram(&unknown, false),
+        Err(KcpError::InvalidPacket)
+    );
+    assert_eq!(decode_datagram(b"", false), Err(KcpError::InvalidPacket));
+}
+
+#[test]
+fn cli_prints_segments_and_summaries() {
+    let path = std::env::temp_dir().join(format!("kcp-dump-{}.pcap", std::process::id()));
+    std::fs::write(&path, lossy_capture(true)).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_kcp-dump"))
+        .arg(&path)
+        .args(["--port", "4000"])
+        .output()
+        .unwrap();
+    let quiet = Command::new(env!("CARGO_BIN_EXE_kcp-dump"))
+        .arg(&path)
+        .args(["--quiet", "--conv", "9"])
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let first = stdout.lines().next().unwrap();
+    assert!(
+        first.starts_with("0.000000 10.0.0.1:4000 > 10.0.0.2:4000 conv=9 cmd=push"),
+        "{first}"
+    );
+    assert!(stdout.contains("cmd=ack"));
+    assert!(stdout.contains("\nconv 9\n"));
+    assert!(stdout.contains("retransmits "));
+
+    assert!(quiet.status.success());
+    let summary = String::from_utf8(quiet.stdout).unwrap();
+    assert!(!summary.starts_with("conv="));
+    assert!(summary.lines().count() <= 5, "{summary}");
+
+    let pcapng = Command::new(env!("CARGO_BIN_EXE_kcp-dump"))
+        .arg("-")
+        .stdin(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            use std::io::Write;
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(&[0x0a, 0x0d, 0x0d, 0x0a, 0, 0, 0, 0])?;
+            child.wait_with_output()
+        })
+        .unwrap();
+    assert!(!pcapng.status.success());
+    assert!(String::from_utf8_lossy(&pcapng.stderr).contains("pcapng"));
+}
@@ -0,0 +1,164 @@
+use std::io::{ErrorKind, Write};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use ultra_kcp_core::constants::KcpLogFlags;
+use ultra_kcp_core::kcp::KcpControl;
+use ultra_kcp_core::trace::TraceOutput;
+use ultra_kcp_sim::sim::{Outbox, OutboxCallback};
+use ultra_kcp_tools::trace::{Event, Record, TraceSummary};
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Trace of a 3 second transfer losing one datagram out of four, starting at `start`
+fn lossy_trace(start: u32) -> Vec<u8> {
+    let trace = Shared::default();
+    let sender_out = Outbox::default();
+    let receiver_out = Outbox::default();
+    let mut sender = KcpControl::new_on_stack(9, None);
+    sender.set_callback(Box::new(TraceOutput::new(
+        OutboxCallback(sender_out.clone()),
+        trace.clone(),
+    )));
+    sender.set_tracing(true);
+    sender.set_log_mask(KcpLogFlags::all());
+    let mut receiver = KcpControl::new_on_stack(9, None);
+    receiver.set_callback(Box::new(OutboxCallback(receiver_out.clone())));
+    for kcp in [&mut sender, &mut receiver] {
+        kcp.set_nodelay(1, 10, 2, false);
+    }
+
+    for _ in 0..100 {
+        sender.send(&[1; 800]).unwrap();
+    }
+    let mut sent = 0;
+    for step in 0..300u32 {
+        let current = start.wrapping_add(step * 10);
+        sender.update(current);
+        receiver.update(current);
+        for packet in sender_out.lock().unwrap().drain(..) {
+            sent += 1;
+            if sent % 4 != 0 {
+                receiver.input(&packet).unwrap();
+            }
+        }
+        for packet in receiver_out.lock().unwrap().drain(..) {
+            sender.input(&packet).unwrap();
+        }
+    }
+    let trace = trace.0.lock().unwrap().clone();
+    trace
+}
+
+#[test]
+fn summary_covers_the_transfer() {
+    let trace = lossy_trace(0);
+    let summary = TraceSummary::analyze(&trace[..], 500).unwrap();
+    println!("{summary}");
+
+    assert_eq!(
+        summary.events,
+        trace
+            .split(|&b| b == b'\n')
+            .filter(|l| !l.is_empty())
+            .count() as u64
+    );
+    assert!(summary.data_sent > 100);
+    assert!(summary.retransmits > 0);
+    assert_eq!(
+        summary.data_sent,
+        summary.buckets.iter().map(|b| b.sent as u64).sum::<u64>()
+    );
+    assert_eq!(
+        summary.retransmits,
+        summary
+            .buckets
+            .iter()
+            .map(|b| b.retransmits as u64)
+            .sum::<u64>()
+    );
+    assert!(summary.rtt_samples > 0);
+    let (min, avg, max) = (
+        summary.rtt_min.unwrap(),
+        summary.rtt_avg().unwrap(),
+        summary.rtt_max.unwrap(),
+    );
+    assert!(min <= avg && avg <= max);
+    assert!(summary.cwnd_max.unwrap() > summary.cwnd_min.unwrap());
+    assert!(summary.buckets.len() <= 6);
+    assert!(summary.buckets.iter().all(|b| b.start % 500 == 0));
+}
+
+#[test]
+fn wrapping_timestamps_give_the_same_summary() {
+    let baseline = TraceSummary::analyze(&lossy_trace(0)[..], 500).unwrap();
+    let wrapped = TraceSummary::analyze(&lossy_trace(u32::MAX - 1000)[..], 500).unwrap();
+    assert_eq!(wrapped.buckets, baseline.buckets);
+    assert_eq!(wrapped.retransmits, baseline.retransmits);
+}
+
+#[test]
+fn records_parse_every_event() {
+    let record = Record::parse(
+        r#"{"time":7,"name":"cwnd_changed","data":{"from":2,"to":3,"reason":"ack"}}"#,
+    )
+    .unwrap();
+    assert_eq!(record.time, 7);
+    assert_eq!(
+        record.event,
+        Event::CwndChanged {
+            from: 2,
+            to: 3,
+            reason: "ack".to_string()
+        }
+    );
+
+    let error = TraceSummary::analyze(
+        &b"\n{\"time\":1,\"name\":\"unknown\",\"data\":{}}\n"[..],
+        100,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("line 2:"), "{error}");
+}
+
+#[test]
+fn cli_prints_the_timeline() {
+    let path = std::env::temp_dir().join(format!("kcp-trace-{}.jsonl", std::process::id()));
+    std::fs::write(&path, lossy_trace(0)).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_kcp-trace"))
+        .arg(&path)
+        .args(["--bucket", "1000"])
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("retransmitted"));
+    assert_eq!(
+        stdout
+            .lines()
+            .filter(|line| line.trim_start().starts_with("1000 "))
+            .count(),
+        1
+    );
+
+    let missing = Command::new(env!("CARGO_BIN_EXE_kcp-trace"))
+        .arg("/nonexistent/trace.jsonl")
+        .output()
+        .unwrap();
+    assert!(!missing.status.success());
+}
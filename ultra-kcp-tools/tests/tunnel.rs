@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::process::{Child, Command};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ultra_kcp_tools::tunnel::{Client, Server, TunnelOptions};
+use ultra_kcp_tools::udp::Preset;
+
+/// TCP server echoing every connection until its peer closes
+fn echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || {
+                let mut reader = stream.try_clone().unwrap();
+                let _ = std::io::copy(&mut reader, &mut stream);
+                let _ = stream.shutdown(Shutdown::Write);
+            });
+        }
+    });
+    address
+}
+
+/// UDP relay between clients and `server`, dropping one packet out of `every` each way
+fn lossy_relay(server: SocketAddr, every: u64) -> SocketAddr {
+    let front = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+    let address = front.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut backs: HashMap<SocketAddr, Arc<UdpSocket>> = HashMap::new();
+        let mut buf = vec![0; 65535];
+        let mut count = 0;
+        loop {
+            let (len, client) = front.recv_from(&mut buf).unwrap();
+            let back = backs.entry(client).or_insert_with(|| {
+                let back = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+                let (back_thread, front) = (back.clone(), front.clone());
+                thread::spawn(move || {
+                    let mut buf = vec![0; 65535];
+                    let mut count = 0;
+                    while let Ok(len) = back_thread.recv(&mut buf) {
+                        count += 1;
+                        if count % every != 0 {
+                            let _ = front.send_to(&buf[..len], client);
+                        }
+                    }
+                });
+                back
+            });
+            count += 1;
+            if count % every != 0 {
+                let _ = back.send_to(&buf[..len], server);
+            }
+        }
+    });
+    address
+}
+
+fn options() -> TunnelOptions {
+    let mut options = TunnelOptions::default();
+    options.kcp.preset = Some(Preset::Fast3);
+    options.kcp.sndwnd = 256;
+    options.kcp.rcvwnd = 256;
+    options
+}
+
+/// Start a server in front of `target` and a client, return the client address
+fn tunnel(target: SocketAddr, options: TunnelOptions, loss: Option<u64>) -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0".parse().unwrap(), target, options.clone()).unwrap();
+    let mut remote = server.local_addr().unwrap();
+    thread::spawn(move || server.run().unwrap());
+    if let Some(every) = loss {
+        remote = lossy_relay(remote, every);
+    }
+    let client = Client::bind("127.0.0.1:0".parse().unwrap(), remote, options).unwrap();
+    let address = client.local_addr().unwrap();
+    thread::spawn(move || client.run().unwrap());
+    address
+}
+
+/// Send `size` bytes through an echo tunnel and check they come back
+fn echo(address: SocketAddr, size: usize, seed: u8) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+    let data: Vec<u8> = (0..size)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect();
+    let mut writer = stream.try_clone().unwrap();
+    let sent = data.clone();
+    let writer = thread::spawn(move || {
+        writer.write_all(&sent).unwrap();
+        writer.shutdown(Shutdown::Write).unwrap();
+    });
+    let mut echoed = Vec::new();
+    stream.read_to_end(&mut echoed).unwrap();
+    writer.join().unwrap();
+    assert_eq!(echoed.len(), data.len());
+    assert!(echoed == data, "echo differs");
+}
+
+#[test]
+fn connections_are_echoed_end_to_end() {
+    let address = tunnel(echo_server(), options(), None);
+    let connections: Vec<_> = (0..4)
+        .map(|seed| thread::spawn(move || echo(address, 300_000, seed)))
+        .collect();
+    for connection in connections {
+        connection.join().unwrap();
+    }
+    // sessions are independent of each other, a new one works after the first ones closed
+    echo(address, 10, 9);
+}
+
+#[test]
+fn lost_packets_are_recovered() {
+    let address = tunnel(echo_server(), options(), Some(10));
+    echo(address, 200_000, 1);
+}
+
+#[test]
+fn unreachable_target_closes_the_connection() {
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let target = closed.local_addr().unwrap();
+    drop(closed);
+    let address = tunnel(target, options(), None);
+
+    let start = Instant::now();
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+    assert!(data.is_empty());
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[cfg(all(feature = "crypto", feature = "fec"))]
+#[test]
+fn encrypted_tunnel_with_fec() {
+    let mut options = options();
+    options.key = Some([7; 32]);
+    options.fec = Some((10, 3));
+    let address = tunnel(echo_server(), options.clone(), Some(10));
+    echo(address, 200_000, 2);
+
+    // a server with another key never answers
+    options.idle_timeout = 1000;
+    let mut wrong = options.clone();
+    wrong.key = Some([8; 32]);
+    let server = Server::bind("127.0.0.1:0".parse().unwrap(), echo_server(), wrong).unwrap();
+    let remote = server.local_addr().unwrap();
+    thread::spawn(move || server.run().unwrap());
+    let client = Client::bind("127.0.0.1:0".parse().unwrap(), remote, options).unwrap();
+    let address = client.local_addr().unwrap();
+    thread::spawn(move || client.run().unwrap());
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"hello").unwrap();
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+    assert!(data.is_empty());
+}
+
+/// Kill a child process when the test ends, even on failure
+struct Guard(Child);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn cli_forwards_connections() {
+    let udp = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let tcp = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let target = echo_server();
+    let spawn = |args: &[String]| {
+        Guard(
+            Command::new(env!("CARGO_BIN_EXE_kcp-tunnel"))
+                .args(args)
+                .args(["--preset", "fast3"])
+                .spawn()
+                .unwrap(),
+        )
+    };
+    let _server = spawn(&[
+        "server".into(),
+        "--listen".into(),
+        udp.to_string(),
+        "--target".into(),
+        target.to_string(),
+    ]);
+    let _client = spawn(&[
+        "client".into(),
+        "--listen".into(),
+        tcp.to_string(),
+        "--remote".into(),
+        udp.to_string(),
+    ]);
+
+    let start = Instant::now();
+    while TcpStream::connect(tcp).is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "client not listening"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    echo(tcp, 50_000, 3);
+}